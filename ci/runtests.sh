@@ -6,13 +6,15 @@ set -e
 export CARGO_TARGET_DIR=target/ci
 
 rustup target add thumbv7em-none-eabihf
-rustup component add rustfmt
+rustup component add rustfmt clippy
 
 export RUSTDOCFLAGS='-D warnings'
 export RUSTFLAGS="-D warnings"
 
 cargo fmt -- --check
 
+cargo clippy --all-targets --features mctp-estack/log
+
 # stable, std
 cargo build --release --features mctp-estack/log
 cargo test --features mctp-estack/log
@@ -31,6 +33,12 @@ done
 cd mctp-estack
 cargo build --target thumbv7em-none-eabihf --features defmt --no-default-features
 cargo build --features log
+for f in checked-invariants blocking test-util std; do
+    cargo clippy --all-targets --features log,$f
+    cargo test --features log,$f
+done
+cargo clippy --all-targets --features log,checked-invariants,blocking,test-util,std
+cargo test --features log,checked-invariants,blocking,test-util,std
 )
 
 cargo doc --features mctp-estack/log
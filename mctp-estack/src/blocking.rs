@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Blocking request channel
+//!
+//! A synchronous wrapper of [`RouterAsyncReqChannel`] for simple
+//! applications that don't use an async executor.
+//!
+//! Each call runs the async operation to completion with
+//! [`embassy_futures::block_on()`], which busy-polls the current thread.
+//! This is intended for simple single-task use. Other `Router` work
+//! (`inbound()` for incoming packets, port transmission, and
+//! `update_time()`) must make progress independently of the blocked
+//! task, otherwise a call will block forever.
+
+#[allow(unused)]
+use crate::fmt::{debug, error, info, trace, warn};
+
+use crate::router::RouterAsyncReqChannel;
+use crate::{ReassemblyPool, Router, StaticPool};
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use mctp::{AsyncReqChannel, Eid, Error, MsgType, Result, Tag};
+
+/// A blocking request channel.
+///
+/// Implements [`mctp::ReqChannel`] by blocking on a
+/// [`RouterAsyncReqChannel`]. See the [module documentation](self)
+/// for restrictions.
+pub struct BlockingReqChannel<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    chan: RouterAsyncReqChannel<'r, M, R, LISTENERS, RECEIVERS>,
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > BlockingReqChannel<'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Create a new `BlockingReqChannel` to `eid`.
+    pub fn new(
+        router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+        eid: Eid,
+    ) -> Self {
+        Self {
+            chan: router.req(eid),
+            router,
+        }
+    }
+
+    /// Send a request and wait for the response.
+    ///
+    /// The response payload is returned in `buf`. A response with
+    /// a different message type fails with `Error::InvalidInput`.
+    ///
+    /// With `deadline_millis` set, `Error::TimedOut` is returned once the
+    /// time passed to [`Router::update_time()`] reaches `deadline_millis`.
+    ///
+    /// Each call uses a new tag.
+    pub fn call<'f>(
+        &mut self,
+        typ: MsgType,
+        req: &[u8],
+        buf: &'f mut [u8],
+        deadline_millis: Option<u64>,
+    ) -> Result<&'f mut [u8]> {
+        // A fresh channel, since each tag is only used once
+        let eid = self.chan.remote_eid();
+        self.chan = self.router.req(eid);
+
+        block_on(async {
+            self.chan.send(typ, req).await?;
+            let (msg, rtyp, _tag, _ic) = match deadline_millis {
+                Some(d) => self.chan.recv_deadline(buf, d).await?,
+                None => self.chan.recv(buf).await?,
+            };
+            if rtyp != typ {
+                debug!("Response type {} mismatch", rtyp.0);
+                return Err(Error::InvalidInput);
+            }
+            Ok(msg)
+        })
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > mctp::ReqChannel for BlockingReqChannel<'_, M, R, LISTENERS, RECEIVERS>
+{
+    fn send_vectored(
+        &mut self,
+        typ: MsgType,
+        integrity_check: bool,
+        bufs: &[&[u8]],
+    ) -> Result<()> {
+        block_on(self.chan.send_vectored(typ, integrity_check, bufs))
+    }
+
+    fn recv<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+    ) -> Result<(&'f mut [u8], MsgType, Tag, bool)> {
+        block_on(self.chan.recv(buf))
+    }
+
+    fn remote_eid(&self) -> Eid {
+        self.chan.remote_eid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::{PortBuilder, PortId, PortLookup, PortStorage};
+    use crate::test_util::PacketBuilder;
+    use crate::Stack;
+    use mctp::ReqChannel;
+
+    struct DefaultRoute;
+
+    impl PortLookup for DefaultRoute {
+        fn by_eid(
+            &mut self,
+            _eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            Some(PortId(0))
+        }
+    }
+
+    #[test]
+    fn req_channel() {
+        let mut storage = PortStorage::<8>::new();
+        let mut pb = PortBuilder::new(&mut storage);
+        let (top, mut bottom) = pb.build(255).unwrap();
+        let ports = [top];
+        let mut lookup = DefaultRoute;
+        let stack = Stack::new(Eid(10), 255, 0);
+        let router: Router = Router::new(stack, &ports, &mut lookup);
+        let typ = MsgType(0x10);
+
+        let mut req = BlockingReqChannel::new(&router, Eid(20));
+        req.send(typ, &[4]).unwrap();
+        let (pkt, _dest) = bottom.try_outbound().unwrap();
+        let hdr = crate::peek_header(pkt).unwrap();
+        bottom.outbound_done();
+
+        // Response from the peer
+        let resp = PacketBuilder::new(
+            Eid(20),
+            Eid(10),
+            Tag::Unowned(hdr.tag.tag()),
+            typ,
+        )
+        .build(&[5])
+        .unwrap();
+        block_on(router.inbound(&resp, PortId(0)));
+
+        let mut buf = [0u8; 10];
+        let (msg, rtyp, _tag, _ic) = req.recv(&mut buf).unwrap();
+        assert_eq!(msg, &[5]);
+        assert_eq!(rtyp, typ);
+
+        // Times out without a response
+        block_on(router.update_time(100)).unwrap();
+        let r = req.call(typ, &[4], &mut buf, Some(50));
+        assert!(matches!(r, Err(Error::TimedOut)));
+        assert!(bottom.try_outbound().is_some());
+    }
+}
@@ -8,7 +8,8 @@
 //! MCTP Control Protocol implementation
 
 use crate::fmt::*;
-use crate::Router;
+use crate::{ReassemblyPool, Router, StaticPool};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
 use libmctp::control_packet::CompletionCode;
 use mctp::{AsyncRespChannel, Eid, Error, Listener, MsgType};
 use uuid::Uuid;
@@ -240,15 +241,28 @@ where
 }
 
 /// A Control Message handler.
-pub struct MctpControl<'a> {
+pub struct MctpControl<
+    'a,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
     rsp_buf: [u8; MAX_MSG_SIZE],
     types: heapless::Vec<MsgType, MAX_MSG_TYPES>,
     uuid: Option<Uuid>,
-    router: &'a Router<'a>,
+    router: &'a Router<'a, M, R, LISTENERS, RECEIVERS>,
 }
 
-impl<'a> MctpControl<'a> {
-    pub fn new(router: &'a Router<'a>) -> Self {
+impl<
+        'a,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > MctpControl<'a, M, R, LISTENERS, RECEIVERS>
+{
+    pub fn new(router: &'a Router<'a, M, R, LISTENERS, RECEIVERS>) -> Self {
         Self {
             rsp_buf: [0u8; MAX_MSG_SIZE],
             types: heapless::Vec::new(),
@@ -295,7 +309,7 @@ impl<'a> MctpControl<'a> {
     async fn handle_req(
         &mut self,
         req: &'_ MctpControlMsg<'_>,
-    ) -> ControlResult<MctpControlMsg> {
+    ) -> ControlResult<MctpControlMsg<'_>> {
         let cc = req.command_code().map_err(|cc| {
             debug!("Unsupported control command {}", cc);
             CompletionCode::ErrorUnsupportedCmd
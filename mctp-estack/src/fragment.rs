@@ -28,6 +28,8 @@ pub struct Fragmenter {
 
     // A count of how many bytes have already been sent.
     payload_used: usize,
+    // Payload length from the most recent fragment call
+    total_len: usize,
 }
 
 impl Fragmenter {
@@ -58,6 +60,7 @@ impl Fragmenter {
 
         Ok(Self {
             payload_used: 0,
+            total_len: 0,
             src,
             dest,
             typ,
@@ -79,6 +82,15 @@ impl Fragmenter {
         self.dest
     }
 
+    /// Sets the source EID, used for packets not yet fragmented.
+    pub(crate) fn set_source(&mut self, src: Eid) {
+        self.src = src;
+    }
+
+    pub fn typ(&self) -> MsgType {
+        self.typ
+    }
+
     pub fn cookie(&self) -> Option<AppCookie> {
         self.cookie
     }
@@ -104,6 +116,36 @@ impl Fragmenter {
         payload: &[u8],
         out: &'f mut [u8],
     ) -> SendOutput<'f> {
+        let copy = |offset: usize, d: &mut [u8]| {
+            d.copy_from_slice(&payload[offset..offset + d.len()]);
+            d.len()
+        };
+        self.fragment_with(payload.len(), copy, out)
+    }
+
+    /// Returns fragments for a MCTP payload provided by a closure.
+    ///
+    /// This avoids holding the full payload in a buffer.
+    /// `total_len` is the length of the payload, and must be the same for
+    /// each call.
+    ///
+    /// `producer(offset, buf)` is called to fill the fragment's payload
+    /// region `buf`, starting from `offset` within the payload. It returns
+    /// the number of bytes written.
+    /// If fewer than `buf.len()` bytes are written the fragmenter fails with
+    /// `Error::InvalidInput`. Prior fragments will already have been
+    /// returned, so the remote endpoint will see an incomplete message.
+    ///
+    /// Output is the same as [`fragment()`](Self::fragment).
+    pub fn fragment_with<'f, F>(
+        &mut self,
+        total_len: usize,
+        producer: F,
+        out: &'f mut [u8],
+    ) -> SendOutput<'f>
+    where
+        F: FnOnce(usize, &mut [u8]) -> usize,
+    {
         if self.done {
             return SendOutput::success(self);
         }
@@ -127,21 +169,24 @@ impl Fragmenter {
             rest = &mut rest[1..];
         }
 
-        if payload.len() < self.payload_used {
+        if total_len < self.payload_used {
             // Caller is passing varying payload buffers
             return SendOutput::failure(Error::InvalidInput, self);
         }
+        self.total_len = total_len;
 
-        // Copy as much as is available in input or output
-        let p = &payload[self.payload_used..];
-        let l = p.len().min(rest.len());
+        // Fill as much as is available in input or output
+        let l = (total_len - self.payload_used).min(rest.len());
         let (d, rest) = rest.split_at_mut(l);
+        if producer(self.payload_used, d) != l {
+            debug!("Short payload producer");
+            return SendOutput::failure(Error::InvalidInput, self);
+        }
         self.payload_used += l;
-        d.copy_from_slice(&p[..l]);
 
         // Add the header
         let mut header = self.header();
-        if self.payload_used == payload.len() {
+        if self.payload_used == total_len {
             header.set_eom(1);
             self.done = true;
         }
@@ -157,6 +202,14 @@ impl Fragmenter {
     pub fn is_done(&self) -> bool {
         self.done
     }
+
+    /// Returns the number of payload bytes remaining to be fragmented.
+    ///
+    /// The payload length is only known once a fragment has been
+    /// produced, prior to that this returns 0.
+    pub fn remaining(&self) -> usize {
+        self.total_len - self.payload_used
+    }
 }
 
 pub enum SendOutput<'p> {
@@ -38,14 +38,21 @@ use heapless::FnvIndexMap;
 
 use mctp::{Eid, Error, MsgType, Result, Tag, TagValue};
 
+#[cfg(any(test, feature = "blocking"))]
+pub mod blocking;
 pub mod control;
 mod fragment;
 pub mod i2c;
+#[cfg(any(test, feature = "std"))]
+pub mod pcap;
 mod reassemble;
 pub mod router;
 pub mod serial;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod usb;
 mod util;
+pub mod vendor;
 
 pub use fragment::{Fragmenter, SendOutput};
 use reassemble::Reassembler;
@@ -116,12 +123,34 @@ pub mod config {
     pub const MAX_MTU: usize = get_build_var!("MCTP_ESTACK_MAX_MTU", 255);
     const _: () =
         assert!(MAX_MTU >= crate::HEADER_LEN + 1, "MAX_MTU too small");
+
+    /// Number of packets enqueued between yields when sending, default 8
+    ///
+    /// A [`Router`](crate::Router) sending a large message will yield
+    /// to other tasks after this many fragments have been enqueued
+    /// to a port, so that a long send doesn't starve other tasks on the
+    /// same executor. Set to 0 to never yield.
+    ///
+    /// Customise with `MCTP_ESTACK_SEND_YIELD_INTERVAL` environment variable.
+    pub const SEND_YIELD_INTERVAL: usize =
+        get_build_var!("MCTP_ESTACK_SEND_YIELD_INTERVAL", 8);
+
+    /// Number of recently forwarded packets tracked to detect
+    /// forwarding loops, default 8
+    ///
+    /// See [`Router::set_max_forwards()`](crate::Router::set_max_forwards).
+    ///
+    /// Customise with `MCTP_ESTACK_FORWARD_LOOP_WINDOW` environment variable.
+    pub const FORWARD_LOOP_WINDOW: usize =
+        get_build_var!("MCTP_ESTACK_FORWARD_LOOP_WINDOW", 8);
 }
 
 #[derive(Debug)]
 struct Flow {
     // preallocated flows have None expiry
     expiry_stamp: Option<EventStamp>,
+    // milliseconds after expiry_stamp
+    lifetime: u32,
     cookie: Option<AppCookie>,
 }
 
@@ -131,6 +160,51 @@ pub struct AppCookie(pub usize);
 
 type Header = libmctp::base_packet::MCTPTransportHeader<[u8; HEADER_LEN]>;
 
+/// Fields of a MCTP packet header.
+///
+/// Returned by [`peek_header()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub source: Eid,
+    pub dest: Eid,
+    pub tag: Tag,
+    /// Start of message
+    pub som: bool,
+    /// End of message
+    pub eom: bool,
+    /// Packet sequence number
+    pub seq: u8,
+}
+
+/// Parses the MCTP header of a packet.
+///
+/// Returns [`Error::InvalidInput`] for a short packet or a
+/// bad header version.
+pub fn peek_header(packet: &[u8]) -> Result<PacketHeader> {
+    let header = Reassembler::header(packet)?;
+    let tv = TagValue(header.msg_tag());
+    Ok(PacketHeader {
+        source: Eid(header.source_endpoint_id()),
+        dest: Eid(header.dest_endpoint_id()),
+        tag: if header.to() == 1 {
+            Tag::Owned(tv)
+        } else {
+            Tag::Unowned(tv)
+        },
+        som: header.som() == 1,
+        eom: header.eom() == 1,
+        seq: header.pkt_seq(),
+    })
+}
+
+/// Returns true for the first packet of an MCTP Control message.
+pub(crate) fn is_control_start(packet: &[u8]) -> bool {
+    peek_header(packet).is_ok_and(|h| h.som)
+        && packet
+            .get(HEADER_LEN)
+            .is_some_and(|t| t & 0x7f == mctp::MCTP_TYPE_CONTROL.0)
+}
+
 /// A handle to a received message.
 ///
 /// Must be returned to the stack with [`finished_receive`](Stack::finished_receive)
@@ -142,21 +216,87 @@ type Header = libmctp::base_packet::MCTPTransportHeader<[u8; HEADER_LEN]>;
 #[derive(Debug)]
 pub struct ReceiveHandle(usize);
 
+/// How owned tags are allocated by a [`Stack`].
+///
+/// Set with [`Stack::set_tag_alloc()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TagAllocStrategy {
+    /// Rotate through tag values, so consecutive requests to a peer
+    /// use different tags even when earlier tags have been released.
+    #[default]
+    RoundRobin,
+    /// Use the lowest free tag value.
+    LowestFree,
+}
+
+/// Storage for message reassembly buffers.
+///
+/// A [`Stack`] reassembles up to [`config::NUM_RECEIVE`]
+/// messages at a time, each into a separate buffer from the pool.
+/// [`StaticPool`] is the default, holding the buffers within the `Stack`.
+/// Other implementations can provide buffers from elsewhere, such as
+/// a particular memory region. Use with [`Stack::new_with_pool()`].
+pub trait ReassemblyPool {
+    /// Returns the buffer for reassembly slot `index`.
+    ///
+    /// `index` is less than `NUM_RECEIVE`. The same buffer must be
+    /// returned for an index each time. Messages longer than the buffer
+    /// are dropped.
+    fn buffer(&mut self, index: usize) -> &mut [u8];
+
+    /// Returns the size of buffers held outside the pool itself.
+    ///
+    /// This is used by
+    /// [`Router::memory_footprint()`](crate::Router::memory_footprint).
+    /// Buffers held inline, as for [`StaticPool`], are already counted
+    /// in the size of the `Stack`. The default returns 0, so a pool
+    /// that borrows its buffers should override this.
+    fn footprint(&self) -> usize {
+        0
+    }
+}
+
+/// The default [`ReassemblyPool`], with buffers of `MAX_PAYLOAD` bytes
+/// held in the `Stack`.
+pub struct StaticPool([[u8; MAX_PAYLOAD]; NUM_RECEIVE]);
+
+impl Default for StaticPool {
+    fn default() -> Self {
+        Self([[0; MAX_PAYLOAD]; NUM_RECEIVE])
+    }
+}
+
+impl core::fmt::Debug for StaticPool {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StaticPool").finish_non_exhaustive()
+    }
+}
+
+impl ReassemblyPool for StaticPool {
+    fn buffer(&mut self, index: usize) -> &mut [u8] {
+        &mut self.0[index]
+    }
+}
+
 #[derive(Debug)]
-pub struct Stack {
+pub struct Stack<R = StaticPool> {
     own_eid: Eid,
 
     // flows where we own the tag
     flows: FnvIndexMap<(Eid, TagValue), Flow, FLOWS>,
 
-    // The buffer is kept outside of the Reassembler, in case it is borrowed
-    // from other storage locations in future.
+    // Each reassembler has the used length of the same index
+    // buffer in `pool`.
     // This is [Option<>] rather than Vec so that indices remain stable
     // for the ReceiveHandle. Could use a Map instead?
-    reassemblers: [Option<(Reassembler, Vec<u8, MAX_PAYLOAD>)>; NUM_RECEIVE],
+    reassemblers: [Option<(Reassembler, usize)>; NUM_RECEIVE],
+    pool: R,
 
     /// monotonic time and counter.
     now: EventStamp,
+    /// Added to `update()` times, so that `now` doesn't go backwards
+    /// if the caller's clock does.
+    clock_offset: u64,
     /// cached next expiry time from update()
     next_timeout: u64,
 
@@ -164,9 +304,23 @@ pub struct Stack {
 
     // Arbitrary counter to make tag allocation more variable.
     next_tag: u8,
+    tag_alloc: TagAllocStrategy,
 
     // Arbitrary next sequence number to start a fragmenter
     next_seq: u8,
+
+    // Owned tags released since the last take_released()
+    released: Vec<(Eid, TagValue), FLOWS>,
+    track_released: bool,
+
+    // Keep a reassembler free for control messages
+    control_reserve: bool,
+
+    // Limit of owned tags for all peers
+    max_owned_tags: Option<usize>,
+
+    // Limit of packets in a received message
+    max_fragments: Option<usize>,
 }
 
 impl Stack {
@@ -181,6 +335,20 @@ impl Stack {
     /// calls may use a smaller MTU if needed (for example a per-link or per-EID MTU).
     /// `new()` will panic if a MTU smaller than 5 is given (minimum MCTP header and type byte).
     pub fn new(own_eid: Eid, mtu: usize, now_millis: u64) -> Self {
+        Self::new_with_pool(StaticPool::default(), own_eid, mtu, now_millis)
+    }
+}
+
+impl<R: ReassemblyPool> Stack<R> {
+    /// Create a new `Stack` with a custom reassembly buffer pool.
+    ///
+    /// Other arguments are as for [`Stack::new()`].
+    pub fn new_with_pool(
+        pool: R,
+        own_eid: Eid,
+        mtu: usize,
+        now_millis: u64,
+    ) -> Self {
         let now = EventStamp {
             clock: now_millis,
             counter: 0,
@@ -189,12 +357,20 @@ impl Stack {
         Self {
             own_eid,
             now,
+            clock_offset: 0,
             next_timeout: 0,
             mtu,
             flows: Default::default(),
             reassemblers: Default::default(),
+            pool,
             next_tag: 0,
+            tag_alloc: TagAllocStrategy::default(),
             next_seq: 0,
+            released: Vec::new(),
+            track_released: false,
+            control_reserve: false,
+            max_owned_tags: None,
+            max_fragments: None,
         }
     }
 
@@ -202,26 +378,30 @@ impl Stack {
     ///
     /// This is used for expiring flows and reassembly.
     ///
-    /// Returns [`Error::InvalidInput`] if time goes backwards.
-    fn update_clock(&mut self, now_millis: u64) -> Result<()> {
-        if now_millis < self.now.clock {
-            Err(Error::InvalidInput)
+    /// If time goes backwards (for example a clock step or rollover),
+    /// the internal clock is re-baselined so that no time elapses.
+    ///
+    /// Returns the internal clock value.
+    fn update_clock(&mut self, now_millis: u64) -> u64 {
+        let now = now_millis.saturating_add(self.clock_offset);
+        if now < self.now.clock {
+            debug!("Clock went backwards by {}", self.now.clock - now);
+            self.clock_offset += self.now.clock - now;
+        } else if now > self.now.clock {
+            self.now.clock = now;
+            self.now.counter = 0;
         } else {
-            if now_millis > self.now.clock {
-                self.now.clock = now_millis;
-                self.now.counter = 0;
-            } else {
-                // update_clock was called with the same millisecond as previously.
-                // Don't do anything.
-            }
-            Ok(())
+            // update_clock was called with the same millisecond as previously.
+            // Don't do anything.
         }
+        self.now.clock
     }
 
     /// Updates timeouts and returns the next timeout in milliseconds
     ///
     /// Must be called regularly to update the current clock value.
-    /// Returns [`Error::InvalidInput`] if time goes backwards.
+    /// `now_millis` should be monotonic. If it goes backwards the interval
+    /// is treated as zero, and timeouts continue from the new value.
     ///
     /// Returns `(next_timeout, any_expired)`.
     /// `next_timeout` is a suitable interval (milliseconds) for the next
@@ -229,7 +409,7 @@ impl Stack {
     ///
     /// `any_expired` is set true if any message receive timeouts expired with this call.
     pub fn update(&mut self, now_millis: u64) -> Result<(u64, bool)> {
-        self.update_clock(now_millis)?;
+        let now_millis = self.update_clock(now_millis);
 
         if let Some(remain) = self.next_timeout.checked_sub(now_millis) {
             if remain > 0 {
@@ -243,7 +423,7 @@ impl Stack {
 
         // Check reassembler expiry for incomplete packets
         for r in self.reassemblers.iter_mut() {
-            if let Some((re, _buf)) = r {
+            if let Some((re, _len)) = r {
                 match re.check_expired(
                     &self.now,
                     REASSEMBLY_EXPIRY_TIMEOUT,
@@ -261,17 +441,20 @@ impl Stack {
         }
 
         // Expire reply-packet flows
-        self.flows.retain(|_k, flow| {
+        self.flows.retain(|k, flow| {
             match flow.expiry_stamp {
                 // no expiry
                 None => true,
                 Some(stamp) => {
-                    match stamp
-                        .check_timeout(&self.now, REASSEMBLY_EXPIRY_TIMEOUT)
-                    {
+                    match stamp.check_timeout(&self.now, flow.lifetime) {
                         // expired, remove it
                         None => {
                             any_expired = true;
+                            if self.track_released
+                                && self.released.push(*k).is_err()
+                            {
+                                debug!("Released tag list full");
+                            }
                             false
                         }
                         Some(t) => {
@@ -289,6 +472,18 @@ impl Stack {
         Ok((timeout as u64, any_expired))
     }
 
+    /// Returns the interval until timeouts next need checking.
+    ///
+    /// This is the same interval (milliseconds) as most recently
+    /// returned by [`update()`](Self::update), reduced by any clock
+    /// advance since then. It does not advance the clock or check
+    /// timeouts. Returns 0 if `update()` is due now.
+    pub fn suggested_interval(&self) -> u64 {
+        self.next_timeout
+            .saturating_sub(self.now.clock)
+            .min(TIMEOUT_INTERVAL as u64)
+    }
+
     /// Initiates a MCTP message send.
     ///
     /// Returns a [`Fragmenter`] that will packetize the message.
@@ -309,22 +504,73 @@ impl Stack {
         ic: bool,
         mtu: Option<usize>,
         cookie: Option<AppCookie>,
+    ) -> Result<Fragmenter> {
+        let tag_lifetime = tag_expires.then_some(REASSEMBLY_EXPIRY_TIMEOUT);
+        self.start_send_lifetime(dest, typ, tag, tag_lifetime, ic, mtu, cookie)
+    }
+
+    /// Initiates a MCTP message send, with a specific owned tag lifetime.
+    ///
+    /// An owned tag allocated for the send expires `tag_lifetime`
+    /// milliseconds after the send if no response is received, or never
+    /// expires with `None`. [`start_send()`](Self::start_send) uses
+    /// a default lifetime of 6 seconds.
+    ///
+    /// Other arguments are as for `start_send()`.
+    pub fn start_send_lifetime(
+        &mut self,
+        dest: Eid,
+        typ: MsgType,
+        tag: Option<Tag>,
+        tag_lifetime: Option<u32>,
+        ic: bool,
+        mtu: Option<usize>,
+        cookie: Option<AppCookie>,
     ) -> Result<Fragmenter> {
         // Add an entry to the flow table for owned tags
         let tag = match tag {
             None => {
                 // allocate a tag
-                Tag::Owned(self.set_flow(dest, None, tag_expires, cookie)?)
+                Tag::Owned(self.set_flow(dest, None, tag_lifetime, cookie)?)
             }
             Some(Tag::Owned(tv)) => {
                 let check =
-                    self.set_flow(dest, Some(tv), tag_expires, cookie)?;
+                    self.set_flow(dest, Some(tv), tag_lifetime, cookie)?;
                 debug_assert!(check == tv);
                 Tag::Owned(tv)
             }
             Some(Tag::Unowned(tv)) => Tag::Unowned(tv),
         };
 
+        self.fragmenter(dest, typ, tag, ic, mtu, cookie)
+    }
+
+    /// Initiates a MCTP message send with an exact tag.
+    ///
+    /// Unlike [`start_send()`](Self::start_send), an owned `tag` isn't
+    /// allocated or recorded as a flow, so no response will be
+    /// accepted for it. This is intended for testing peers with
+    /// unusual tag and TO bit combinations.
+    pub fn start_send_raw(
+        &mut self,
+        dest: Eid,
+        typ: MsgType,
+        tag: Tag,
+        ic: bool,
+        mtu: Option<usize>,
+    ) -> Result<Fragmenter> {
+        self.fragmenter(dest, typ, tag, ic, mtu, None)
+    }
+
+    fn fragmenter(
+        &mut self,
+        dest: Eid,
+        typ: MsgType,
+        tag: Tag,
+        ic: bool,
+        mtu: Option<usize>,
+        cookie: Option<AppCookie>,
+    ) -> Result<Fragmenter> {
         let mut frag_mtu = self.mtu;
         if let Some(m) = mtu {
             frag_mtu = frag_mtu.min(m);
@@ -355,35 +601,75 @@ impl Stack {
     pub fn receive(
         &mut self,
         packet: &[u8],
-    ) -> Result<Option<(MctpMessage, ReceiveHandle)>> {
+    ) -> Result<Option<(MctpMessage<'_>, ReceiveHandle)>> {
+        self.receive_inner(packet, self.own_eid, None)
+    }
+
+    /// Receive a packet, accepting unowned messages without a flow.
+    ///
+    /// This behaves as [`receive`](Self::receive), but a new message
+    /// with the TO bit clear that doesn't match a flow from
+    /// [`start_send`](Self::start_send) is accepted rather than
+    /// rejected, and will have `cookie` set.
+    ///
+    /// A message matching an existing flow is handled as a response
+    /// to that flow, as with `receive()`.
+    pub fn receive_unowned(
+        &mut self,
+        packet: &[u8],
+        cookie: AppCookie,
+    ) -> Result<Option<(MctpMessage<'_>, ReceiveHandle)>> {
+        self.receive_inner(packet, self.own_eid, Some(cookie))
+    }
+
+    /// Receive a packet.
+    ///
+    /// `local_eid` is the EID accepted as a destination for new messages,
+    /// usually `own_eid`. `unowned_cookie` is as for `receive_unowned()`.
+    fn receive_inner(
+        &mut self,
+        packet: &[u8],
+        local_eid: Eid,
+        unowned_cookie: Option<AppCookie>,
+    ) -> Result<Option<(MctpMessage<'_>, ReceiveHandle)>> {
         // Get or insert a reassembler for this packet
         let idx = self.get_reassembler(packet)?;
-        let (re, buf) = if let Some(r) = &mut self.reassemblers[idx] {
+        let (re, len) = if let Some(r) = &mut self.reassemblers[idx] {
             r
         } else {
             // Create a new one
             let mut re =
-                Reassembler::new(self.own_eid, packet, self.now.increment())?;
+                Reassembler::new(local_eid, packet, self.now.increment())?;
 
             if !re.tag.is_owner() {
                 // Only allow it if we had an existing flow
                 if let Some(f) = self.lookup_flow(re.peer, re.tag.tag()) {
                     re.set_cookie(f.cookie);
+                } else if unowned_cookie.is_some() {
+                    re.set_cookie(unowned_cookie);
                 } else {
                     return Err(Error::Unreachable);
                 }
             }
-            self.reassemblers[idx].insert((re, Vec::new()))
+            self.reassemblers[idx].insert((re, 0))
         };
 
         // Feed the packet to the reassembler
-        match re.receive(packet, buf, self.now.increment()) {
+        let buf = self.pool.buffer(idx);
+        let stamp = self.now.increment();
+        match re.receive(packet, buf, len, stamp, self.max_fragments) {
             // Received a complete message
             Ok(Some(_msg)) => {
                 // Have received a "response", flow is finished.
-                // TODO preallocated tags won't remove the flow.
-                if !re.tag.is_owner() {
-                    let (peer, tv) = (re.peer, re.tag.tag());
+                // Preallocated tags (tag_expires=false) keep the flow,
+                // allowing multiple responses until cancel_flow().
+                let (peer, tv) = (re.peer, re.tag.tag());
+                // Messages from receive_unowned() may not have a flow.
+                let expires = self
+                    .flows
+                    .get(&(peer, tv))
+                    .is_some_and(|f| f.expiry_stamp.is_some());
+                if !re.tag.is_owner() && expires {
                     self.remove_flow(peer, tv);
                 }
 
@@ -391,8 +677,8 @@ impl Stack {
                 // we hit lifetime problems setting `= None` in the Err case.
                 // These two lines can be removed once Rust "polonius" borrow
                 // checker is added.
-                let (re, buf) = self.reassemblers[idx].as_mut().unwrap();
-                let msg = re.message(buf)?;
+                let (re, len) = self.reassemblers[idx].as_mut().unwrap();
+                let msg = re.message(&self.pool.buffer(idx)[..*len])?;
 
                 let handle = re.take_handle(idx);
                 Ok(Some((msg, handle)))
@@ -424,14 +710,14 @@ impl Stack {
     }
 
     /// Provides a message previously returned from [`receive`](Self::receive)
-    pub fn fetch_message(&mut self, handle: &ReceiveHandle) -> MctpMessage {
-        let Some(Some((re, buf))) = self.reassemblers.get_mut(handle.0) else {
+    pub fn fetch_message(&mut self, handle: &ReceiveHandle) -> MctpMessage<'_> {
+        let Some(Some((re, len))) = self.reassemblers.get(handle.0) else {
             // ReceiveHandle can only be constructed when
             // a completed message exists, so this should be impossible.
             unreachable!("Bad ReceiveHandle");
         };
 
-        let Ok(msg) = re.message(buf) else {
+        let Ok(msg) = re.message(&self.pool.buffer(handle.0)[..*len]) else {
             unreachable!("Bad ReceiveHandle");
         };
         msg
@@ -440,7 +726,7 @@ impl Stack {
     /// Returns a handle to the `Stack` and complete the message
     pub fn finished_receive(&mut self, handle: ReceiveHandle) {
         if let Some(r) = self.reassemblers.get_mut(handle.0) {
-            if let Some((re, _buf)) = r {
+            if let Some((re, _len)) = r {
                 re.return_handle(handle);
                 *r = None;
                 return;
@@ -452,7 +738,7 @@ impl Stack {
     /// Returns a handle to the `Stack`, the message will be kept (until timeouts)
     pub fn return_handle(&mut self, handle: ReceiveHandle) {
         // OK unwrap: handle can't be invalid
-        let (re, _buf) = self.reassemblers[handle.0].as_mut().unwrap();
+        let (re, _len) = self.reassemblers[handle.0].as_mut().unwrap();
         re.return_handle(handle);
     }
 
@@ -488,7 +774,23 @@ impl Stack {
         &mut self,
         cookies: &[AppCookie],
     ) -> Option<ReceiveHandle> {
-        // Find the earliest matching entry
+        self.get_deferred_bycookie_priority(cookies, |_| 0)
+    }
+
+    /// Retrieves a deferred message, preferring higher priority types.
+    ///
+    /// As for [`get_deferred_bycookie`](Self::get_deferred_bycookie),
+    /// but returns the earliest of the messages with the highest
+    /// `priority(typ)`.
+    pub fn get_deferred_bycookie_priority<P>(
+        &mut self,
+        cookies: &[AppCookie],
+        priority: P,
+    ) -> Option<ReceiveHandle>
+    where
+        P: Fn(MsgType) -> u8,
+    {
+        // Find the earliest matching entry of the highest priority
         self.done_reassemblers()
             .filter(|(_i, re)| {
                 if let Some(c) = re.cookie {
@@ -498,7 +800,10 @@ impl Stack {
                 }
                 false
             })
-            .min_by_key(|(_i, re)| re.stamp)
+            .min_by_key(|(_i, re)| {
+                let prio = re.done_type().map_or(0, &priority);
+                (core::cmp::Reverse(prio), re.stamp)
+            })
             .map(|(i, re)| re.take_handle(i))
     }
 
@@ -514,7 +819,7 @@ impl Stack {
             .filter_map(|(i, r)| {
                 // re must be Some and is_done
                 r.as_mut()
-                    .and_then(|(re, _buf)| re.is_done().then_some((i, re)))
+                    .and_then(|(re, _len)| re.is_done().then_some((i, re)))
             })
     }
 
@@ -524,10 +829,22 @@ impl Stack {
         cookie: Option<AppCookie>,
     ) {
         // OK unwrap: handle can't be invalid
-        let (re, _buf) = self.reassemblers[handle.0].as_mut().unwrap();
+        let (re, _len) = self.reassemblers[handle.0].as_mut().unwrap();
         re.set_cookie(cookie)
     }
 
+    /// Writes and reads back each unused reassembly buffer.
+    ///
+    /// See [`Router::pre_warm()`](crate::Router::pre_warm).
+    pub(crate) fn pre_warm(&mut self) -> Result<()> {
+        for (i, slot) in self.reassemblers.iter().enumerate() {
+            if slot.is_none() {
+                util::check_memory(self.pool.buffer(i))?;
+            }
+        }
+        Ok(())
+    }
+
     /// Sets the local Endpoint ID.
     pub fn set_eid(&mut self, eid: u8) -> Result<()> {
         self.own_eid = Eid::new_normal(eid)
@@ -545,20 +862,50 @@ impl Stack {
         Reassembler::is_local_dest(self.own_eid, packet)
     }
 
+    /// Returns true for a packet without SOM that doesn't continue
+    /// a reassembly in progress.
+    pub(crate) fn is_orphan_fragment(&self, packet: &[u8]) -> bool {
+        peek_header(packet).is_ok_and(|h| !h.som)
+            && !self.reassemblers.iter().any(|r| {
+                r.as_ref()
+                    .is_some_and(|(re, _len)| re.matches_packet(packet))
+            })
+    }
+
     /// Returns an index in to the `reassemblers` array
     fn get_reassembler(&mut self, packet: &[u8]) -> Result<usize> {
         // Look for an existing match
         let pos = self.reassemblers.iter().position(|r| {
             r.as_ref()
-                .is_some_and(|(re, _buf)| re.matches_packet(packet))
+                .is_some_and(|(re, _len)| re.matches_packet(packet))
         });
         if let Some(pos) = pos {
             return Ok(pos);
         }
 
+        // A new reassembly must start with SOM
+        if self.is_orphan_fragment(packet) {
+            trace!("fragment without SOM");
+            return Err(Error::InvalidInput);
+        }
+
         // Find a spare slot
-        let pos = self.reassemblers.iter().position(|r| r.is_none());
-        if let Some(pos) = pos {
+        let mut spare = self
+            .reassemblers
+            .iter()
+            .enumerate()
+            .filter(|(_i, r)| r.is_none())
+            .map(|(i, _r)| i);
+        if let Some(pos) = spare.next() {
+            let last = spare.next().is_none();
+            if self.control_reserve
+                && last
+                && NUM_RECEIVE > 1
+                && !is_control_start(packet)
+            {
+                trace!("last reassembler reserved for control");
+                return Err(Error::NoSpace);
+            }
             return Ok(pos);
         }
 
@@ -581,9 +928,15 @@ impl Stack {
         let mut tag = None;
 
         // Find an unset bit
-        self.next_tag = (self.next_tag + 1) & mctp::MCTP_TAG_MAX;
-        let end = self.next_tag + mctp::MCTP_TAG_MAX;
-        for t in self.next_tag..=end {
+        let start = match self.tag_alloc {
+            TagAllocStrategy::RoundRobin => {
+                self.next_tag = (self.next_tag + 1) & mctp::MCTP_TAG_MAX;
+                self.next_tag
+            }
+            TagAllocStrategy::LowestFree => 0,
+        };
+        let end = start + mctp::MCTP_TAG_MAX;
+        for t in start..=end {
             let t = t & mctp::MCTP_TAG_MAX;
             let tagmask = 1 << t;
             if used & tagmask == 0 {
@@ -603,7 +956,7 @@ impl Stack {
         &mut self,
         peer: Eid,
         fixedtag: Option<TagValue>,
-        flow_expires: bool,
+        lifetime: Option<u32>,
         cookie: Option<AppCookie>,
     ) -> Result<TagValue> {
         let tag = fixedtag.or_else(|| self.alloc_tag(peer));
@@ -613,10 +966,16 @@ impl Stack {
             return Err(Error::TagUnavailable);
         };
 
-        let expiry_stamp = flow_expires.then(|| self.now.increment());
+        if self.max_owned_tags.is_some_and(|m| self.flows.len() >= m) {
+            debug!("Owned tag limit reached");
+            return Err(Error::TagUnavailable);
+        }
+
+        let expiry_stamp = lifetime.map(|_| self.now.increment());
 
         let f = Flow {
             expiry_stamp,
+            lifetime: lifetime.unwrap_or(0),
             cookie,
         };
         let r = self
@@ -633,7 +992,7 @@ impl Stack {
         &mut self,
         peer: Eid,
         tag: Option<TagValue>,
-        flow_expires: bool,
+        lifetime: Option<u32>,
         cookie: Option<AppCookie>,
     ) -> Result<TagValue> {
         trace!("set flow {}", peer);
@@ -654,7 +1013,7 @@ impl Stack {
             }
         }
 
-        self.new_flow(peer, tag, flow_expires, cookie)
+        self.new_flow(peer, tag, lifetime, cookie)
     }
 
     fn lookup_flow(&self, peer: Eid, tv: TagValue) -> Option<&Flow> {
@@ -666,6 +1025,88 @@ impl Stack {
         let r = self.flows.remove(&(peer, tv));
 
         debug_assert!(r.is_some(), "non-existent remove_flow");
+        if r.is_some() {
+            self.note_released(peer, tv);
+        }
+    }
+
+    fn note_released(&mut self, peer: Eid, tv: TagValue) {
+        if self.track_released && self.released.push((peer, tv)).is_err() {
+            debug!("Released tag list full");
+        }
+    }
+
+    /// Returns the number of owned tags that can still be allocated to `peer`.
+    ///
+    /// This is limited both by the tags already in use for `peer`
+    /// and by the total number of flows.
+    pub fn free_tags(&self, peer: Eid) -> usize {
+        let used = self.flows.keys().filter(|(p, _tag)| *p == peer).count();
+        let per_peer = (mctp::MCTP_TAG_MAX as usize + 1).saturating_sub(used);
+        let capacity = self
+            .max_owned_tags
+            .map_or(self.flows.capacity(), |m| m.min(self.flows.capacity()));
+        let total = capacity.saturating_sub(self.flows.len());
+        per_peer.min(total)
+    }
+
+    /// Limit the number of owned tags allocated for all peers.
+    ///
+    /// Once `max` owned tags are in use, sends that need a new owned tag
+    /// fail with [`Error::TagUnavailable`]. Tags already allocated
+    /// beyond a new limit remain valid until released.
+    /// `None` allows up to [`config::FLOWS`] tags, the default.
+    pub fn set_max_owned_tags(&mut self, max: Option<usize>) {
+        self.max_owned_tags = max;
+    }
+
+    /// Limit the number of packets in a received message.
+    ///
+    /// A message with more than `max` packets is dropped, and its
+    /// reassembly slot released. The packet exceeding the limit returns
+    /// [`Error::RxFailure`] from [`receive()`](Self::receive).
+    /// This guards against a peer exhausting resources with
+    /// many small fragments. `None` (the default) allows any number,
+    /// limited only by the reassembly buffer size.
+    pub fn set_max_fragments(&mut self, max: Option<usize>) {
+        self.max_fragments = max;
+    }
+
+    /// Reserve a reassembly slot for MCTP Control messages.
+    ///
+    /// When enabled, other message types won't start reassembly
+    /// in the last free slot, so that control messages can still be
+    /// received while other traffic occupies the remaining slots.
+    /// Has no effect if `NUM_RECEIVE` is 1.
+    pub fn set_control_reserve(&mut self, reserve: bool) {
+        self.control_reserve = reserve;
+    }
+
+    /// Set how owned tags are allocated.
+    ///
+    /// The default is [`TagAllocStrategy::RoundRobin`].
+    pub fn set_tag_alloc(&mut self, strategy: TagAllocStrategy) {
+        self.tag_alloc = strategy;
+    }
+
+    /// Enables recording of released owned tags.
+    ///
+    /// When enabled, owned tags released by expiry, response completion,
+    /// or [`cancel_flow()`](Self::cancel_flow) are recorded until retrieved
+    /// with [`take_released()`](Self::take_released).
+    pub fn set_track_released(&mut self, track: bool) {
+        self.track_released = track;
+        if !track {
+            self.released.clear();
+        }
+    }
+
+    /// Returns the owned tags released since the previous call.
+    ///
+    /// Items are `(peer, tag)`. Only populated when enabled by
+    /// [`set_track_released()`](Self::set_track_released).
+    pub fn take_released(&mut self) -> Vec<(Eid, TagValue), FLOWS> {
+        core::mem::take(&mut self.released)
     }
 
     pub fn cancel_flow(&mut self, source: Eid, tv: TagValue) -> Result<()> {
@@ -673,7 +1114,7 @@ impl Stack {
         let tag = Tag::Unowned(tv);
         let mut removed = false;
         for r in self.reassemblers.iter_mut() {
-            if let Some((re, _buf)) = r.as_mut() {
+            if let Some((re, _len)) = r.as_mut() {
                 if re.tag == tag && re.peer == source {
                     if re.handle_taken() {
                         trace!("Outstanding handle");
@@ -691,6 +1132,9 @@ impl Stack {
         if removed {
             debug_assert!(r.is_some());
         }
+        if r.is_some() {
+            self.note_released(source, tv);
+        }
         Ok(())
     }
 }
@@ -774,9 +1218,161 @@ pub(crate) mod fmt {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
 
     // TODO:
     // back to back fragmenter/reassembler
 
     // back to back stacks?
+
+    #[test]
+    fn tag_alloc() {
+        let typ = MsgType(0x10);
+        let mut stack = Stack::new(Eid(10), 64, 0);
+        let send = |stack: &mut Stack| {
+            let f = stack
+                .start_send(Eid(20), typ, None, true, false, None, None)
+                .unwrap();
+            f.tag().tag().0
+        };
+
+        // Released tags are not reused immediately
+        let mut tags = std::vec::Vec::new();
+        for _ in 0..9 {
+            let t = send(&mut stack);
+            stack.cancel_flow(Eid(20), TagValue(t)).unwrap();
+            tags.push(t);
+        }
+        assert_eq!(tags, [1, 2, 3, 4, 5, 6, 7, 0, 1]);
+
+        stack.set_tag_alloc(TagAllocStrategy::LowestFree);
+        let t = send(&mut stack);
+        stack.cancel_flow(Eid(20), TagValue(t)).unwrap();
+        assert_eq!(t, 0);
+        // Outstanding tags are skipped
+        let tags = [send(&mut stack), send(&mut stack), send(&mut stack)];
+        assert_eq!(tags, [0, 1, 2]);
+        stack.cancel_flow(Eid(20), TagValue(1)).unwrap();
+        assert_eq!(send(&mut stack), 1);
+    }
+
+    #[test]
+    fn clock_backwards() {
+        let mut stack = Stack::new(Eid(10), 64, 1000);
+        stack.set_track_released(true);
+        let f = stack
+            .start_send(Eid(20), MsgType(0x10), None, true, false, None, None)
+            .unwrap();
+        let tv = f.tag().tag();
+
+        stack.update(2000).unwrap();
+        assert_eq!(stack.flows.len(), 1);
+
+        // Step backwards, no time elapses
+        let (_, expired) = stack.update(10).unwrap();
+        assert!(!expired);
+        assert_eq!(stack.flows.len(), 1);
+        assert!(stack.suggested_interval() <= TIMEOUT_INTERVAL as u64);
+
+        // 5990 ms since the flow was created
+        let (_, expired) = stack.update(4000).unwrap();
+        assert!(!expired);
+        assert_eq!(stack.flows.len(), 1);
+
+        // Expires on time, not leaked
+        let (_, expired) = stack.update(5020).unwrap();
+        assert!(expired);
+        assert!(stack.flows.is_empty());
+        assert_eq!(stack.take_released().as_slice(), &[(Eid(20), tv)]);
+
+        // Forward again from the new baseline
+        stack.update(5030).unwrap();
+        assert_eq!(stack.now.clock, 7020);
+    }
+
+    #[test]
+    fn custom_pool() {
+        use crate::test_util::PacketBuilder;
+
+        // Buffers from a provided slice
+        struct SlicePool<'a>(&'a mut [[u8; 20]]);
+
+        impl ReassemblyPool for SlicePool<'_> {
+            fn buffer(&mut self, index: usize) -> &mut [u8] {
+                &mut self.0[index]
+            }
+        }
+
+        let mut mem = [[0u8; 20]; NUM_RECEIVE];
+        let mut stack =
+            Stack::new_with_pool(SlicePool(&mut mem), Eid(10), 64, 0);
+        let typ = MsgType(0x10);
+        let tag = Tag::Owned(TagValue(1));
+
+        let pkt = PacketBuilder::new(Eid(20), Eid(10), tag, typ)
+            .build(&[7; 20])
+            .unwrap();
+        let (msg, handle) = stack.receive(&pkt).unwrap().unwrap();
+        assert_eq!(msg.payload, &[7; 20]);
+        stack.finished_receive(handle);
+
+        // Too large for the pool's buffers
+        let pkt = PacketBuilder::new(Eid(21), Eid(10), tag, typ)
+            .build(&[7; 21])
+            .unwrap();
+        assert!(matches!(stack.receive(&pkt), Err(Error::NoSpace)));
+
+        drop(stack);
+        assert_eq!(mem[0], [7; 20]);
+    }
+
+    #[test]
+    fn max_fragments() {
+        use crate::test_util::PacketBuilder;
+
+        let mut stack = Stack::new(Eid(10), 64, 0);
+        stack.set_max_fragments(Some(3));
+        let typ = MsgType(0x10);
+        let tag = Tag::Owned(TagValue(1));
+        let frag = |seq: u8, som, eom| {
+            PacketBuilder::new(Eid(20), Eid(10), tag, typ)
+                .som(som)
+                .eom(eom)
+                .seq(seq)
+                .build(&[seq; 1])
+                .unwrap()
+        };
+
+        // Within the limit
+        assert!(stack.receive(&frag(0, true, false)).unwrap().is_none());
+        assert!(stack.receive(&frag(1, false, false)).unwrap().is_none());
+        let (msg, handle) =
+            stack.receive(&frag(2, false, true)).unwrap().unwrap();
+        assert_eq!(msg.payload, &[0, 1, 2]);
+        stack.finished_receive(handle);
+
+        // Aborted on the 4th fragment
+        for seq in 0..3 {
+            assert!(stack
+                .receive(&frag(seq, seq == 0, false))
+                .unwrap()
+                .is_none());
+        }
+        assert!(matches!(
+            stack.receive(&frag(3, false, false)),
+            Err(Error::RxFailure)
+        ));
+        // The slot is released, later fragments are orphans
+        assert!(stack.reassemblers.iter().all(|r| r.is_none()));
+        assert!(matches!(
+            stack.receive(&frag(0, false, true)),
+            Err(Error::InvalidInput)
+        ));
+
+        // A new message is accepted
+        let (msg, handle) =
+            stack.receive(&frag(1, true, true)).unwrap().unwrap();
+        assert_eq!(msg.payload, &[1]);
+        stack.finished_receive(handle);
+    }
 }
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Packet capture export
+//!
+//! Writes MCTP packets to a [pcapng] file that can be read by
+//! Wireshark or other tools. Packets are written with the
+//! `LINKTYPE_MCTP` link type, starting with the MCTP transport header.
+//!
+//! Each [`PortId`] is recorded as a separate pcapng interface.
+//! Packets can be recorded from any point they are available, for example
+//! when passed to [`Router::inbound()`](crate::Router::inbound) or
+//! returned by [`PortBottom::outbound()`](crate::router::PortBottom::outbound).
+//!
+//! [pcapng]: https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-03.html
+
+use crate::router::PortId;
+use std::io::{Result, Write};
+
+/// `LINKTYPE_MCTP`, packets begin with the MCTP transport header
+pub const LINKTYPE_MCTP: u16 = 291;
+
+const BLOCK_SHB: u32 = 0x0a0d_0d0a;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// Writes packets in pcapng format.
+///
+/// Timestamps are recorded with microsecond resolution.
+pub struct PcapWriter<W: Write> {
+    w: W,
+    /// Number of interface blocks written
+    interfaces: usize,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Create a new `PcapWriter`, writing the file header to `w`.
+    pub fn new(mut w: W) -> Result<Self> {
+        // Section Header Block
+        let len = 28u32;
+        w.write_all(&BLOCK_SHB.to_le_bytes())?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&BYTE_ORDER_MAGIC.to_le_bytes())?;
+        // Version 1.0
+        w.write_all(&1u16.to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+        // Section length unspecified
+        w.write_all(&(-1i64).to_le_bytes())?;
+        w.write_all(&len.to_le_bytes())?;
+        Ok(Self { w, interfaces: 0 })
+    }
+
+    /// Write a packet.
+    ///
+    /// `timestamp_millis` is in milliseconds, typically the same clock
+    /// as passed to [`Router::update_time()`](crate::Router::update_time).
+    pub fn write_packet(
+        &mut self,
+        timestamp_millis: u64,
+        port: PortId,
+        pkt: &[u8],
+    ) -> Result<()> {
+        // Interface IDs are assigned in order, so define up to this port
+        while self.interfaces <= port.0 as usize {
+            self.write_interface()?;
+        }
+
+        let pad = pkt.len().next_multiple_of(4) - pkt.len();
+        let len = u32::try_from(32 + pkt.len() + pad)
+            .map_err(|_| std::io::ErrorKind::InvalidInput)?;
+        let caplen = pkt.len() as u32;
+        let ts = timestamp_millis.wrapping_mul(1000);
+
+        // Enhanced Packet Block
+        self.w.write_all(&BLOCK_EPB.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&(port.0 as u32).to_le_bytes())?;
+        self.w.write_all(&((ts >> 32) as u32).to_le_bytes())?;
+        self.w.write_all(&(ts as u32).to_le_bytes())?;
+        self.w.write_all(&caplen.to_le_bytes())?;
+        self.w.write_all(&caplen.to_le_bytes())?;
+        self.w.write_all(pkt)?;
+        self.w.write_all(&[0u8; 3][..pad])?;
+        self.w.write_all(&len.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.w.flush()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    fn write_interface(&mut self) -> Result<()> {
+        // Interface Description Block
+        let len = 20u32;
+        self.w.write_all(&BLOCK_IDB.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.w.write_all(&LINKTYPE_MCTP.to_le_bytes())?;
+        // Reserved
+        self.w.write_all(&0u16.to_le_bytes())?;
+        // No snap length limit
+        self.w.write_all(&0u32.to_le_bytes())?;
+        self.w.write_all(&len.to_le_bytes())?;
+        self.interfaces += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(b: &[u8], off: usize) -> u32 {
+        u32::from_le_bytes(b[off..][..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn pcapng_records() {
+        let mut w = PcapWriter::new(std::vec::Vec::new()).unwrap();
+        w.write_packet(1500, PortId(1), &[0x01, 0x0a, 0x14, 0xc8, 0x7e])
+            .unwrap();
+        w.write_packet(1501, PortId(0), &[0x01, 0x14, 0x0a, 0xc0])
+            .unwrap();
+        let b = w.into_inner();
+
+        // Section header
+        assert_eq!(u32_at(&b, 0), BLOCK_SHB);
+        assert_eq!(u32_at(&b, 4), 28);
+        assert_eq!(u32_at(&b, 8), BYTE_ORDER_MAGIC);
+        assert_eq!(u32_at(&b, 24), 28);
+        let mut off = 28;
+
+        // Interfaces for ports 0 and 1
+        for _ in 0..2 {
+            assert_eq!(u32_at(&b, off), BLOCK_IDB);
+            assert_eq!(u32_at(&b, off + 4), 20);
+            assert_eq!(&b[off + 8..][..2], &LINKTYPE_MCTP.to_le_bytes());
+            assert_eq!(u32_at(&b, off + 16), 20);
+            off += 20;
+        }
+
+        // First packet, padded to 8 bytes
+        assert_eq!(u32_at(&b, off), BLOCK_EPB);
+        assert_eq!(u32_at(&b, off + 4), 40);
+        assert_eq!(u32_at(&b, off + 8), 1);
+        assert_eq!(u32_at(&b, off + 12), 0);
+        assert_eq!(u32_at(&b, off + 16), 1_500_000);
+        assert_eq!(u32_at(&b, off + 20), 5);
+        assert_eq!(u32_at(&b, off + 24), 5);
+        assert_eq!(&b[off + 28..][..5], &[0x01, 0x0a, 0x14, 0xc8, 0x7e]);
+        assert_eq!(u32_at(&b, off + 36), 40);
+        off += 40;
+
+        // Second packet, no new interface
+        assert_eq!(u32_at(&b, off), BLOCK_EPB);
+        assert_eq!(u32_at(&b, off + 4), 36);
+        assert_eq!(u32_at(&b, off + 8), 0);
+        assert_eq!(u32_at(&b, off + 16), 1_501_000);
+        assert_eq!(u32_at(&b, off + 32), 36);
+        off += 36;
+
+        assert_eq!(b.len(), off);
+    }
+}
@@ -18,6 +18,8 @@ enum State {
         typ: MsgType,
         ic: bool,
         next_seq: u8,
+        /// Packets received for the message
+        fragments: usize,
     },
     Done {
         typ: MsgType,
@@ -99,11 +101,19 @@ impl Reassembler {
     ///
     /// Returns `Ok(Some(_))` when a full message is reassembled.
     /// Returns `Ok(None)` on success when the message is incomplete.
-    pub fn receive<'f, const N: usize>(
+    ///
+    /// The message is reassembled into `message`, with `len` bytes
+    /// used so far.
+    ///
+    /// Returns `Error::RxFailure` if the message has more than
+    /// `max_fragments` packets.
+    pub fn receive<'f>(
         &mut self,
         packet: &[u8],
-        message: &'f mut Vec<u8, N>,
+        message: &'f mut [u8],
+        len: &mut usize,
         stamp: EventStamp,
+        max_fragments: Option<usize>,
     ) -> Result<Option<MctpMessage<'f>>> {
         if !self.matches_packet(packet) {
             // Callers should have already checked matches_packet().
@@ -127,14 +137,19 @@ impl Reassembler {
             let (typ, ic) = mctp::decode_type_ic(packet[HEADER_LEN]);
             let next_seq = header.pkt_seq();
 
-            self.state = State::Active { next_seq, typ, ic };
+            self.state = State::Active {
+                next_seq,
+                typ,
+                ic,
+                fragments: 0,
+            };
 
             // New SOM packet restarts reassembly
-            if !message.is_empty() {
+            if *len != 0 {
                 // TODO counters
                 debug!("Duplicate SOM");
             }
-            message.clear();
+            *len = 0;
             self.stamp = stamp;
         }
 
@@ -142,6 +157,7 @@ impl Reassembler {
             typ,
             ic,
             ref mut next_seq,
+            ref mut fragments,
         } = self.state
         else {
             // TODO counters
@@ -156,31 +172,47 @@ impl Reassembler {
             // TODO counters
             debug!("Bad seq got {} expect {}", header.pkt_seq(), next_seq);
             self.state = State::Bad;
-            message.clear();
+            *len = 0;
             return Err(Error::InvalidInput);
         }
 
-        message.extend_from_slice(payload).map_err(|_| {
+        *fragments += 1;
+        if max_fragments.is_some_and(|m| *fragments > m) {
+            debug!("Too many fragments, {}", fragments);
+            self.state = State::Bad;
+            *len = 0;
+            return Err(Error::RxFailure);
+        }
+
+        let end = *len + payload.len();
+        let dst = message.get_mut(*len..end).ok_or_else(|| {
             self.state = State::Bad;
             trace!("nospace message too long");
             Error::NoSpace
         })?;
+        dst.copy_from_slice(payload);
+        *len = end;
 
         if eom {
             self.state = State::Done { typ, ic };
             self.stamp = stamp;
-            trace!("message reassembly complete, len {}", message.len());
-            return Ok(Some(self.message(message)?));
+            trace!("message reassembly complete, len {}", end);
+            return Ok(Some(self.message(&message[..end])?));
         }
 
         Ok(None)
     }
 
+    /// Returns the message type of a complete message.
+    pub fn done_type(&self) -> Option<MsgType> {
+        match self.state {
+            State::Done { typ, .. } => Some(typ),
+            _ => None,
+        }
+    }
+
     /// Must be called in Done state
-    pub fn message<'f, const N: usize>(
-        &self,
-        message: &'f mut Vec<u8, N>,
-    ) -> Result<MctpMessage<'f>> {
+    pub fn message<'f>(&self, message: &'f [u8]) -> Result<MctpMessage<'f>> {
         let State::Done { typ, ic } = self.state else {
             return Err(Error::BadArgument);
         };
@@ -192,7 +224,7 @@ impl Reassembler {
 
             typ,
             ic,
-            payload: message.as_slice(),
+            payload: message,
             cookie: self.cookie,
         })
     }
@@ -10,38 +10,128 @@ use crate::fmt::{debug, error, info, trace, warn};
 
 use core::cell::RefCell;
 use core::future::{poll_fn, Future};
+use core::ops::RangeInclusive;
 use core::pin::pin;
-use core::task::Poll;
+use core::task::{Poll, Waker};
 
 use crate::reassemble::Reassembler;
 use crate::{
-    AppCookie, Fragmenter, ReceiveHandle, SendOutput, Stack, MAX_MTU,
-    MAX_PAYLOAD,
+    is_control_start, peek_header, AppCookie, Fragmenter, MctpMessage,
+    PacketHeader, ReassemblyPool, ReceiveHandle, SendOutput, Stack, StaticPool,
+    FLOWS, FORWARD_LOOP_WINDOW, HEADER_LEN, MAX_MTU, MAX_PAYLOAD,
+    SEND_YIELD_INTERVAL,
 };
 use mctp::{Eid, Error, MsgType, Result, Tag, TagValue};
 
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
 use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
 use embassy_sync::zerocopy_channel::{Channel, Receiver, Sender};
 
 use heapless::Vec;
 
-// TODO sizing is a bit arbitrary. They don't take up much space.
-const MAX_LISTENERS: usize = 20;
-const MAX_RECEIVERS: usize = 50;
+mod control_lane;
+mod forward_loop;
+mod pool;
+mod stats;
+#[cfg(test)]
+mod tests;
 
-// TODO: feature to configure mutex?
-type RawMutex = embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-type AsyncMutex<T> = embassy_sync::mutex::Mutex<RawMutex, T>;
-type BlockingMutex<T> =
-    embassy_sync::blocking_mutex::Mutex<RawMutex, RefCell<T>>;
+use control_lane::MAX_CONTROL_FLOWS;
+use forward_loop::ForwardSeen;
+use pool::RecvPoolSlots;
+pub use pool::{PooledMessage, RecvPool, MAX_RECV_POOL};
+pub use stats::{
+    DequeueLatency, LinkError, LinkErrorStats, ListenerStats, PacketSizes,
+    PortStats, RouterStats, PACKET_SIZE_BUCKETS,
+};
+
+// Peers with an MTU set by Router::set_peer_mtu()
+const MAX_PEER_MTUS: usize = 16;
+// Message types with an MTU set by Router::set_type_mtu()
+const MAX_TYPE_MTUS: usize = 8;
+// Additional EIDs added by Router::add_local_eid()
+const MAX_LOCAL_EIDS: usize = 4;
+
+/// Ports that a single `Router::reconfigure()` can change
+const MAX_RECONFIGURE_PORTS: usize = 8;
+
+/// Number of partially queued messages per port that a failed send
+/// can cancel, see `PortShared::cancel()`
+const MAX_CANCELLED: usize = 4;
 
-type PortRawMutex = embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-// type PortRawMutex = embassy_sync::blocking_mutex::raw::NoopRawMutex;
+type AsyncMutex<M, T> = embassy_sync::mutex::Mutex<M, T>;
+type InnerGuard<'a, 'r, M, R, const LISTENERS: usize, const RECEIVERS: usize> =
+    embassy_sync::mutex::MutexGuard<
+        'a,
+        M,
+        RouterInner<'r, R, LISTENERS, RECEIVERS>,
+    >;
+type SenderGuard<'g, 'a, M> =
+    embassy_sync::mutex::MutexGuard<'g, M, Sender<'a, M, PktBuf>>;
+type BlockingMutex<M, T> = embassy_sync::blocking_mutex::Mutex<M, RefCell<T>>;
 
 // Identifier for a Port
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PortId(pub u8);
 
+/// Requirement for the integrity check (IC) bit of messages received
+/// by a listener.
+///
+/// Set with [`RouterAsyncListener::set_ic_policy()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IcPolicy {
+    /// Messages are accepted regardless of the IC bit.
+    #[default]
+    Any,
+    /// Messages without the IC bit set are dropped.
+    Require,
+    /// Messages with the IC bit set are dropped.
+    Forbid,
+}
+
+impl IcPolicy {
+    fn allows(&self, ic: bool) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Require => ic,
+            Self::Forbid => !ic,
+        }
+    }
+}
+
+/// Handling of incoming packets with a null source EID.
+///
+/// Set with [`Router::set_null_source_policy()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NullSourcePolicy {
+    /// Packets are handled normally.
+    #[default]
+    Accept,
+    /// Packets are handled normally, with a warning logged.
+    Log,
+    /// Packets are dropped.
+    Drop,
+}
+
+/// Behaviour when forwarding to a port with a full queue.
+///
+/// Set with [`Router::set_port_overflow()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the packet being forwarded.
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued packet, then enqueue the forwarded packet.
+    ///
+    /// A forward that takes the last free queue slot marks the oldest
+    /// queued packet to be discarded, which happens when the `PortBottom`
+    /// next retrieves a packet. Forwarding never waits for the
+    /// `PortBottom`. If it hasn't retrieved a packet since, the queue is
+    /// full and the forwarded packet is dropped, as for `DropNewest`.
+    DropOldest,
+}
+
 /// A trait implemented by applications to determine the routing table.
 pub trait PortLookup: Send {
     /// Returns the `PortId` for a destination EID.
@@ -59,6 +149,192 @@ pub trait PortLookup: Send {
         eid: Eid,
         source_port: Option<PortId>,
     ) -> Option<PortId>;
+
+    /// Returns true to deliver packets for `eid` locally.
+    ///
+    /// This is called for incoming packets with a destination other
+    /// than the local EID, prior to forwarding. It allows an application
+    /// to receive messages addressed to additional EIDs, for example
+    /// to terminate traffic for a monitoring EID.
+    ///
+    /// Messages are delivered to listeners as for the local EID.
+    /// Responses are sent with `eid` as the source.
+    /// Local sends to `eid` are also delivered locally.
+    ///
+    /// The default implementation returns false.
+    fn deliver_local(&mut self, _eid: Eid) -> bool {
+        false
+    }
+
+    /// Returns a port to receive a copy of an incoming packet.
+    ///
+    /// This is called for each incoming packet with a valid header,
+    /// before it is delivered locally or forwarded as normal. If a
+    /// port is returned the packet is also enqueued to that port, for
+    /// example for a monitoring interface. Packets larger than the
+    /// mirror port's MTU are not mirrored.
+    ///
+    /// `source_port` is the port the packet arrived on.
+    ///
+    /// The default implementation returns `None`.
+    fn mirror(
+        &mut self,
+        _header: &PacketHeader,
+        _source_port: PortId,
+    ) -> Option<PortId> {
+        None
+    }
+}
+
+/// A routing table of EID ranges, implementing [`PortLookup`].
+///
+/// Holds up to `N` routes, each a range of EIDs reached through a port.
+/// Routes may not overlap. A default route is used for EIDs that
+/// don't match any range.
+#[derive(Debug)]
+pub struct StaticRouteTable<const N: usize> {
+    /// Routes as `(start, end, port)`
+    routes: Vec<(Eid, Eid, PortId), N>,
+    default: Option<PortId>,
+}
+
+impl<const N: usize> StaticRouteTable<N> {
+    /// Create an empty table, with no default route.
+    pub const fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Add a route for EIDs `start` to `end` inclusive.
+    ///
+    /// Returns `Error::BadArgument` if `start` is greater than `end`,
+    /// or the range overlaps an existing route.
+    /// Returns `Error::NoSpace` if the table has `N` routes.
+    pub fn add_route(
+        &mut self,
+        start: Eid,
+        end: Eid,
+        port: PortId,
+    ) -> Result<()> {
+        if start > end {
+            return Err(Error::BadArgument);
+        }
+        if self
+            .routes
+            .iter()
+            .any(|(s, e, _p)| start <= *e && *s <= end)
+        {
+            debug!("Route {}-{} overlaps", start.0, end.0);
+            return Err(Error::BadArgument);
+        }
+        self.routes
+            .push((start, end, port))
+            .map_err(|_| Error::NoSpace)
+    }
+
+    /// Remove the route for EIDs `start` to `end`.
+    ///
+    /// The range must match that of a route added with
+    /// [`add_route()`](Self::add_route), otherwise `Error::BadArgument`
+    /// is returned. Returns the route's port.
+    pub fn remove_route(&mut self, start: Eid, end: Eid) -> Result<PortId> {
+        let i = self
+            .routes
+            .iter()
+            .position(|(s, e, _p)| (*s, *e) == (start, end))
+            .ok_or(Error::BadArgument)?;
+        Ok(self.routes.swap_remove(i).2)
+    }
+
+    /// Set the port for EIDs that don't match a route.
+    ///
+    /// `None` (the default) drops packets to unmatched EIDs.
+    pub fn set_default_route(&mut self, port: Option<PortId>) {
+        self.default = port;
+    }
+}
+
+impl<const N: usize> Default for StaticRouteTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PortLookup for StaticRouteTable<N> {
+    fn by_eid(
+        &mut self,
+        eid: Eid,
+        _source_port: Option<PortId>,
+    ) -> Option<PortId> {
+        // Routes don't overlap, so at most one matches
+        self.routes
+            .iter()
+            .find(|(s, e, _p)| (*s..=*e).contains(&eid))
+            .map(|(_s, _e, p)| *p)
+            .or(self.default)
+    }
+}
+
+/// Optional application callbacks for [`Router`] events.
+///
+/// Set with [`Router::set_hooks`]. Hooks are called without the
+/// `Router`'s internal locks held, but should return promptly.
+pub trait RouterHooks: Sync {
+    /// Called when an owned tag is released.
+    ///
+    /// This occurs when a response message is received, when the tag
+    /// expires, or when a no-expire tag is released by
+    /// [`RouterAsyncReqChannel::async_drop`].
+    fn on_tag_release(&self, _eid: Eid, _tag: TagValue) {}
+
+    /// Called after each packet of a locally sent message is enqueued.
+    ///
+    /// `remaining` is the number of payload bytes still to be sent,
+    /// reaching 0 for the final packet. This can be used for progress
+    /// reporting of large sends.
+    fn on_send_progress(&self, _eid: Eid, _tag: Tag, _remaining: usize) {}
+
+    /// Called for each packet of a locally sent message before it is
+    /// enqueued to a port.
+    ///
+    /// The packet may be inspected or modified in place, for example
+    /// for fault injection. Its length can't be changed. If the
+    /// modified packet no longer has a valid MCTP header it is
+    /// not enqueued and the send fails with `Error::InvalidInput`.
+    /// Earlier packets of the message still in the port's queue are
+    /// then dropped, though a packet already retrieved by the
+    /// [`PortBottom`] can't be recalled.
+    ///
+    /// This isn't called for forwarded packets or local loopback.
+    fn filter_outbound(&self, _pkt: &mut [u8]) {}
+
+    /// Called for an incoming packet that is dropped since it
+    /// has no route.
+    ///
+    /// The packet had a valid header, but wasn't for a local EID
+    /// and the [`PortLookup`] had no port for its destination.
+    /// `port` is the port the packet arrived on.
+    fn on_unroutable(&self, _header: &PacketHeader, _port: PortId) {}
+
+    /// Called when a complete request message for a local EID is
+    /// dropped since no listener is bound for its type.
+    ///
+    /// This can be used to detect missing handlers. Messages dropped
+    /// by a bound listener's IC policy or queue depth aren't reported.
+    fn on_no_listener(&self, _source: Eid, _typ: MsgType) {}
+
+    /// Called with the payload of a locally sent message before it
+    /// is fragmented.
+    ///
+    /// For vectored sends `payload` is the message assembled in the
+    /// port's scratch buffer, and the scratch buffer's lock is held
+    /// while this runs.
+    ///
+    /// This isn't called for local loopback, or for sends with a
+    /// payload producer.
+    fn on_message(&self, _eid: Eid, _typ: MsgType, _payload: &[u8]) {}
 }
 
 /// Used like `heapless::Vec`, but lets the mut buffer be written into
@@ -67,6 +343,14 @@ struct PktBuf {
     data: [u8; MAX_MTU],
     len: usize,
     dest: Eid,
+    /// Time the packet was enqueued, if the port records latency
+    stamp: Option<u64>,
+    /// Woken by `outbound_done()`, for the last packet of a message
+    /// sent with `RouterAsyncReqChannel::set_sent_waker()`
+    sent_waker: Option<Waker>,
+    /// Locally sent message the packet belongs to, or 0 for
+    /// forwarded packets
+    msg: u32,
 }
 
 impl PktBuf {
@@ -75,13 +359,14 @@ impl PktBuf {
             data: [0u8; MAX_MTU],
             len: 0,
             dest: Eid(0),
+            stamp: None,
+            sent_waker: None,
+            msg: 0,
         }
     }
 
     fn set(&mut self, data: &[u8]) -> Result<()> {
-        let hdr = Reassembler::header(data);
-        debug_assert!(hdr.is_ok());
-        let hdr = hdr?;
+        let hdr = Reassembler::header(data)?;
         let dst = self.data.get_mut(..data.len()).ok_or(Error::NoSpace)?;
         dst.copy_from_slice(data);
         self.len = data.len();
@@ -98,50 +383,480 @@ impl core::ops::Deref for PktBuf {
     }
 }
 
+/// Wakers for request and response channels waiting in `recv()`.
+///
+/// Unlike `MultiWakerRegistration`, registration fails when full rather
+/// than waking existing wakers. With more waiters than capacity
+/// that would otherwise cause waiters to repeatedly wake each other.
+struct ReceiveWakers<const RECEIVERS: usize> {
+    /// Each waker has the flow it is waiting on, if any
+    wakers: Vec<(Waker, Option<(Eid, TagValue)>), RECEIVERS>,
+}
+
+impl<const RECEIVERS: usize> ReceiveWakers<RECEIVERS> {
+    const fn new() -> Self {
+        Self { wakers: Vec::new() }
+    }
+
+    /// Registers a waker.
+    ///
+    /// Returns `Error::NoSpace` if the waker is not already registered
+    /// and the list is full.
+    fn register(&mut self, waker: &Waker) -> Result<()> {
+        self.register_flow(waker, None)
+    }
+
+    /// Registers a waker waiting on an owned tag flow.
+    ///
+    /// As for `register()`, `flow` is used by `wake_flows()`.
+    fn register_flow(
+        &mut self,
+        waker: &Waker,
+        flow: Option<(Eid, TagValue)>,
+    ) -> Result<()> {
+        if self
+            .wakers
+            .iter()
+            .any(|(w, f)| *f == flow && w.will_wake(waker))
+        {
+            return Ok(());
+        }
+        self.wakers
+            .push((waker.clone(), flow))
+            .map_err(|_| Error::NoSpace)
+    }
+
+    /// Wakes and clears wakers waiting on any of `flows`.
+    fn wake_flows(&mut self, flows: &[(Eid, TagValue)]) {
+        if flows.is_empty() {
+            return;
+        }
+        self.wakers.retain(|(w, f)| {
+            if f.is_some_and(|f| flows.contains(&f)) {
+                w.wake_by_ref();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Returns the number of registered wakers.
+    fn len(&self) -> usize {
+        self.wakers.len()
+    }
+
+    /// Wakes and clears all registered wakers.
+    ///
+    /// Wakers of dropped futures are only released here.
+    fn wake(&mut self) {
+        while let Some((w, _f)) = self.wakers.pop() {
+            w.wake();
+        }
+    }
+}
+
+/// Wakers for `Router::await_route()`.
+struct RouteWakers<const RECEIVERS: usize> {
+    /// Count of configuration changes, wrapping
+    changes: u32,
+    wakers: ReceiveWakers<RECEIVERS>,
+}
+
+/// State shared between a `PortTop` and `PortBottom`.
+struct PortShared<M: RawMutex> {
+    inner: BlockingMutex<M, PortSharedInner>,
+}
+
+struct PortSharedInner {
+    /// Count of packets enqueued, wrapping
+    enqueued: u32,
+    /// Count of packets consumed by `outbound_done()`, wrapping
+    consumed: u32,
+    /// The `PortBottom` has been dropped
+    detached: bool,
+    /// Number of oldest queued packets the `PortBottom` should discard,
+    /// for `OverflowPolicy::DropOldest`
+    discard: usize,
+    /// The `PortBottom` has returned a packet without `outbound_done()`
+    peeked: bool,
+    /// Wakers waiting for `consumed` to advance or `detached`
+    consumed_wakers: MultiWakerRegistration<4>,
+    /// Time from the most recent `Router::update_time()`
+    now: u64,
+    /// Record dequeue latency, set by `Router::set_port_latency()`
+    timestamps: bool,
+    latency: DequeueLatency,
+    /// Id of the most recent locally sent message, never 0
+    last_msg: u32,
+    /// Messages with queued packets the `PortBottom` should skip,
+    /// as (message id, sequence number of its last enqueued packet)
+    cancelled: Vec<(u32, u32), MAX_CANCELLED>,
+}
+
+impl<M: RawMutex> PortShared<M> {
+    const fn new() -> Self {
+        Self {
+            inner: BlockingMutex::new(RefCell::new(PortSharedInner {
+                enqueued: 0,
+                consumed: 0,
+                detached: false,
+                discard: 0,
+                peeked: false,
+                consumed_wakers: MultiWakerRegistration::new(),
+                now: 0,
+                timestamps: false,
+                latency: DequeueLatency {
+                    count: 0,
+                    total: 0,
+                    max: 0,
+                },
+                last_msg: 0,
+                cancelled: Vec::new(),
+            })),
+        }
+    }
+
+    /// Records an enqueued packet, returning its sequence number.
+    ///
+    /// Must be called with the port's `packets` lock held.
+    fn enqueued(&self) -> u32 {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.enqueued = s.enqueued.wrapping_add(1);
+            s.enqueued
+        })
+    }
+
+    /// Records a consumed packet.
+    ///
+    /// `stamp` is the packet's enqueue time, to record latency.
+    fn consumed(&self, stamp: Option<u64>) {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.consumed = s.consumed.wrapping_add(1);
+            s.consumed_wakers.wake();
+            // wrapping comparison, keep while consumed < last
+            let consumed = s.consumed;
+            s.cancelled
+                .retain(|(_m, last)| (consumed.wrapping_sub(*last) as i32) < 0);
+            if let Some(stamp) = stamp {
+                let elapsed = s.now.saturating_sub(stamp);
+                s.latency.record(elapsed);
+            }
+        })
+    }
+
+    /// Returns an id for a new locally sent message.
+    fn next_msg(&self) -> u32 {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.last_msg = s.last_msg.wrapping_add(1).max(1);
+            s.last_msg
+        })
+    }
+
+    /// Requests the `PortBottom` to skip the queued packets of message
+    /// `msg`, the last of which has sequence number `last`.
+    ///
+    /// Returns false if too many messages are already cancelled.
+    fn cancel(&self, msg: u32, last: u32) -> bool {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            // wrapping comparison, consumed >= last
+            if (s.consumed.wrapping_sub(last) as i32) >= 0 {
+                // Nothing left queued
+                return true;
+            }
+            s.cancelled.push((msg, last)).is_ok()
+        })
+    }
+
+    /// Returns true if packets of message `msg` should be skipped.
+    fn is_cancelled(&self, msg: u32) -> bool {
+        msg != 0
+            && self.inner.lock(|s| {
+                s.borrow().cancelled.iter().any(|(m, _last)| *m == msg)
+            })
+    }
+
+    /// Returns the time to stamp an enqueued packet, or `None`
+    /// if latency isn't recorded.
+    fn stamp(&self) -> Option<u64> {
+        self.inner.lock(|s| {
+            let s = s.borrow();
+            s.timestamps.then_some(s.now)
+        })
+    }
+
+    fn set_now(&self, now: u64) {
+        self.inner.lock(|s| s.borrow_mut().now = now)
+    }
+
+    fn set_timestamps(&self, enable: bool) {
+        self.inner.lock(|s| s.borrow_mut().timestamps = enable)
+    }
+
+    fn latency(&self) -> DequeueLatency {
+        self.inner.lock(|s| s.borrow().latency)
+    }
+
+    /// Returns the sequence number of the last consumed packet.
+    fn consumed_count(&self) -> u32 {
+        self.inner.lock(|s| s.borrow().consumed)
+    }
+
+    /// Returns true if packet `seq` has been consumed, otherwise
+    /// registers `waker`.
+    fn poll_consumed(&self, seq: u32, waker: &Waker) -> bool {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            // wrapping comparison, consumed >= seq
+            if (s.consumed.wrapping_sub(seq) as i32) >= 0 {
+                true
+            } else {
+                s.consumed_wakers.register(waker);
+                false
+            }
+        })
+    }
+
+    fn wake(&self) {
+        self.inner.lock(|s| s.borrow_mut().consumed_wakers.wake())
+    }
+
+    /// Requests the `PortBottom` to discard the oldest queued packet.
+    fn discard_oldest(&self) {
+        self.inner.lock(|s| s.borrow_mut().discard += 1)
+    }
+
+    /// Returns the number of queued packets that may be discarded,
+    /// out of `len`.
+    ///
+    /// Excludes packets already requested to be discarded,
+    /// and a packet returned by the `PortBottom`.
+    fn discardable(&self, len: usize) -> usize {
+        self.inner.lock(|s| {
+            let s = s.borrow();
+            len.saturating_sub(s.discard + s.peeked as usize)
+        })
+    }
+
+    fn set_peeked(&self, peeked: bool) {
+        self.inner.lock(|s| s.borrow_mut().peeked = peeked)
+    }
+
+    fn peeked(&self) -> bool {
+        self.inner.lock(|s| s.borrow().peeked)
+    }
+
+    /// Returns and clears the discard requests.
+    fn take_discard(&self) -> usize {
+        self.inner
+            .lock(|s| core::mem::take(&mut s.borrow_mut().discard))
+    }
+
+    fn detach(&self) {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.detached = true;
+            s.consumed_wakers.wake();
+        })
+    }
+
+    fn is_detached(&self) -> bool {
+        self.inner.lock(|s| s.borrow().detached)
+    }
+
+    /// Returns true if the `PortBottom` has been dropped, otherwise
+    /// registers `waker`.
+    fn poll_detached(&self, waker: &Waker) -> bool {
+        self.inner.lock(|s| {
+            let mut s = s.borrow_mut();
+            if !s.detached {
+                s.consumed_wakers.register(waker);
+            }
+            s.detached
+        })
+    }
+}
+
 /// The "producer" side of a queue of packets to send out a MCTP port/interface.
 ///
 /// It will be used by `Routing` to enqueue packets to a port.
-pub struct PortTop<'a> {
+pub struct PortTop<'a, M: RawMutex = CriticalSectionRawMutex> {
     /// Forwarded packet queue.
     /// The outer mutex will not be held over an await.
-    packets: AsyncMutex<Sender<'a, PortRawMutex, PktBuf>>,
+    packets: AsyncMutex<M, Sender<'a, M, PktBuf>>,
 
     /// Temporary storage to flatten vectorised local sent messages
     // prior to fragmentation and queueing.
-    message: AsyncMutex<Vec<u8, MAX_PAYLOAD>>,
+    message: AsyncMutex<M, &'a mut [u8]>,
+
+    shared: &'a PortShared<M>,
+
+    /// Set by `PortBuilder::set_user_data()`
+    user_data: u32,
+
+    /// Capacity of the `packets` queue
+    queue_capacity: usize,
+
+    /// Length of the `message` scratch buffer
+    scratch_len: usize,
+
+    /// Accessed with `PortTop::settings()`
+    settings: BlockingMutex<M, PortSettings>,
+
+    /// Accessed with `PortTop::state()`
+    state: BlockingMutex<M, PortState>,
+}
 
+/// Port configuration set through the `Router`.
+struct PortSettings {
+    /// May be changed by `Router::set_port_mtu()`
     mtu: usize,
+
+    /// Destination EIDs allowed for forwarded packets,
+    /// set by `Router::set_port_eid_range()`
+    eid_range: Option<RangeInclusive<Eid>>,
+
+    /// Set by `Router::set_port_enabled()`
+    enabled: bool,
+
+    /// Set by `Router::set_port_overflow()`
+    overflow: OverflowPolicy,
+
+    /// Hold `packets` for all fragments of a message,
+    /// set by `Router::set_port_atomic_messages()`
+    atomic_messages: bool,
+
+    /// Keep a queue slot for control messages,
+    /// set by `Router::set_control_lane()`
+    control_lane: bool,
+}
+
+/// Port counters and forwarding state.
+struct PortState {
+    /// Unfinished control messages being forwarded, as
+    /// (source, dest, tag)
+    control_flows: Vec<(Eid, Eid, Tag), MAX_CONTROL_FLOWS>,
+
+    /// Sizes of enqueued packets
+    packet_sizes: PacketSizes,
+
+    /// Reported by `Router::note_link_error()`
+    link_errors: LinkErrorStats,
+
+    /// Traffic counters, returned by `Router::port_stats()`
+    stats: PortStats,
 }
 
-impl PortTop<'_> {
+impl<'a, M: RawMutex> PortTop<'a, M> {
+    fn settings<T>(&self, f: impl FnOnce(&mut PortSettings) -> T) -> T {
+        self.settings.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    fn state<T>(&self, f: impl FnOnce(&mut PortState) -> T) -> T {
+        self.state.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    fn mtu(&self) -> usize {
+        self.settings(|s| s.mtu)
+    }
+
+    /// Records an enqueued packet.
+    fn record_size(&self, len: usize) {
+        self.state(|s| {
+            s.packet_sizes.record(len);
+            s.stats.tx_packets = s.stats.tx_packets.wrapping_add(1);
+            s.stats.tx_bytes = s.stats.tx_bytes.wrapping_add(len as u64);
+        });
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut PortStats)) {
+        self.state(|s| f(&mut s.stats))
+    }
+
+    fn enabled(&self) -> bool {
+        self.settings(|s| s.enabled)
+    }
+
+    /// Returns false if `eid` is outside a range set for the port.
+    fn allows_eid(&self, eid: Eid) -> bool {
+        self.settings(|s| s.eid_range.as_ref().is_none_or(|r| r.contains(&eid)))
+    }
+
     /// Enqueues a packet.
     ///
     /// Do not call with locks held.
     /// May block waiting for a port queue to flush.
-    /// Packet must be a valid MCTP packet, may panic otherwise.
-    async fn forward_packet(&self, pkt: &[u8]) -> Result<()> {
-        debug_assert!(Reassembler::header(pkt).is_ok());
+    /// Returns `Error::InvalidInput` if the packet has an invalid header.
+    ///
+    /// Returns the port's sequence number for the enqueued packet.
+    async fn forward_packet(&self, pkt: &[u8]) -> Result<u32> {
+        Reassembler::header(pkt)?;
+
+        if self.shared.is_detached() {
+            debug!("Forward to detached port");
+            return Err(Error::TxFailure);
+        }
 
+        let control = self.is_control_packet(pkt);
         let mut sender = self.packets.lock().await;
-        // Note: must not await while holding `sender`
+        // Note: must not await while holding `sender`.
 
         // Check space first (can't rollback after try_send)
-        if pkt.len() > self.mtu {
+        if pkt.len() > self.mtu() {
             debug!("Forward packet too large");
+            self.update_stats(|s| {
+                s.dropped_too_large = s.dropped_too_large.wrapping_add(1)
+            });
             return Err(Error::NoSpace);
         }
 
+        if self.reserve_last(control) && sender.len() + 1 >= self.queue_capacity
+        {
+            debug!("Dropped forward packet, slot reserved for control");
+            self.update_stats(|s| {
+                s.dropped_queue_full = s.dropped_queue_full.wrapping_add(1)
+            });
+            return Err(Error::TxFailure);
+        }
+
+        let policy = self.settings(|s| s.overflow);
+        if policy == OverflowPolicy::DropOldest
+            && !sender.is_full()
+            && sender.len() + 1 >= self.queue_capacity
+            && self.shared.discardable(sender.len()) > 0
+        {
+            // Take the last free slot. The PortBottom discards the
+            // oldest packet when it next retrieves a packet, freeing
+            // a slot for the next forward.
+            debug!("Dropping oldest packet");
+            self.shared.discard_oldest();
+            self.update_stats(|s| {
+                s.dropped_queue_full = s.dropped_queue_full.wrapping_add(1)
+            });
+        }
+
         // Get a slot to send
         let slot = sender.try_send().ok_or_else(|| {
             debug!("Dropped forward packet");
+            self.update_stats(|s| {
+                s.dropped_queue_full = s.dropped_queue_full.wrapping_add(1)
+            });
             Error::TxFailure
         })?;
 
         // Fill the buffer
-        // OK unwrap: pkt.len() checked above.
+        // OK unwrap: pkt.len() and header checked above.
         slot.set(pkt).unwrap();
+        slot.stamp = self.shared.stamp();
+        slot.sent_waker = None;
+        slot.msg = 0;
         sender.send_done();
-        Ok(())
+        self.record_size(pkt.len());
+        Ok(self.shared.enqueued())
     }
 
     /// Fragments and enqueues a message.
@@ -152,6 +867,8 @@ impl PortTop<'_> {
         &self,
         fragmenter: &mut Fragmenter,
         pkt: &[&[u8]],
+        hooks: Option<&dyn RouterHooks>,
+        sent: Option<&Waker>,
     ) -> Result<Tag> {
         trace!("send_message");
         let mut msg;
@@ -160,27 +877,140 @@ impl PortTop<'_> {
             pkt[0]
         } else {
             msg = self.message.lock().await;
-            msg.clear();
+            let mut len = 0;
             for p in pkt {
-                msg.extend_from_slice(p).map_err(|_| {
+                let dst = msg.get_mut(len..len + p.len()).ok_or_else(|| {
                     debug!("Message too large");
                     Error::NoSpace
                 })?;
+                dst.copy_from_slice(p);
+                len += p.len();
             }
-            &msg
+            &msg[..len]
+        };
+
+        if let Some(hooks) = hooks {
+            hooks.on_message(fragmenter.dest(), fragmenter.typ(), payload);
+        }
+
+        self.send_fragments(fragmenter, hooks, sent, |f, out| {
+            f.fragment(payload, out)
+        })
+        .await
+    }
+
+    /// Enqueues packets for a message with a payload producer.
+    ///
+    /// See [`Fragmenter::fragment_with()`].
+    async fn send_message_with<F>(
+        &self,
+        fragmenter: &mut Fragmenter,
+        total_len: usize,
+        hooks: Option<&dyn RouterHooks>,
+        sent: Option<&Waker>,
+        mut producer: F,
+    ) -> Result<Tag>
+    where
+        F: FnMut(usize, &mut [u8]) -> usize,
+    {
+        trace!("send_message_with");
+        self.send_fragments(fragmenter, hooks, sent, |f, out| {
+            f.fragment_with(total_len, &mut producer, out)
+        })
+        .await
+    }
+
+    /// Enqueues packets for a message.
+    ///
+    /// `sent` is woken when the last packet is consumed.
+    async fn send_fragments<F>(
+        &self,
+        fragmenter: &mut Fragmenter,
+        hooks: Option<&dyn RouterHooks>,
+        sent: Option<&Waker>,
+        mut fragment: F,
+    ) -> Result<Tag>
+    where
+        F: for<'f> FnMut(&mut Fragmenter, &'f mut [u8]) -> SendOutput<'f>,
+    {
+        // In atomic mode the lock is held for the whole message,
+        // otherwise it is taken for each packet.
+        let atomic = self.settings(|s| s.atomic_messages);
+        let control = fragmenter.typ() == mctp::MCTP_TYPE_CONTROL;
+        let reserve_last = self.reserve_last(control);
+        let mut held = if atomic {
+            Some(self.lock_sender(reserve_last).await)
+        } else {
+            None
         };
 
+        let msg = self.shared.next_msg();
+        // Sequence number of the most recent packet enqueued
+        let mut last = None;
+        let mut count = 0usize;
         loop {
-            let mut sender = self.packets.lock().await;
+            if SEND_YIELD_INTERVAL > 0
+                && count > 0
+                && count % SEND_YIELD_INTERVAL == 0
+            {
+                // Let other tasks make progress during a large send
+                embassy_futures::yield_now().await;
+            }
+            count += 1;
 
-            let qpkt = sender.send().await;
+            let mut local = None;
+            let sender = match held.as_mut() {
+                Some(s) => s,
+                None => local.insert(self.lock_sender(reserve_last).await),
+            };
+
+            // Fail rather than waiting forever if the PortBottom is detached
+            let detached = poll_fn(|cx| {
+                if self.shared.poll_detached(cx.waker()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            });
+            let qpkt = match select(sender.send(), detached).await {
+                Either::First(qpkt) => qpkt,
+                Either::Second(()) => {
+                    debug!("Send to detached port");
+                    break Err(Error::TxFailure);
+                }
+            };
             qpkt.len = 0;
             qpkt.dest = fragmenter.dest();
-            let r = fragmenter.fragment(payload, &mut qpkt.data);
+            qpkt.stamp = self.shared.stamp();
+            qpkt.sent_waker = None;
+            qpkt.msg = msg;
+            let r = fragment(fragmenter, &mut qpkt.data);
             match r {
                 SendOutput::Packet(p) => {
-                    qpkt.len = p.len();
+                    if let Some(hooks) = hooks {
+                        hooks.filter_outbound(p);
+                        if Reassembler::header(p).is_err() {
+                            debug!("Filtered packet is invalid");
+                            // Slot is left unsent. Earlier packets of
+                            // the message are skipped by the PortBottom.
+                            if let Some(last) = last {
+                                if !self.shared.cancel(msg, last) {
+                                    debug!("Partial message left queued");
+                                }
+                            }
+                            break Err(Error::InvalidInput);
+                        }
+                    }
+                    let len = p.len();
+                    qpkt.len = len;
+                    if fragmenter.is_done() {
+                        qpkt.sent_waker = sent.cloned();
+                    }
                     sender.send_done();
+                    self.record_size(len);
+                    last = Some(self.shared.enqueued());
+                    drop(local);
+                    send_progress(hooks, fragmenter);
                     if fragmenter.is_done() {
                         break Ok(fragmenter.tag());
                     }
@@ -188,6 +1018,7 @@ impl PortTop<'_> {
                 SendOutput::Error { err, .. } => {
                     debug!("Error packetising");
                     sender.send_done();
+                    self.shared.enqueued();
                     break Err(err);
                 }
                 SendOutput::Complete { .. } => unreachable!(),
@@ -199,12 +1030,18 @@ impl PortTop<'_> {
 /// The "consumer" side of a queue of packets to send out a MCTP interface,
 ///
 /// This is used by the interface implementation.
-pub struct PortBottom<'a> {
+///
+/// If the interface stops consuming packets it should call
+/// [`detach()`](Self::detach), so that sends to the port fail
+/// rather than waiting for the queue to drain.
+pub struct PortBottom<'a, M: RawMutex = CriticalSectionRawMutex> {
     /// packet queue
-    packets: Receiver<'a, PortRawMutex, PktBuf>,
+    packets: Receiver<'a, M, PktBuf>,
+
+    shared: &'a PortShared<M>,
 }
 
-impl PortBottom<'_> {
+impl<M: RawMutex> PortBottom<'_, M> {
     /// Retrieve an outbound packet to send for this port.
     ///
     /// Should call [`outbound_done()`](Self::outbound_done) to consume the
@@ -215,8 +1052,16 @@ impl PortBottom<'_> {
         if self.packets.len() > 1 {
             trace!("packets avail {}", self.packets.len());
         }
-        let pkt = self.packets.receive().await;
-        (pkt, pkt.dest)
+        poll_fn(|cx| {
+            self.discard_oldest();
+            self.skip_cancelled();
+            self.packets.poll_receive(cx).map(|_| ())
+        })
+        .await;
+        // OK unwrap: poll_receive() was ready
+        let pkt = self.packets.try_receive().unwrap();
+        self.shared.set_peeked(true);
+        (pkt, pkt.dest)
     }
 
     /// Attempt to retrieve an outbound packet.
@@ -229,118 +1074,577 @@ impl PortBottom<'_> {
     /// `try_outbound()` may be called multiple times to peek at the same packet.
     pub fn try_outbound(&mut self) -> Option<(&[u8], Eid)> {
         trace!("packets avail {} try", self.packets.len());
-        self.packets.try_receive().map(|pkt| (&**pkt, pkt.dest))
+        self.discard_oldest();
+        self.skip_cancelled();
+        let pkt = self.packets.try_receive()?;
+        self.shared.set_peeked(true);
+        Some((&**pkt, pkt.dest))
     }
 
     /// Consume the outbound packet and advance the queue.
     pub fn outbound_done(&mut self) {
-        self.packets.receive_done()
+        let (stamp, sent) = self
+            .packets
+            .try_receive()
+            .map_or((None, None), |p| (p.stamp, p.sent_waker.take()));
+        self.packets.receive_done();
+        self.shared.consumed(stamp);
+        self.shared.set_peeked(false);
+        if let Some(sent) = sent {
+            sent.wake();
+        }
+        self.discard_oldest();
+        self.skip_cancelled();
+    }
+
+    /// Discards the oldest packets as requested by
+    /// `OverflowPolicy::DropOldest` forwards.
+    ///
+    /// A packet that has already been returned is never discarded.
+    fn discard_oldest(&mut self) {
+        if self.shared.peeked() {
+            return;
+        }
+        for _ in 0..self.shared.take_discard() {
+            // A discarded packet was not transmitted, don't wake
+            let Some(p) = self.packets.try_receive() else {
+                break;
+            };
+            debug!("Dropped oldest packet");
+            p.sent_waker = None;
+            self.packets.receive_done();
+            // Discarded packets aren't counted in latency
+            self.shared.consumed(None);
+        }
+    }
+
+    /// Skips queued packets of a partial message, after a send failed
+    /// part way through.
+    ///
+    /// As for `discard_oldest()`, a packet that has already been
+    /// returned is never skipped.
+    fn skip_cancelled(&mut self) {
+        if self.shared.peeked() {
+            return;
+        }
+        while let Some(p) = self.packets.try_receive() {
+            if !self.shared.is_cancelled(p.msg) {
+                break;
+            }
+            debug!("Dropped packet of failed send");
+            p.sent_waker = None;
+            self.packets.receive_done();
+            self.shared.consumed(None);
+        }
+    }
+
+    /// Detach the consumer from the port.
+    ///
+    /// Subsequent sends and forwards to the port (including those
+    /// currently waiting for queue space) will fail with
+    /// [`Error::TxFailure`].
+    ///
+    /// This isn't performed on drop, since a `Drop` implementation
+    /// would require a `PortBottom` to be dropped before its `Router`.
+    pub fn detach(self) {
+        self.shared.detach();
     }
 }
 
 /// Storage for a Port, being a physical MCTP interface.
-// TODO: instead of storing Vec<u8, N>, it could
-// store `&'r []` and a length field, which would allow different ports
-// have different MAX_MESSAGE/MAX_MTU. Does add another lifetime parameter.
-pub struct PortStorage<const FORWARD_QUEUE: usize = 4> {
+///
+/// `SCRATCH` is the size of a buffer used to flatten messages sent with
+/// multiple slices (`send_vectored()`) prior to fragmentation.
+/// It defaults to `MAX_PAYLOAD`. A smaller `SCRATCH` saves memory on
+/// devices with many ports, but vectored sends with a total payload
+/// larger than `SCRATCH` will fail with [`Error::NoSpace`].
+/// Sends of a single slice don't use the scratch buffer.
+// TODO: PktBuf could also store `&'r []` and a length field, which
+// would allow different ports have different MAX_MTU.
+pub struct PortStorage<
+    const FORWARD_QUEUE: usize = 4,
+    const SCRATCH: usize = MAX_PAYLOAD,
+> {
     /// forwarded packet queue
     packets: [PktBuf; FORWARD_QUEUE],
+
+    /// vectored send scratch buffer
+    message: [u8; SCRATCH],
 }
 
-impl<const FORWARD_QUEUE: usize> PortStorage<FORWARD_QUEUE> {
-    pub fn new() -> Self {
+impl<const FORWARD_QUEUE: usize, const SCRATCH: usize>
+    PortStorage<FORWARD_QUEUE, SCRATCH>
+{
+    /// Create a new `PortStorage`.
+    ///
+    /// This is a `const fn`, so storage can be placed in a `static`
+    /// (for example with `static_cell::ConstStaticCell`). The zeroed
+    /// buffers are then part of `.bss`, cleared by the startup code
+    /// rather than filled at runtime.
+    pub const fn new() -> Self {
         Self {
             packets: [const { PktBuf::new() }; FORWARD_QUEUE],
+            message: [0u8; SCRATCH],
+        }
+    }
+
+    /// Writes and reads back all buffers, to confirm the memory is usable.
+    ///
+    /// Must be called prior to [`PortBuilder::new()`]. Once the `Router`
+    /// is running, [`Router::pre_warm()`] checks other buffers.
+    ///
+    /// Returns `Error::Other` if a buffer doesn't read back correctly.
+    pub fn pre_warm(&mut self) -> Result<()> {
+        for p in self.packets.iter_mut() {
+            crate::util::check_memory(&mut p.data)
+                .inspect_err(|_| error!("Port memory check failed"))?;
         }
+        crate::util::check_memory(&mut self.message)
+            .inspect_err(|_| error!("Port memory check failed"))
     }
 }
 
-impl<const FORWARD_QUEUE: usize> Default for PortStorage<FORWARD_QUEUE> {
+impl<const FORWARD_QUEUE: usize, const SCRATCH: usize> Default
+    for PortStorage<FORWARD_QUEUE, SCRATCH>
+{
     fn default() -> Self {
         Self::new()
     }
 }
 
-pub struct PortBuilder<'a> {
+pub struct PortBuilder<'a, M: RawMutex = CriticalSectionRawMutex> {
     /// forwarded packet queue
-    packets: Channel<'a, PortRawMutex, PktBuf>,
+    packets: Channel<'a, M, PktBuf>,
+
+    /// vectored send scratch buffer
+    message: &'a mut [u8],
+
+    shared: PortShared<M>,
+
+    user_data: u32,
+
+    /// Capacity of `packets`
+    queue_capacity: usize,
 }
 
 impl<'a> PortBuilder<'a> {
-    pub fn new<const FORWARD_QUEUE: usize>(
-        storage: &'a mut PortStorage<FORWARD_QUEUE>,
+    pub fn new<const FORWARD_QUEUE: usize, const SCRATCH: usize>(
+        storage: &'a mut PortStorage<FORWARD_QUEUE, SCRATCH>,
+    ) -> Self {
+        Self::new_with_mutex(storage)
+    }
+}
+
+impl<'a, M: RawMutex> PortBuilder<'a, M> {
+    /// Create a `PortBuilder` with a non-default mutex.
+    ///
+    /// This is the same as [`new()`](PortBuilder::new), with the mutex
+    /// taken from the `PortBuilder` type. For example
+    /// `let pb: PortBuilder<'_, NoopRawMutex> = PortBuilder::new_with_mutex(&mut storage);`
+    ///
+    /// The `Router` using the port has the same mutex type.
+    pub fn new_with_mutex<const FORWARD_QUEUE: usize, const SCRATCH: usize>(
+        storage: &'a mut PortStorage<FORWARD_QUEUE, SCRATCH>,
     ) -> Self {
         // PortBuilder and PortStorage need to be separate structs, since
         // zerocopy_channel::Channel takes a slice.
         Self {
             packets: Channel::new(storage.packets.as_mut_slice()),
+            message: storage.message.as_mut_slice(),
+            shared: PortShared::new(),
+            user_data: 0,
+            queue_capacity: FORWARD_QUEUE,
         }
     }
 
-    pub fn build(&mut self, mtu: usize) -> Result<(PortTop, PortBottom)> {
+    /// Set opaque application data for the port.
+    ///
+    /// This is passed to the `PortTop` on the next [`build()`](Self::build),
+    /// and can be retrieved with [`Router::port_user_data()`], for example
+    /// to find transport context from a `PortId`. Defaults to 0.
+    pub fn set_user_data(&mut self, user_data: u32) {
+        self.user_data = user_data;
+    }
+
+    pub fn build(
+        &mut self,
+        mtu: usize,
+    ) -> Result<(PortTop<'_, M>, PortBottom<'_, M>)> {
         if mtu > MAX_MTU {
             debug!("port mtu {} > MAX_MTU {}", mtu, MAX_MTU);
             return Err(Error::BadArgument);
         }
 
         let (ps, pr) = self.packets.split();
+        let scratch_len = self.message.len();
 
         let t = PortTop {
-            message: AsyncMutex::new(Vec::new()),
+            message: AsyncMutex::new(&mut *self.message),
             packets: AsyncMutex::new(ps),
-            mtu,
+            shared: &self.shared,
+            user_data: self.user_data,
+            queue_capacity: self.queue_capacity,
+            scratch_len,
+            settings: BlockingMutex::new(RefCell::new(PortSettings {
+                mtu,
+                eid_range: None,
+                enabled: true,
+                overflow: OverflowPolicy::default(),
+                atomic_messages: false,
+                control_lane: false,
+            })),
+            state: BlockingMutex::new(RefCell::new(PortState {
+                control_flows: Vec::new(),
+                packet_sizes: PacketSizes::default(),
+                link_errors: LinkErrorStats::default(),
+                stats: PortStats::default(),
+            })),
+        };
+        let b = PortBottom {
+            packets: pr,
+            shared: &self.shared,
         };
-        let b = PortBottom { packets: pr };
         Ok((t, b))
     }
 }
 
-pub struct Router<'r> {
-    inner: AsyncMutex<RouterInner<'r>>,
-    ports: &'r [PortTop<'r>],
+/// An MCTP router, with a local [`Stack`] and ports.
+///
+/// `M` is the mutex used by the `Router` and its ports,
+/// [`CriticalSectionRawMutex`] by default. Single-core cooperative
+/// executors can use [`NoopRawMutex`](embassy_sync::blocking_mutex::raw::NoopRawMutex)
+/// to avoid critical sections, the `Router` is then not `Sync`.
+/// Ports are created with [`PortBuilder::new_with_mutex()`].
+///
+/// `R` is the stack's [`ReassemblyPool`].
+///
+/// `LISTENERS` limits the listeners bound at once, including types
+/// registered with [`prebind()`](Self::prebind).
+/// Each slot uses roughly 40 bytes on 32-bit targets (56 bytes on 64-bit).
+///
+/// `RECEIVERS` limits the request and response channels waiting in
+/// `recv()` at once, beyond that they fail with `Error::NoSpace`.
+/// The same limit applies to tasks waiting on time updates or routes.
+/// Each slot uses roughly 36 bytes on 32-bit targets (72 bytes on 64-bit),
+/// for the three waiting lists.
+pub struct Router<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    inner: AsyncMutex<M, RouterInner<'r, R, LISTENERS, RECEIVERS>>,
+    ports: &'r [PortTop<'r, M>],
 
     /// Listeners for different message types.
     // Has a separate non-async Mutex so it can be used by RouterAsyncListener::drop()
     // TODO filter by more than just MsgType, maybe have a Map of some sort?
-    app_listeners:
-        BlockingMutex<[Option<(MsgType, WakerRegistration)>; MAX_LISTENERS]>,
+    app_listeners: BlockingMutex<M, [Option<ListenerBind>; LISTENERS]>,
+
+    /// Free buffers for `RouterAsyncListener::recv_pooled()`,
+    /// set by `Router::set_recv_pool()`.
+    recv_pool: BlockingMutex<M, RecvPoolSlots<'r>>,
+
+    /// Accessed with `Router::state()`
+    state: BlockingMutex<M, RouterState<'r, RECEIVERS>>,
+}
+
+/// `Router` state for use without the `inner` lock.
+struct RouterState<'r, const RECEIVERS: usize> {
+    hooks: Option<&'r dyn RouterHooks>,
+
+    /// Time from the most recent `update_time()`
+    now: u64,
+    /// Receivers waiting on a deadline, woken by `update_time()`
+    time_wakers: ReceiveWakers<RECEIVERS>,
+
+    /// Tasks in `await_route()`, woken by `reconfigure()`
+    route_wakers: RouteWakers<RECEIVERS>,
+
+    /// The local stack's EID, updated with the stack's EID.
+    eid: Eid,
+
+    stats: RouterStats,
+
+    /// Handles of dropped `RouterRecvGuard`s, finished by `lock_inner()`
+    released_handles: Vec<ReceiveHandle, { crate::NUM_RECEIVE }>,
+
+    /// Count of internal invariant failures
+    #[cfg(feature = "checked-invariants")]
+    invariant_errors: u32,
+}
+
+/// A comparable snapshot of a [`Router`]'s state, for tests.
+///
+/// Created by [`Router::snapshot()`]. Entries are sorted.
+#[cfg(any(test, feature = "test-util"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterSnapshot<const LISTENERS: usize = 20> {
+    /// Allocated owned tags, as `(peer, tag)`
+    pub flows: Vec<(Eid, TagValue), FLOWS>,
+    /// Reassembly contexts, as `(peer, tag, complete)`
+    pub reassembly: Vec<(Eid, Tag, bool), { crate::NUM_RECEIVE }>,
+    /// Message types of bound listeners
+    pub listeners: Vec<MsgType, LISTENERS>,
+}
+
+struct ListenerBind {
+    typ: MsgType,
+    waker: WakerRegistration,
+    /// Also receives unowned messages without a matching flow
+    unowned: bool,
+    /// Created by `Router::prebind()`, kept when the listener is dropped
+    prebound: bool,
+    /// A `RouterAsyncListener` exists
+    attached: bool,
+    /// Set by `RouterAsyncListener::set_ic_policy()`
+    ic_policy: IcPolicy,
+    /// Receives any type without a specific listener, for `recv_any()`.
+    /// `typ` is unused.
+    catch_all: bool,
+    stats: ListenerStats,
+    /// Also receives broadcast messages,
+    /// set by `RouterAsyncListener::set_broadcast()`
+    broadcast: bool,
+    /// Maximum waiting messages, set by `Router::listener_buffered()`
+    depth: Option<usize>,
+}
+
+/// A `Stack` owned by a `Router`, or borrowed with `Router::new_borrowed()`.
+// Owned is the usual case, and can't be boxed without alloc.
+#[allow(clippy::large_enum_variant)]
+enum RouterStack<'r, R> {
+    Owned(Stack<R>),
+    Borrowed(&'r mut Stack<R>),
+}
+
+impl<R> core::ops::Deref for RouterStack<'_, R> {
+    type Target = Stack<R>;
+
+    fn deref(&self) -> &Stack<R> {
+        match self {
+            Self::Owned(s) => s,
+            Self::Borrowed(s) => s,
+        }
+    }
+}
+
+impl<R> core::ops::DerefMut for RouterStack<'_, R> {
+    fn deref_mut(&mut self) -> &mut Stack<R> {
+        match self {
+            Self::Owned(s) => s,
+            Self::Borrowed(s) => s,
+        }
+    }
 }
 
-pub struct RouterInner<'r> {
+pub struct RouterInner<
+    'r,
+    R: ReassemblyPool,
+    const LISTENERS: usize,
+    const RECEIVERS: usize,
+> {
     /// Core MCTP stack
-    stack: Stack,
+    stack: RouterStack<'r, R>,
 
     // Wakers for RouterAsyncReqChannel and RouterAsyncRespChannel
-    app_receive_wakers: MultiWakerRegistration<MAX_RECEIVERS>,
+    app_receive_wakers: ReceiveWakers<RECEIVERS>,
 
     lookup: &'r mut dyn PortLookup,
+
+    /// MTU used for sends to the NULL EID, limited by the port MTU.
+    baseline_mtu: usize,
+
+    /// Limit of complete messages waiting for listeners
+    deferred_limit: Option<usize>,
+    /// Count of messages dropped due to `deferred_limit`
+    deferred_dropped: u32,
+
+    null_source: NullSourcePolicy,
+
+    /// Listener receive priorities, set by `Router::set_type_priority()`
+    type_priority: Vec<(MsgType, u8), LISTENERS>,
+
+    /// Send MTUs for peers, set by `Router::set_peer_mtu()`
+    peer_mtu: Vec<(Eid, usize), MAX_PEER_MTUS>,
+
+    /// Send MTUs for message types, set by `Router::set_type_mtu()`
+    type_mtu: Vec<(MsgType, usize), MAX_TYPE_MTUS>,
+
+    /// Set by `Router::set_unroutable_response()`
+    unroutable_response: bool,
+
+    /// Set by `Router::set_control_lane()`
+    control_lane: bool,
+
+    /// Set by `Router::set_drop_self_source()`
+    drop_self_source: bool,
+
+    /// Set by `Router::set_routing_only()`
+    routing_only: bool,
+
+    /// Additional local EIDs, set by `Router::add_local_eid()`
+    local_eids: Vec<Eid, MAX_LOCAL_EIDS>,
+
+    /// Set by `Router::set_max_forwards()`
+    max_forwards: Option<u8>,
+    /// Recently forwarded packets, most recent last
+    forward_seen: Vec<ForwardSeen, FORWARD_LOOP_WINDOW>,
+}
+
+impl<R: ReassemblyPool, const LISTENERS: usize, const RECEIVERS: usize>
+    RouterInner<'_, R, LISTENERS, RECEIVERS>
+{
+    /// Returns true if a send to `eid` is delivered locally.
+    ///
+    /// The NULL and broadcast EIDs are never local for sends, even
+    /// while the stack's own EID is unassigned.
+    fn is_local_send(&mut self, eid: Eid) -> bool {
+        if eid == mctp::MCTP_ADDR_NULL || eid == mctp::MCTP_ADDR_ANY {
+            return false;
+        }
+        eid == self.stack.eid()
+            || self.local_eids.contains(&eid)
+            || self.lookup.deliver_local(eid)
+    }
+
+    fn set_baseline_mtu(&mut self, mtu: usize) -> Result<()> {
+        check_mtu(mtu)?;
+        self.baseline_mtu = mtu;
+        Ok(())
+    }
+
+    /// Returns the number of complete messages waiting for a listener.
+    fn waiting_for(&self, cookie: AppCookie) -> usize {
+        self.stack
+            .reassemblers
+            .iter()
+            .flatten()
+            .filter(|(re, _len)| {
+                re.is_done() && !re.handle_taken() && re.cookie == Some(cookie)
+            })
+            .count()
+    }
+
+    /// Retrieves a message for a listener, highest priority type first.
+    fn get_deferred_listener(
+        &mut self,
+        cookie: AppCookie,
+    ) -> Option<ReceiveHandle> {
+        let prio = &self.type_priority;
+        let control_lane = self.control_lane;
+        self.stack.get_deferred_bycookie_priority(&[cookie], |typ| {
+            if control_lane && typ == mctp::MCTP_TYPE_CONTROL {
+                return u8::MAX;
+            }
+            prio.iter()
+                .find(|(t, _p)| *t == typ)
+                .map_or(0, |(_t, p)| *p)
+        })
+    }
 }
 
-impl<'r> Router<'r> {
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Router<'r, M, R, LISTENERS, RECEIVERS>
+{
     /// Create a new Router.
     ///
     /// The EID of the provided `stack` is used to match local destination packets.
     ///
     /// `ports` is a list of transport interfaces for the router. The indices
     /// of the `ports`  slice are used as `PortId` identifiers.
+    /// `ports` may be empty, in which case only messages to the stack's own
+    /// EID (loopback) can be sent.
     ///
     /// `lookup` callbacks define the routing table for outbound packets.
+    ///
+    /// The `Router` uses the reassembly pool of the `stack`, see
+    /// [`Stack::new_with_pool()`].
+    ///
+    /// The mutex and sizes are taken from the `Router` type, which
+    /// usually needs to be given. For example
+    /// `let router: Router = Router::new(stack, &ports, &mut lookup);`
+    /// for the defaults, or `Router<'_, NoopRawMutex, _, 8, 16>`.
     pub fn new(
-        stack: Stack,
-        ports: &'r [PortTop<'r>],
+        stack: Stack<R>,
+        ports: &'r [PortTop<'r, M>],
+        lookup: &'r mut dyn PortLookup,
+    ) -> Self {
+        Self::new_inner(RouterStack::Owned(stack), ports, lookup)
+    }
+
+    /// Create a new Router using a borrowed `Stack`.
+    ///
+    /// This is the same as [`new()`](Self::new), but the `stack` is
+    /// owned elsewhere, for example alongside other components that
+    /// inspect it. The `stack` is exclusively borrowed for the lifetime
+    /// of the `Router`, and is only used behind the `Router`'s internal
+    /// lock. Once the `Router` is dropped the `stack` can be used again,
+    /// with any state (flows, reassembly, EID) left by the `Router`.
+    ///
+    /// The `Router` enables [`Stack::set_track_released()`] on the stack.
+    pub fn new_borrowed(
+        stack: &'r mut Stack<R>,
+        ports: &'r [PortTop<'r, M>],
+        lookup: &'r mut dyn PortLookup,
+    ) -> Self {
+        Self::new_inner(RouterStack::Borrowed(stack), ports, lookup)
+    }
+
+    fn new_inner(
+        mut stack: RouterStack<'r, R>,
+        ports: &'r [PortTop<'r, M>],
         lookup: &'r mut dyn PortLookup,
     ) -> Self {
+        let eid = stack.eid();
+        // Released tags wake receivers and are passed to hooks
+        stack.set_track_released(true);
         let inner = RouterInner {
             stack,
-            app_receive_wakers: MultiWakerRegistration::new(),
+            app_receive_wakers: ReceiveWakers::new(),
             lookup,
+            baseline_mtu: HEADER_LEN + mctp::MCTP_MIN_MTU,
+            deferred_limit: None,
+            deferred_dropped: 0,
+            null_source: NullSourcePolicy::default(),
+            type_priority: Vec::new(),
+            peer_mtu: Vec::new(),
+            type_mtu: Vec::new(),
+            unroutable_response: false,
+            control_lane: false,
+            drop_self_source: false,
+            routing_only: false,
+            local_eids: Vec::new(),
+            max_forwards: None,
+            forward_seen: Vec::new(),
         };
 
         Self {
             inner: AsyncMutex::new(inner),
             app_listeners: BlockingMutex::new(RefCell::new(
-                [const { None }; MAX_LISTENERS],
+                [const { None }; LISTENERS],
             )),
+            recv_pool: BlockingMutex::new(RefCell::new(RecvPoolSlots::new())),
+            state: BlockingMutex::new(RefCell::new(RouterState {
+                hooks: None,
+                now: 0,
+                time_wakers: ReceiveWakers::new(),
+                route_wakers: RouteWakers {
+                    changes: 0,
+                    wakers: ReceiveWakers::new(),
+                },
+                eid,
+                stats: RouterStats::default(),
+                released_handles: Vec::new(),
+                #[cfg(feature = "checked-invariants")]
+                invariant_errors: 0,
+            })),
             ports,
         }
     }
@@ -350,139 +1654,794 @@ impl<'r> Router<'r> {
     /// A suitable interval (milliseconds) for the next call to `update_time()` will
     /// be returned, currently a maximum of 100 ms.
     pub async fn update_time(&self, now_millis: u64) -> Result<u64> {
-        let mut inner = self.inner.lock().await;
-        let (next, expired) = inner.stack.update(now_millis)?;
-        if expired {
-            // Wake pending sockets in case one was waiting on a now-expired response.
-            // TODO something more efficient, maybe Reassembler should hold a waker?
-            inner.app_receive_wakers.wake();
+        self.state(|s| {
+            s.now = now_millis;
+            s.time_wakers.wake();
+        });
+
+        let mut inner = self.lock_inner().await;
+        let (next, _expired) = inner.stack.update(now_millis)?;
+        // Wake receivers waiting on expired flows, once for all expiries
+        let released = inner.stack.take_released();
+        inner.app_receive_wakers.wake_flows(&released);
+        drop(inner);
+        self.notify_released(released);
+
+        // Confirmed forwards may have timed out
+        for p in self.ports {
+            p.shared.set_now(now_millis);
+            p.shared.wake();
         }
         Ok(next)
     }
 
+    /// Returns the interval until `update_time()` should next be called.
+    ///
+    /// This is the interval (milliseconds) most recently returned by
+    /// [`update_time()`](Self::update_time), and doesn't advance
+    /// the clock. Returns 0 if `update_time()` is due.
+    pub async fn suggested_interval(&self) -> u64 {
+        let inner = self.lock_inner().await;
+        inner.stack.suggested_interval()
+    }
+
+    /// Writes a readable report of the router's state to `out`.
+    ///
+    /// This covers the local EID, ports, counters, listeners, owned tag
+    /// flows and reassembly slots. It is intended for diagnostic output,
+    /// the format may change.
+    ///
+    /// `out` may be a `heapless::String`, in which case an error
+    /// is returned if it is too small for the report.
+    pub async fn dump(
+        &self,
+        out: &mut dyn core::fmt::Write,
+    ) -> core::fmt::Result {
+        let inner = self.lock_inner().await;
+        let stack = &inner.stack;
+        writeln!(out, "EID {}", stack.eid())?;
+
+        for (i, top) in self.ports.iter().enumerate() {
+            write!(out, "port {} mtu {}", i, top.mtu())?;
+            match top.packets.try_lock() {
+                Ok(sender) => write!(
+                    out,
+                    " queue {}/{}",
+                    sender.len(),
+                    top.queue_capacity
+                )?,
+                Err(_) => write!(out, " queue busy/{}", top.queue_capacity)?,
+            }
+            let sizes = top.state(|s| s.packet_sizes);
+            write!(out, " max packet {}", sizes.max)?;
+            if top.shared.is_detached() {
+                write!(out, " detached")?;
+            }
+            writeln!(out)?;
+        }
+
+        let stats = self.stats();
+        writeln!(
+            out,
+            "forwarded {} delivered {} dropped detached {} receivers peak {}",
+            stats.forwarded_total,
+            stats.local_delivered_total,
+            stats.forward_dropped_detached,
+            stats.receivers_peak,
+        )?;
+
+        self.app_listeners.lock(|a| {
+            for (i, b) in a.borrow().iter().enumerate() {
+                let Some(b) = b else { continue };
+                if b.catch_all {
+                    write!(out, "listener {} any", i)?;
+                } else {
+                    write!(out, "listener {} type {}", i, b.typ)?;
+                }
+                write!(
+                    out,
+                    " received {} too small {}",
+                    b.stats.received, b.stats.too_small
+                )?;
+                if b.prebound && !b.attached {
+                    write!(out, " prebound")?;
+                }
+                if b.unowned {
+                    write!(out, " unowned")?;
+                }
+                if b.broadcast {
+                    write!(out, " broadcast")?;
+                }
+                writeln!(out)?;
+            }
+            Ok(())
+        })?;
+
+        for ((eid, tv), flow) in stack.flows.iter() {
+            write!(out, "flow eid {} tag {}", eid, tv.0)?;
+            if flow.expiry_stamp.is_none() {
+                write!(out, " noexpire")?;
+            }
+            writeln!(out)?;
+        }
+
+        let mut used = 0;
+        for (re, len) in stack.reassemblers.iter().flatten() {
+            let state = if re.is_done() { "done" } else { "active" };
+            writeln!(
+                out,
+                "reassembly eid {} tag {} {} len {}",
+                re.peer, re.tag, state, len
+            )?;
+            used += 1;
+        }
+        writeln!(out, "reassembly {}/{} used", used, crate::NUM_RECEIVE)
+    }
+
+    /// Returns the message types with a complete message waiting for a listener.
+    ///
+    /// A dispatcher can use this to poll only listeners that are ready.
+    /// Each type is listed once. Messages that were already taken by
+    /// a `recv()` in progress are not included.
+    pub async fn pending_types(&self) -> Vec<MsgType, LISTENERS> {
+        let inner = self.lock_inner().await;
+        self.app_listeners.lock(|a| {
+            let a = a.borrow();
+            let mut types = Vec::new();
+            let waiting = inner
+                .stack
+                .reassemblers
+                .iter()
+                .flatten()
+                .filter(|(re, _len)| re.is_done() && !re.handle_taken());
+            for (re, _len) in waiting {
+                let bound = re
+                    .cookie
+                    .is_some_and(|c| a.get(c.0).is_some_and(|b| b.is_some()));
+                let Some(typ) = re.done_type() else {
+                    continue;
+                };
+                if bound && !types.contains(&typ) {
+                    // OK to ignore: a full list has every listener's type,
+                    // apart from catch-all listeners.
+                    let _ = types.push(typ);
+                }
+            }
+            types
+        })
+    }
+
+    /// Returns a snapshot of the router's state.
+    ///
+    /// This is intended for test assertions, comparing snapshots
+    /// before and after an operation.
+    #[cfg(any(test, feature = "test-util"))]
+    pub async fn snapshot(&self) -> RouterSnapshot<LISTENERS> {
+        let inner = self.lock_inner().await;
+        let stack = &inner.stack;
+
+        // collect() won't overflow, capacities match the sources
+        let mut flows: Vec<_, FLOWS> = stack.flows.keys().copied().collect();
+        flows.sort_unstable();
+
+        let mut reassembly: Vec<_, { crate::NUM_RECEIVE }> = stack
+            .reassemblers
+            .iter()
+            .flatten()
+            .map(|(re, _len)| (re.peer, re.tag, re.is_done()))
+            .collect();
+        reassembly.sort_unstable();
+
+        let mut listeners: Vec<_, LISTENERS> = self.app_listeners.lock(|a| {
+            a.borrow()
+                .iter()
+                .flatten()
+                .filter(|b| !b.catch_all)
+                .map(|b| b.typ)
+                .collect()
+        });
+        listeners.sort_unstable();
+
+        RouterSnapshot {
+            flows,
+            reassembly,
+            listeners,
+        }
+    }
+
     /// Provide an incoming packet to the router.
     ///
     /// Returns the packet's MCTP source EID for any valid packet,
     /// regardless of whether the packet is handled, forwarded, or dropped.
     pub async fn inbound(&self, pkt: &[u8], port: PortId) -> Option<Eid> {
-        let mut inner = self.inner.lock().await;
+        let header = crate::peek_header(pkt).ok()?;
+        self.inbound_parsed(pkt, &header, port).await
+    }
 
-        let Ok(header) = Reassembler::header(pkt) else {
-            return None;
+    /// Provide an incoming packet with an already parsed header.
+    ///
+    /// This is the same as [`inbound()`](Self::inbound), for transport
+    /// bindings that have already called [`peek_header()`](crate::peek_header)
+    /// on `pkt`. The router uses `header` for routing rather than parsing
+    /// the packet again.
+    ///
+    /// `header` must be the header of `pkt`. This is checked in debug builds.
+    pub async fn inbound_parsed(
+        &self,
+        pkt: &[u8],
+        header: &PacketHeader,
+        port: PortId,
+    ) -> Option<Eid> {
+        debug_assert_eq!(crate::peek_header(pkt).ok().as_ref(), Some(header));
+        self.inbound_inner(pkt, header, port).await.0
+    }
+
+    /// Provide an incoming packet, with confirmation of forwarding.
+    ///
+    /// This is the same as [`inbound()`](Self::inbound). When the packet
+    /// is forwarded to a port a [`ForwardConfirm`] is also returned.
+    /// [`ForwardConfirm::wait()`] completes once the destination port's
+    /// [`PortBottom`] has consumed the packet with
+    /// [`outbound_done()`](PortBottom::outbound_done), allowing
+    /// the ingress transport to apply flow control.
+    ///
+    /// The confirmation doesn't need to be awaited before providing
+    /// further packets. A transport that waits for each confirmation
+    /// before receiving its next packet will stall all flows on the
+    /// ingress link behind a slow destination port.
+    pub async fn inbound_confirmed(
+        &self,
+        pkt: &[u8],
+        port: PortId,
+    ) -> (
+        Option<Eid>,
+        Option<ForwardConfirm<'_, 'r, M, R, LISTENERS, RECEIVERS>>,
+    ) {
+        let Ok(header) = crate::peek_header(pkt) else {
+            return (None, None);
         };
+        self.inbound_inner(pkt, &header, port).await
+    }
+
+    async fn inbound_inner(
+        &self,
+        pkt: &[u8],
+        header: &PacketHeader,
+        port: PortId,
+    ) -> (
+        Option<Eid>,
+        Option<ForwardConfirm<'_, 'r, M, R, LISTENERS, RECEIVERS>>,
+    ) {
+        let mut inner = self.lock_inner().await;
+
         // Source EID is returned even if packet routing fails
-        let ret_src = Some(Eid(header.source_endpoint_id()));
+        let ret_src = Some(header.source);
+        let dest_eid = header.dest;
 
-        // Handle locally if possible
-        if inner.stack.is_local_dest(pkt) {
-            match inner.stack.receive(pkt) {
-                // Complete message
-                Ok(Some((msg, handle))) => {
-                    let typ = msg.typ;
-                    let tag = msg.tag;
-                    drop(inner);
-                    self.incoming_local(tag, typ, handle).await;
-                    return ret_src;
-                }
-                // Fragment consumed, message is incomplete
-                Ok(None) => {
-                    return ret_src;
+        let in_port = self.ports.get(port.0 as usize);
+        if let Some(in_port) = in_port {
+            in_port
+                .update_stats(|s| s.rx_packets = s.rx_packets.wrapping_add(1));
+        }
+
+        if ret_src == Some(mctp::MCTP_ADDR_NULL) {
+            match inner.null_source {
+                NullSourcePolicy::Accept => (),
+                NullSourcePolicy::Log => {
+                    warn!("Packet from null EID, dest {}", dest_eid.0)
                 }
-                Err(e) => {
-                    debug!("Dropped local recv packet. {}", e);
-                    return ret_src;
+                NullSourcePolicy::Drop => {
+                    debug!("Dropped packet from null EID");
+                    return (ret_src, None);
                 }
             }
         }
 
-        // Look for a route to forward to
-        let dest_eid = Eid(header.dest_endpoint_id());
+        // Copy to a mirror port, prior to normal handling
+        if let Some(m) = inner.lookup.mirror(header, port) {
+            drop(inner);
+            self.mirror_packet(pkt, m).await;
+            inner = self.lock_inner().await;
+        }
+
+        // Handle locally if possible
+        let local_eid = if inner.routing_only {
+            None
+        } else if inner.stack.is_local_dest(pkt) {
+            Some(inner.stack.eid())
+        } else if inner.local_eids.contains(&dest_eid) {
+            Some(dest_eid)
+        } else if inner.lookup.deliver_local(dest_eid) {
+            trace!("claimed local EID {}", dest_eid.0);
+            Some(dest_eid)
+        } else {
+            None
+        };
+
+        if let Some(local_eid) = local_eid {
+            self.receive_local(inner, pkt, local_eid).await;
+            return (ret_src, None);
+        }
+
+        // Broadcasts are received locally as well as forwarded
+        if dest_eid == mctp::MCTP_ADDR_ANY
+            && !inner.routing_only
+            && self.broadcast_listener()
+        {
+            self.receive_local(inner, pkt, dest_eid).await;
+            inner = self.lock_inner().await;
+        }
 
+        // A packet claiming our own source is a loop or spoofed
+        let own_eid = inner.stack.eid();
+        if inner.drop_self_source
+            && own_eid != mctp::MCTP_ADDR_NULL
+            && ret_src == Some(own_eid)
+        {
+            warn!("Dropped forward packet with local source EID");
+            self.update_stats(|s| {
+                s.forward_dropped_self_source =
+                    s.forward_dropped_self_source.wrapping_add(1)
+            });
+            return (ret_src, None);
+        }
+
+        // Look for a route to forward to
         let Some(p) = inner.lookup.by_eid(dest_eid, Some(port)) else {
             debug!("No route for recv {}", dest_eid);
-            return ret_src;
+            if let Some(in_port) = in_port {
+                in_port.update_stats(|s| {
+                    s.dropped_noroute = s.dropped_noroute.wrapping_add(1)
+                });
+            }
+            if inner.unroutable_response {
+                self.send_unroutable_response(inner, pkt).await;
+            } else {
+                drop(inner);
+            }
+            if let Some(hooks) = self.hooks() {
+                hooks.on_unroutable(header, port);
+            }
+            return (ret_src, None);
         };
-        drop(inner);
 
         let Some(top) = self.ports.get(p.0 as usize) else {
             debug!("Bad port ID from lookup");
-            return ret_src;
+            return (ret_src, None);
+        };
+
+        // Checked with the lock held, consistent with the lookup
+        // for reconfigure()
+        if !top.enabled() {
+            debug!("Dropped forward to disabled port {}", p.0);
+            self.update_stats(|s| {
+                s.forward_dropped_disabled =
+                    s.forward_dropped_disabled.wrapping_add(1)
+            });
+            return (ret_src, None);
+        }
+        if !top.allows_eid(dest_eid) {
+            warn!(
+                "Dropped forward to EID {} outside port {} range",
+                dest_eid.0, p.0
+            );
+            self.update_stats(|s| {
+                s.forward_dropped_range =
+                    s.forward_dropped_range.wrapping_add(1)
+            });
+            return (ret_src, None);
+        }
+
+        let now = self.state(|s| s.now);
+        if inner.forward_loop(pkt, now) {
+            warn!("Dropped looping forward packet to EID {}", dest_eid.0);
+            self.update_stats(|s| {
+                s.forward_dropped_loop = s.forward_dropped_loop.wrapping_add(1)
+            });
+            if let Some(in_port) = in_port {
+                in_port.update_stats(|s| {
+                    s.dropped_loop = s.dropped_loop.wrapping_add(1)
+                });
+            }
+            return (ret_src, None);
+        }
+        drop(inner);
+
+        // Drop without waiting on the port's queue
+        if top.shared.is_detached() {
+            debug!("Dropped forward to detached port {}", p.0);
+            self.update_stats(|s| {
+                s.forward_dropped_detached =
+                    s.forward_dropped_detached.wrapping_add(1)
+            });
+            return (ret_src, None);
+        }
+
+        let Ok(seq) = top.forward_packet(pkt).await else {
+            return (ret_src, None);
         };
+        self.update_stats(|s| {
+            s.forwarded_total = s.forwarded_total.wrapping_add(1)
+        });
+        top.update_stats(|s| s.forwarded = s.forwarded.wrapping_add(1));
 
-        let _ = top.forward_packet(pkt).await;
-        ret_src
+        let confirm = ForwardConfirm {
+            router: self,
+            top,
+            seq,
+            enqueued: self.state(|s| s.now),
+        };
+        (ret_src, Some(confirm))
     }
 
-    async fn incoming_local(
+    /// Enqueues a copy of an incoming packet to a mirror port.
+    ///
+    /// See `PortLookup::mirror()`.
+    async fn mirror_packet(&self, pkt: &[u8], port: PortId) {
+        let r = match self.ports.get(port.0 as usize) {
+            Some(top) => top.forward_packet(pkt).await,
+            None => {
+                debug!("Bad mirror port ID from lookup");
+                Err(Error::BadArgument)
+            }
+        };
+        match r {
+            Ok(_) => {
+                self.update_stats(|s| s.mirrored = s.mirrored.wrapping_add(1))
+            }
+            Err(e) => {
+                debug!("Dropped mirror packet. {}", e);
+                self.update_stats(|s| {
+                    s.mirror_dropped = s.mirror_dropped.wrapping_add(1)
+                })
+            }
+        }
+    }
+
+    /// Responds to an unroutable MCTP Control request.
+    ///
+    /// See `set_unroutable_response()`.
+    async fn send_unroutable_response(
         &self,
-        tag: Tag,
-        typ: MsgType,
-        handle: ReceiveHandle,
+        mut inner: InnerGuard<'_, 'r, M, R, LISTENERS, RECEIVERS>,
+        pkt: &[u8],
     ) {
-        trace!("incoming local, type {}", typ.0);
-        if tag.is_owner() {
-            self.incoming_listener(typ, handle).await
-        } else {
-            self.incoming_response(tag, handle).await
+        let Some(resp) = control_error_response(pkt, inner.stack.eid()) else {
+            return;
+        };
+        // OK unwrap: response is well formed
+        let requester = crate::peek_header(&resp).unwrap().dest;
+        let Some(p) = inner.lookup.by_eid(requester, None) else {
+            debug!("No route for unroutable response {}", requester);
+            return;
+        };
+        drop(inner);
+
+        let Some(top) = self.ports.get(p.0 as usize) else {
+            debug!("Bad port ID from lookup");
+            return;
+        };
+        match top.forward_packet(&resp).await {
+            Ok(_) => trace!("Sent unroutable response to {}", requester),
+            Err(e) => debug!("Unroutable response failed {}", e),
         }
     }
 
-    async fn incoming_listener(&self, typ: MsgType, handle: ReceiveHandle) {
-        let mut inner = self.inner.lock().await;
-        let mut handle = Some(handle);
+    /// Receives a packet for a local EID.
+    async fn receive_local(
+        &self,
+        mut inner: InnerGuard<'_, 'r, M, R, LISTENERS, RECEIVERS>,
+        pkt: &[u8],
+        local_eid: Eid,
+    ) {
+        if inner.stack.is_orphan_fragment(pkt) {
+            debug!("Dropped local fragment without SOM");
+            self.update_stats(|s| {
+                s.local_dropped_no_som = s.local_dropped_no_som.wrapping_add(1)
+            });
+            return;
+        }
 
-        // wake the packet listener
-        self.app_listeners.lock(|a| {
-            let mut a = a.borrow_mut();
-            // Find the matching listener
-            for (cookie, entry) in a.iter_mut().enumerate() {
-                if let Some((t, waker)) = entry {
-                    trace!("entry. {} vs {}", t.0, typ.0);
-                    if *t == typ {
-                        // OK unwrap: only set once
-                        let handle = handle.take().unwrap();
-                        inner
-                            .stack
-                            .set_cookie(&handle, Some(AppCookie(cookie)));
-                        inner.stack.return_handle(handle);
-                        waker.wake();
-                        trace!("listener match");
-                        break;
-                    }
-                }
+        let cookie = self.unowned_listener(pkt);
+        let r = inner.stack.receive_inner(pkt, local_eid, cookie);
+        match r {
+            // Complete message
+            Ok(Some((msg, handle))) => {
+                let typ = msg.typ;
+                let tag = msg.tag;
+                let cookie = msg.cookie;
+                self.update_stats(|s| {
+                    s.local_delivered_total =
+                        s.local_delivered_total.wrapping_add(1)
+                });
+                // A complete response releases its flow
+                let released = inner.stack.take_released();
+                drop(inner);
+                self.notify_released(released);
+                self.incoming_local(tag, typ, cookie, handle).await;
+            }
+            // Fragment consumed, message is incomplete
+            Ok(None) => (),
+            Err(e) => {
+                debug!("Dropped local recv packet. {}", e);
             }
-        });
-
-        if let Some(handle) = handle.take() {
-            trace!("listener no match");
-            inner.stack.finished_receive(handle);
         }
     }
 
-    async fn incoming_response(&self, _tag: Tag, handle: ReceiveHandle) {
-        let mut inner = self.inner.lock().await;
-        inner.stack.return_handle(handle);
+    /// Delivers fragments of a message sent to the stack's own EID,
+    /// an EID added by `Router::add_local_eid()`,
+    /// or an EID claimed by `PortLookup::deliver_local()`.
+    async fn send_loopback<F>(
+        &self,
+        fragmenter: &mut Fragmenter,
+        mut fragment: F,
+    ) -> Result<Tag>
+    where
+        F: for<'f> FnMut(&mut Fragmenter, &'f mut [u8]) -> SendOutput<'f>,
+    {
+        let mut buf = [0u8; MAX_MTU];
+        loop {
+            match fragment(fragmenter, &mut buf) {
+                SendOutput::Packet(p) => {
+                    let p: &[u8] = p;
+                    let inner = self.lock_inner().await;
+                    self.receive_local(inner, p, fragmenter.dest()).await;
+                    send_progress(self.hooks(), fragmenter);
+                    if fragmenter.is_done() {
+                        break Ok(fragmenter.tag());
+                    }
+                }
+                SendOutput::Error { err, .. } => {
+                    debug!("Error packetising");
+                    break Err(err);
+                }
+                SendOutput::Complete { .. } => unreachable!(),
+            }
+        }
+    }
+
+    /// Returns true if any listener accepts broadcast messages.
+    fn broadcast_listener(&self) -> bool {
+        self.app_listeners
+            .lock(|a| a.borrow().iter().flatten().any(|b| b.broadcast))
+    }
+
+    /// Returns the cookie of a listener that accepts the packet as
+    /// an unowned message without a flow.
+    ///
+    /// Only applies to the first packet of a message.
+    fn unowned_listener(&self, pkt: &[u8]) -> Option<AppCookie> {
+        let hdr = crate::peek_header(pkt).ok()?;
+        if hdr.tag.is_owner() || !hdr.som {
+            return None;
+        }
+        let (typ, _ic) = mctp::decode_type_ic(*pkt.get(HEADER_LEN)?);
+
+        self.app_listeners
+            .lock(|a| {
+                a.borrow().iter().position(|bind| {
+                    bind.as_ref().is_some_and(|b| {
+                        b.unowned && !b.catch_all && b.typ == typ
+                    })
+                })
+            })
+            .map(AppCookie)
+    }
+
+    async fn incoming_local(
+        &self,
+        tag: Tag,
+        typ: MsgType,
+        cookie: Option<AppCookie>,
+        handle: ReceiveHandle,
+    ) {
+        trace!("incoming local, type {}", typ.0);
+        if tag.is_owner() {
+            self.incoming_listener(typ, handle).await
+        } else if let Some(cookie) = cookie {
+            // Router request channels don't set a cookie, so this
+            // is from receive_unowned()
+            self.incoming_unowned(cookie, handle).await
+        } else {
+            self.incoming_response(tag, handle).await
+        }
+    }
+
+    /// Returns true if a message for a listener should be dropped
+    /// due to the deferred limit.
+    ///
+    /// Drops the message and increments the counter.
+    fn deferred_full(
+        inner: &mut RouterInner<'_, R, LISTENERS, RECEIVERS>,
+        handle: ReceiveHandle,
+    ) -> Option<ReceiveHandle> {
+        let Some(limit) = inner.deferred_limit else {
+            return Some(handle);
+        };
+
+        // Complete listener messages that are waiting for recv()
+        let waiting = inner
+            .stack
+            .reassemblers
+            .iter()
+            .flatten()
+            .filter(|(re, _len)| {
+                re.is_done() && !re.handle_taken() && re.cookie.is_some()
+            })
+            .count();
+
+        if waiting < limit {
+            return Some(handle);
+        }
+
+        debug!("Dropped local message, deferred limit {}", limit);
+        inner.deferred_dropped = inner.deferred_dropped.wrapping_add(1);
+        inner.stack.finished_receive(handle);
+        None
+    }
+
+    async fn incoming_listener(&self, typ: MsgType, handle: ReceiveHandle) {
+        let mut inner = self.lock_inner().await;
+        let Some(handle) = Self::deferred_full(&mut inner, handle) else {
+            return;
+        };
+        let msg = inner.stack.fetch_message(&handle);
+        let ic = msg.ic;
+        let source = msg.source;
+        let broadcast = msg.dest == mctp::MCTP_ADDR_ANY;
+        let mut handle = Some(handle);
+
+        // wake the packet listener, returns false if there is none
+        let bound = self.app_listeners.lock(|a| {
+            let mut a = a.borrow_mut();
+            // Find the matching listener, falling back to a catch-all.
+            // Broadcasts only go to listeners that accept them.
+            let cookie = a
+                .iter()
+                .position(|b| {
+                    b.as_ref().is_some_and(|b| {
+                        !b.catch_all
+                            && b.typ == typ
+                            && (b.broadcast || !broadcast)
+                    })
+                })
+                .or_else(|| {
+                    a.iter().position(|b| {
+                        b.as_ref().is_some_and(|b| b.catch_all && !broadcast)
+                    })
+                });
+            let Some(cookie) = cookie else {
+                return false;
+            };
+            // OK unwrap: found above
+            let bind = a[cookie].as_mut().unwrap();
+            if !bind.ic_policy.allows(ic) {
+                debug!("Dropped listener message, IC {}", ic);
+                return true;
+            }
+            if let Some(depth) = bind.depth {
+                if inner.waiting_for(AppCookie(cookie)) >= depth {
+                    debug!("Dropped listener message, queue depth {}", depth);
+                    bind.stats.queue_dropped =
+                        bind.stats.queue_dropped.wrapping_add(1);
+                    return true;
+                }
+            }
+            // OK unwrap: only set once
+            let handle = handle.take().unwrap();
+            inner.stack.set_cookie(&handle, Some(AppCookie(cookie)));
+            inner.stack.return_handle(handle);
+            bind.waker.wake();
+            trace!("listener match, catch-all {}", bind.catch_all);
+            true
+        });
+
+        if let Some(handle) = handle.take() {
+            trace!("listener no match");
+            inner.stack.finished_receive(handle);
+        }
+        drop(inner);
+
+        if !bound {
+            debug!("No listener for type {}", typ.0);
+            self.update_stats(|s| {
+                s.local_no_listener = s.local_no_listener.wrapping_add(1)
+            });
+            if let Some(hooks) = self.hooks() {
+                hooks.on_no_listener(source, typ);
+            }
+        }
+    }
+
+    async fn incoming_unowned(&self, cookie: AppCookie, handle: ReceiveHandle) {
+        let mut inner = self.lock_inner().await;
+        let Some(handle) = Self::deferred_full(&mut inner, handle) else {
+            return;
+        };
+
+        let msg = inner.stack.fetch_message(&handle);
+        let ic = msg.ic;
+        let broadcast = msg.dest == mctp::MCTP_ADDR_ANY;
+        let found = self.app_listeners.lock(|a| {
+            let mut a = a.borrow_mut();
+            match a.get_mut(cookie.0) {
+                Some(Some(bind)) if bind.unowned => {
+                    if broadcast && !bind.broadcast {
+                        trace!("Listener doesn't accept broadcast");
+                        return false;
+                    }
+                    if !bind.ic_policy.allows(ic) {
+                        debug!("Dropped listener message, IC {}", ic);
+                        return false;
+                    }
+                    bind.waker.wake();
+                    true
+                }
+                _ => false,
+            }
+        });
+
+        if found {
+            inner.stack.return_handle(handle);
+        } else {
+            // Listener was dropped during reassembly, or IC mismatch
+            trace!("unowned listener gone");
+            inner.stack.finished_receive(handle);
+        }
+    }
+
+    async fn incoming_response(&self, _tag: Tag, handle: ReceiveHandle) {
+        let mut inner = self.lock_inner().await;
+        inner.stack.return_handle(handle);
         // TODO: inefficient waking them all. should
         // probably wake only the useful one.
         inner.app_receive_wakers.wake();
     }
 
-    fn app_bind(&self, typ: MsgType) -> Result<AppCookie> {
+    /// Binds a listener.
+    ///
+    /// With `prebind` set, the bind is created unattached (see `prebind()`).
+    fn app_bind(
+        &self,
+        typ: MsgType,
+        unowned: bool,
+        prebind: bool,
+    ) -> Result<AppCookie> {
         self.app_listeners.lock(|a| {
             let mut a = a.borrow_mut();
 
             // Check for existing binds with the same type
-            for bind in a.iter() {
-                if bind.as_ref().is_some_and(|(t, _)| *t == typ) {
-                    return Err(Error::AddrInUse);
+            for (i, bind) in a.iter_mut().enumerate() {
+                let Some(b) =
+                    bind.as_mut().filter(|b| !b.catch_all && b.typ == typ)
+                else {
+                    continue;
+                };
+                if b.prebound && !b.attached && !prebind {
+                    // Attach to a prebind
+                    b.attached = true;
+                    b.unowned = unowned;
+                    return Ok(AppCookie(i));
                 }
+                return Err(Error::AddrInUse);
             }
 
             // Find a free slot
             if let Some((i, bind)) =
                 a.iter_mut().enumerate().find(|(_i, bind)| bind.is_none())
             {
-                *bind = Some((typ, WakerRegistration::new()));
+                *bind = Some(ListenerBind {
+                    typ,
+                    waker: WakerRegistration::new(),
+                    unowned,
+                    prebound: prebind,
+                    attached: !prebind,
+                    ic_policy: IcPolicy::default(),
+                    catch_all: false,
+                    stats: ListenerStats::default(),
+                    broadcast: false,
+                    depth: None,
+                });
                 return Ok(AppCookie(i));
             }
 
@@ -490,13 +2449,79 @@ impl<'r> Router<'r> {
         })
     }
 
+    /// Returns the catch-all bind used by `recv_any()`, creating it if needed.
+    fn app_bind_any(&self) -> Result<AppCookie> {
+        self.app_listeners.lock(|a| {
+            let mut a = a.borrow_mut();
+
+            if let Some(i) = a
+                .iter()
+                .position(|b| b.as_ref().is_some_and(|b| b.catch_all))
+            {
+                return Ok(AppCookie(i));
+            }
+
+            let (i, bind) = a
+                .iter_mut()
+                .enumerate()
+                .find(|(_i, bind)| bind.is_none())
+                .ok_or(Error::NoSpace)?;
+            *bind = Some(ListenerBind {
+                typ: MsgType(0),
+                waker: WakerRegistration::new(),
+                unowned: false,
+                prebound: true,
+                attached: false,
+                ic_policy: IcPolicy::default(),
+                catch_all: true,
+                stats: ListenerStats::default(),
+                broadcast: false,
+                depth: None,
+            });
+            Ok(AppCookie(i))
+        })
+    }
+
+    /// Pre-registers a listener bind for a message type.
+    ///
+    /// Incoming messages with the TO bit set for `typ` will be held
+    /// until a listener is created with [`listener()`](Self::listener)
+    /// (or [`listener_with_unowned()`](Self::listener_with_unowned)),
+    /// which attaches to the existing bind. This avoids dropping
+    /// messages that arrive before an application task has created its
+    /// listener, so should be called before any packets are provided
+    /// with [`inbound()`](Self::inbound).
+    ///
+    /// Held messages are discarded after a timeout, the same as messages
+    /// waiting for an existing listener's `recv()`.
+    ///
+    /// When the attached listener is dropped the bind remains, and a
+    /// new listener may attach.
+    ///
+    /// Returns [`Error::AddrInUse`] if `typ` is already bound.
+    pub fn prebind(&self, typ: MsgType) -> Result<()> {
+        self.app_bind(typ, false, true)?;
+        Ok(())
+    }
+
     fn app_unbind(&self, cookie: AppCookie) -> Result<()> {
         self.app_listeners.lock(|a| {
             let mut a = a.borrow_mut();
             let bind = a.get_mut(cookie.0).ok_or(Error::BadArgument)?;
 
-            if bind.is_none() {
+            let Some(b) = bind else {
                 return Err(Error::BadArgument);
+            };
+
+            if b.prebound {
+                // Keep holding messages for the next listener
+                b.attached = false;
+                b.unowned = false;
+                b.ic_policy = IcPolicy::default();
+                b.stats = ListenerStats::default();
+                b.broadcast = false;
+                b.depth = None;
+                return Ok(());
             }
 
             // Clear the bind.
@@ -511,15 +2536,249 @@ impl<'r> Router<'r> {
     ///
     /// Listeners will pass the cookie returned from `[app_bind]`.
     /// Other receivers will pass `tag_eid`.
+    ///
+    /// With a `deadline`, fails with `Error::TimedOut` once the
+    /// `update_time()` clock reaches `deadline` milliseconds.
     async fn app_recv_message<'f>(
         &self,
         cookie: Option<AppCookie>,
         tag_eid: Option<(Tag, Eid)>,
         buf: &'f mut [u8],
-    ) -> Result<(&'f mut [u8], Eid, MsgType, Tag, bool)> {
-        // Allow single use inside poll_fn
-        let mut buf = Some(buf);
+        deadline: Option<u64>,
+    ) -> Result<(&'f mut [u8], Eid, Eid, MsgType, Tag, bool)> {
+        let recv = self.app_recv_handle(cookie, tag_eid);
+        let (mut inner, handle) = match deadline {
+            Some(deadline) => {
+                // A message that is already waiting takes precedence
+                match select(recv, self.wait_deadline(deadline)).await {
+                    Either::First(r) => r?,
+                    Either::Second(e) => return Err(e),
+                }
+            }
+            None => recv.await?,
+        };
+
+        // Copy the contents to the caller, and finish with it for the stack.
+        let msg = inner.stack.fetch_message(&handle);
+
+        let res = if msg.payload.len() > buf.len() {
+            trace!("no space");
+            Err(Error::NoSpace)
+        } else {
+            trace!("good len {}", msg.payload.len());
+            let buf = &mut buf[..msg.payload.len()];
+            buf.copy_from_slice(msg.payload);
+            Ok((buf, msg.source, msg.dest, msg.typ, msg.tag, msg.ic))
+        };
+
+        if let Some(cookie) = cookie {
+            self.update_listener_stats(cookie, |s| {
+                if res.is_ok() {
+                    s.received = s.received.wrapping_add(1);
+                } else {
+                    s.too_small = s.too_small.wrapping_add(1);
+                }
+            });
+        }
+
+        inner.stack.finished_receive(handle);
+        res
+    }
+
+    fn update_listener_stats(
+        &self,
+        cookie: AppCookie,
+        f: impl FnOnce(&mut ListenerStats),
+    ) {
+        self.app_listeners.lock(|a| {
+            if let Some(Some(bind)) = a.borrow_mut().get_mut(cookie.0) {
+                f(&mut bind.stats)
+            }
+        })
+    }
+
+    /// Passes all waiting messages for a listener to `f`.
+    ///
+    /// See `RouterAsyncListener::drain()`.
+    async fn app_drain<F>(&self, cookie: AppCookie, mut f: F) -> usize
+    where
+        F: FnMut(MsgType, Eid, &[u8]),
+    {
+        let mut inner = self.lock_inner().await;
+        let mut count = 0;
+        while let Some(handle) = inner.get_deferred_listener(cookie) {
+            let msg = inner.stack.fetch_message(&handle);
+            f(msg.typ, msg.source, msg.payload);
+            inner.stack.finished_receive(handle);
+            count += 1;
+        }
+        drop(inner);
+
+        self.update_listener_stats(cookie, |s| {
+            s.received = s.received.wrapping_add(count as u32)
+        });
+        count
+    }
+
+    /// Receive a listener message and send a reply.
+    ///
+    /// See `RouterAsyncListener::recv_reply()`.
+    async fn app_recv_reply<F>(
+        &self,
+        cookie: AppCookie,
+        buf: &mut [u8],
+        reply: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&MctpMessage, &mut [u8]) -> usize,
+    {
+        let (mut inner, handle) =
+            self.app_recv_handle(Some(cookie), None).await?;
+
+        let msg = inner.stack.fetch_message(&handle);
+        let (eid, typ, tv) = (msg.source, msg.typ, msg.tag.tag());
+        let len = reply(&msg, buf);
+        inner.stack.finished_receive(handle);
+        self.update_listener_stats(cookie, |s| {
+            s.received = s.received.wrapping_add(1)
+        });
+
+        let reply = buf.get(..len).ok_or_else(|| {
+            debug!("Bad reply length {}", len);
+            Error::BadArgument
+        })?;
+
+        // Start the send without releasing the lock
+        let (top, mut fragmenter) = self.start_send_locked(
+            &mut inner,
+            eid,
+            typ,
+            Some(Tag::Unowned(tv)),
+            None,
+            false,
+            None,
+        )?;
+        drop(inner);
+
+        match top {
+            Some(top) => {
+                top.send_message(&mut fragmenter, &[reply], self.hooks(), None)
+                    .await?
+            }
+            None => {
+                self.send_loopback(&mut fragmenter, |f, out| {
+                    f.fragment(reply, out)
+                })
+                .await?
+            }
+        };
+        Ok(())
+    }
+
+    /// Receive multiple listener messages into a buffer.
+    ///
+    /// See `RouterAsyncListener::recv_coalesced()`.
+    async fn app_recv_coalesced<'f>(
+        &self,
+        cookie: AppCookie,
+        buf: &'f mut [u8],
+        max_msgs: usize,
+    ) -> Result<(&'f mut [u8], usize)> {
+        if max_msgs == 0 {
+            return Err(Error::BadArgument);
+        }
+
+        let (mut inner, handle) =
+            self.app_recv_handle(Some(cookie), None).await?;
+
+        let mut len = 0;
+        let mut count = 0;
+        let mut handle = Some(handle);
+        while let Some(h) = handle.take() {
+            let msg = inner.stack.fetch_message(&h);
+            let plen = msg.payload.len();
+            let Some(dst) = buf.get_mut(len..len + 2 + plen) else {
+                if count == 0 {
+                    // As for app_recv_message()
+                    trace!("no space");
+                    self.update_listener_stats(cookie, |s| {
+                        s.too_small = s.too_small.wrapping_add(1)
+                    });
+                    inner.stack.finished_receive(h);
+                    return Err(Error::NoSpace);
+                }
+                // Leave it for the next recv
+                inner.stack.return_handle(h);
+                break;
+            };
+            // OK unwrap: MAX_PAYLOAD is smaller
+            let l = u16::try_from(plen).unwrap();
+            dst[..2].copy_from_slice(&l.to_le_bytes());
+            dst[2..].copy_from_slice(msg.payload);
+            inner.stack.finished_receive(h);
+            len += 2 + plen;
+            count += 1;
+
+            if count < max_msgs {
+                handle = inner.get_deferred_listener(cookie);
+            }
+        }
+
+        trace!("coalesced {} messages", count);
+        self.update_listener_stats(cookie, |s| {
+            s.received = s.received.wrapping_add(count as u32)
+        });
+        Ok((&mut buf[..len], count))
+    }
+
+    /// Receive a message without copying.
+    ///
+    /// Arguments are as for `app_recv_message()`.
+    async fn app_recv_borrowed(
+        &'r self,
+        cookie: Option<AppCookie>,
+        tag_eid: Option<(Tag, Eid)>,
+    ) -> Result<RouterRecvGuard<'r, M, R, LISTENERS, RECEIVERS>> {
+        let (inner, handle) = self.app_recv_handle(cookie, tag_eid).await?;
+        // The handle pins the reassembly slot, the lock isn't needed
+        drop(inner);
+        Ok(RouterRecvGuard {
+            router: self,
+            handle: Some(handle),
+        })
+    }
+
+    /// Waits until the `update_time()` clock reaches `deadline`.
+    ///
+    /// Returns `Error::TimedOut`, or another error if the waker
+    /// can't be registered.
+    async fn wait_deadline(&self, deadline: u64) -> Error {
+        poll_fn(|cx| {
+            if self.state(|s| s.now) >= deadline {
+                trace!("recv deadline");
+                return Poll::Ready(Error::TimedOut);
+            }
+            let r = self.state(|s| s.time_wakers.register(cx.waker()));
+            if let Err(e) = r {
+                warn!("Too many waiting receivers");
+                return Poll::Ready(e);
+            }
+            Poll::Pending
+        })
+        .await
+    }
 
+    /// Waits for a message, returning its handle.
+    ///
+    /// The `inner` lock is returned held.
+    async fn app_recv_handle(
+        &self,
+        cookie: Option<AppCookie>,
+        tag_eid: Option<(Tag, Eid)>,
+    ) -> Result<(
+        InnerGuard<'_, 'r, M, R, LISTENERS, RECEIVERS>,
+        ReceiveHandle,
+    )> {
         poll_fn(|cx| {
             // Lock it inside the poll_fn
             let l = self.inner.lock();
@@ -535,13 +2794,14 @@ impl<'r> Router<'r> {
             // TODO: get_deferred is inefficient lookup, does it matter?
             let handle = match (cookie, tag_eid) {
                 // lookup by cookie for Listener
-                (Some(cookie), None) => {
-                    inner.stack.get_deferred_bycookie(&[cookie])
-                }
+                (Some(cookie), None) => inner.get_deferred_listener(cookie),
                 // lookup by tag/eid for ReqChannel
                 (None, Some((tag, eid))) => inner.stack.get_deferred(eid, tag),
                 // one of them must have been set
-                _ => unreachable!(),
+                _ => {
+                    let e = self.invariant_failed("recv lookup");
+                    return Poll::Ready(Err(e));
+                }
             };
 
             let Some(handle) = handle else {
@@ -557,165 +2817,1626 @@ impl<'r> Router<'r> {
                             debug_assert!(false, "recv bad cookie");
                             return;
                         };
-                        let Some((_typ, waker)) = bind else {
+                        let Some(bind) = bind else {
                             debug_assert!(false, "recv no listener");
                             return;
                         };
-                        waker.register(cx.waker());
+                        bind.waker.register(cx.waker());
                     });
                 } else {
                     // Other receivers.
                     trace!("other recv");
-                    inner.app_receive_wakers.register(cx.waker());
+                    let flow = tag_eid.map(|(tag, eid)| (eid, tag.tag()));
+                    if let Err(e) =
+                        inner.app_receive_wakers.register_flow(cx.waker(), flow)
+                    {
+                        warn!("Too many waiting receivers");
+                        return Poll::Ready(Err(e));
+                    }
+                    let n = inner.app_receive_wakers.len() as u32;
+                    self.update_stats(|s| {
+                        s.receivers_peak = s.receivers_peak.max(n)
+                    });
                 }
                 trace!("pending");
                 return Poll::Pending;
             };
 
-            // A matching message was found. Fetch it, copy the contents to the caller,
-            // and finish with it for the stack.
+            // A matching message was found.
             trace!("got handle");
-
-            let msg = inner.stack.fetch_message(&handle);
-
-            // OK unwrap, set above and only hit once on Poll::Ready
-            let buf = buf.take().unwrap();
-            let res = if msg.payload.len() > buf.len() {
-                trace!("no space");
-                Err(Error::NoSpace)
-            } else {
-                trace!("good len {}", msg.payload.len());
-                let buf = &mut buf[..msg.payload.len()];
-                buf.copy_from_slice(msg.payload);
-                Ok((buf, msg.source, msg.typ, msg.tag, msg.ic))
-            };
-
-            inner.stack.finished_receive(handle);
-            Poll::Ready(res)
+            Poll::Ready(Ok((inner, handle)))
         })
         .await
     }
 
     /// Used by traits to send a message, see comment on .send_vectored() methods
     ///
-    /// TODO should handle loopback if eid matches local stack's
+    /// Messages to the stack's own EID, an EID added by
+    /// `Router::add_local_eid()`, or an EID claimed by
+    /// `PortLookup::deliver_local()`, are delivered locally.
+    /// `source` overrides the stack's EID as the source.
+    /// `sent` is woken once the message has been consumed by the port,
+    /// or after local delivery.
     async fn app_send_message(
         &self,
         eid: Eid,
         typ: MsgType,
         tag: Option<Tag>,
-        tag_expires: bool,
+        tag_lifetime: Option<u32>,
         integrity_check: bool,
         buf: &[&[u8]],
         cookie: Option<AppCookie>,
+        source: Option<Eid>,
+        sent: Option<&Waker>,
     ) -> Result<Tag> {
-        let mut inner = self.inner.lock().await;
-
-        let Some(p) = inner.lookup.by_eid(eid, None) else {
-            debug!("No route for recv {}", eid);
-            return Err(Error::TxFailure);
-        };
-
-        let Some(top) = self.ports.get(p.0 as usize) else {
-            debug!("Bad port ID from lookup");
-            return Err(Error::TxFailure);
-        };
-
-        let mtu = top.mtu;
-        let mut fragmenter = inner
-            .stack
-            .start_send(
+        let (top, mut fragmenter) = self
+            .app_start_send(
                 eid,
                 typ,
                 tag,
-                tag_expires,
+                tag_lifetime,
                 integrity_check,
-                Some(mtu),
                 cookie,
             )
-            .inspect_err(|e| trace!("error fragmenter {}", e))?;
-        // release to allow other ports to continue work
-        drop(inner);
+            .await?;
+        if let Some(source) = source {
+            fragmenter.set_source(source);
+        }
+        match top {
+            Some(top) => {
+                top.send_message(&mut fragmenter, buf, self.hooks(), sent)
+                    .await
+            }
+            None => {
+                let total_len = buf.iter().map(|b| b.len()).sum();
+                let r = self
+                    .send_loopback(&mut fragmenter, |f, out| {
+                        f.fragment_with(
+                            total_len,
+                            |o, d| copy_vectored(buf, o, d),
+                            out,
+                        )
+                    })
+                    .await;
+                if let (Ok(_), Some(sent)) = (&r, sent) {
+                    sent.wake_by_ref();
+                }
+                r
+            }
+        }
+    }
 
-        top.send_message(&mut fragmenter, buf).await
+    /// Sends a message with an exact tag, bypassing request and response
+    /// channels.
+    ///
+    /// This is for advanced use such as protocol conformance testing,
+    /// where messages need tag and TO bit combinations that channels
+    /// won't produce. `Tag::Owned` sets the TO bit, `Tag::Unowned`
+    /// clears it. An owned tag isn't allocated from the stack, so
+    /// it may collide with a tag in use, and responses to it will not be
+    /// received.
+    ///
+    /// Otherwise routing and fragmentation are the same as for other
+    /// sends.
+    pub async fn send_raw(
+        &self,
+        eid: Eid,
+        typ: MsgType,
+        tag: Tag,
+        integrity_check: bool,
+        bufs: &[&[u8]],
+    ) -> Result<()> {
+        let (top, mut fragmenter) = {
+            let mut inner = self.lock_inner().await;
+            let (top, mtu) = self.send_route(&mut inner, eid, typ)?;
+            let fragmenter = inner.stack.start_send_raw(
+                eid,
+                typ,
+                tag,
+                integrity_check,
+                Some(mtu),
+            )?;
+            (top, fragmenter)
+        };
+        match top {
+            Some(top) => {
+                top.send_message(&mut fragmenter, bufs, self.hooks(), None)
+                    .await?
+            }
+            None => {
+                let total_len = bufs.iter().map(|b| b.len()).sum();
+                self.send_loopback(&mut fragmenter, |f, out| {
+                    f.fragment_with(
+                        total_len,
+                        |o, d| copy_vectored(bufs, o, d),
+                        out,
+                    )
+                })
+                .await?
+            }
+        };
+        Ok(())
     }
 
-    /// Only needs to be called for tags allocated with tag_expires=false
+    /// Sends a message with a payload producer.
     ///
-    /// Must only be called for owned tags.
-    async fn app_release_tag(&self, eid: Eid, tag: Tag) {
-        let Tag::Owned(tv) = tag else { unreachable!() };
-        let mut inner = self.inner.lock().await;
+    /// As for `app_send_message()`, see [`Fragmenter::fragment_with()`].
+    async fn app_send_message_with<F>(
+        &self,
+        eid: Eid,
+        typ: MsgType,
+        tag: Option<Tag>,
+        tag_lifetime: Option<u32>,
+        integrity_check: bool,
+        total_len: usize,
+        sent: Option<&Waker>,
+        mut producer: F,
+    ) -> Result<Tag>
+    where
+        F: FnMut(usize, &mut [u8]) -> usize,
+    {
+        let (top, mut fragmenter) = self
+            .app_start_send(eid, typ, tag, tag_lifetime, integrity_check, None)
+            .await?;
+        match top {
+            Some(top) => {
+                top.send_message_with(
+                    &mut fragmenter,
+                    total_len,
+                    self.hooks(),
+                    sent,
+                    producer,
+                )
+                .await
+            }
+            None => {
+                let r = self
+                    .send_loopback(&mut fragmenter, |f, out| {
+                        f.fragment_with(total_len, &mut producer, out)
+                    })
+                    .await;
+                if let (Ok(_), Some(sent)) = (&r, sent) {
+                    sent.wake_by_ref();
+                }
+                r
+            }
+        }
+    }
+
+    /// Looks up the port and creates a fragmenter for a send.
+    ///
+    /// Returns a `None` port for local loopback, see `send_route()`.
+    async fn app_start_send(
+        &self,
+        eid: Eid,
+        typ: MsgType,
+        tag: Option<Tag>,
+        tag_lifetime: Option<u32>,
+        integrity_check: bool,
+        cookie: Option<AppCookie>,
+    ) -> Result<(Option<&'r PortTop<'r, M>>, Fragmenter)> {
+        let mut inner = self.lock_inner().await;
+        // inner is released on return, to allow other ports to continue work
+        self.start_send_locked(
+            &mut inner,
+            eid,
+            typ,
+            tag,
+            tag_lifetime,
+            integrity_check,
+            cookie,
+        )
+    }
+
+    /// As for `app_start_send()`, with the `inner` lock already held.
+    fn start_send_locked(
+        &self,
+        inner: &mut RouterInner<'r, R, LISTENERS, RECEIVERS>,
+        eid: Eid,
+        typ: MsgType,
+        tag: Option<Tag>,
+        tag_lifetime: Option<u32>,
+        integrity_check: bool,
+        cookie: Option<AppCookie>,
+    ) -> Result<(Option<&'r PortTop<'r, M>>, Fragmenter)> {
+        let (top, mtu) = self.send_route(inner, eid, typ)?;
+        let fragmenter = inner
+            .stack
+            .start_send_lifetime(
+                eid,
+                typ,
+                tag,
+                tag_lifetime,
+                integrity_check,
+                Some(mtu),
+                cookie,
+            )
+            .inspect_err(|e| trace!("error fragmenter {}", e))?;
+
+        Ok((top, fragmenter))
+    }
+
+    /// Returns the port and MTU for a send.
+    ///
+    /// The port is `None` for local loopback, to the stack's own EID,
+    /// an EID added by `Router::add_local_eid()`,
+    /// or an EID claimed by `PortLookup::deliver_local()`.
+    fn send_route(
+        &self,
+        inner: &mut RouterInner<'r, R, LISTENERS, RECEIVERS>,
+        eid: Eid,
+        typ: MsgType,
+    ) -> Result<(Option<&'r PortTop<'r, M>>, usize)> {
+        if inner.is_local_send(eid) {
+            return Ok((None, MAX_MTU));
+        }
+
+        let Some(p) = inner.lookup.by_eid(eid, None) else {
+            debug!("No route for recv {}", eid);
+            return Err(Error::TxFailure);
+        };
+
+        let Some(top) = self.ports.get(p.0 as usize) else {
+            debug!("Bad port ID from lookup");
+            return Err(Error::TxFailure);
+        };
+
+        if !top.enabled() {
+            debug!("Send to disabled port {}", p.0);
+            return Err(Error::TxFailure);
+        }
+
+        let mut mtu = top.mtu();
+        if eid == mctp::MCTP_ADDR_NULL {
+            // Peer's MTU isn't known prior to EID assignment
+            mtu = mtu.min(inner.baseline_mtu);
+        }
+        if let Some((_e, peer_mtu)) =
+            inner.peer_mtu.iter().find(|(e, _m)| *e == eid)
+        {
+            mtu = mtu.min(*peer_mtu);
+        }
+        if let Some((_t, type_mtu)) =
+            inner.type_mtu.iter().find(|(t, _m)| *t == typ)
+        {
+            mtu = mtu.min(*type_mtu);
+        }
+        Ok((Some(top), mtu))
+    }
+
+    /// Releases all owned tags that were allocated without expiry.
+    ///
+    /// This is a recovery tool for request channels using
+    /// [`tag_noexpire()`](RouterAsyncReqChannel::tag_noexpire) that were
+    /// dropped without calling `async_drop()`, for example after a task
+    /// panicked. Request channels that are still in use will no longer
+    /// receive responses for released tags.
+    ///
+    /// Returns the number of tags released.
+    pub async fn release_all_persistent_tags(&self) -> usize {
+        let mut inner = self.lock_inner().await;
+        // collect() won't overflow, capacity matches flows
+        let persistent: Vec<(Eid, TagValue), FLOWS> = inner
+            .stack
+            .flows
+            .iter()
+            .filter(|(_k, f)| f.expiry_stamp.is_none())
+            .map(|(k, _f)| *k)
+            .collect();
+
+        let mut count = 0;
+        for (eid, tv) in persistent {
+            match inner.stack.cancel_flow(eid, tv) {
+                Ok(()) => count += 1,
+                Err(e) => warn!("flow cancel failed {}", e),
+            }
+        }
+        debug!("Released {} persistent tags", count);
+
+        let released = inner.stack.take_released();
+        drop(inner);
+        self.notify_released(released);
+        count
+    }
+
+    /// Returns the number of owned tags that can still be allocated to `eid`.
+    ///
+    /// Once this reaches zero, sending a request to `eid` fails with
+    /// [`Error::TagUnavailable`] until a tag is released.
+    pub async fn free_tags(&self, eid: Eid) -> usize {
+        self.lock_inner().await.stack.free_tags(eid)
+    }
+
+    /// Limit the number of owned tags allocated for all destinations.
+    ///
+    /// This bounds the number of outstanding requests, beyond the
+    /// limit of 8 tags for each destination. Once `max` owned tags are
+    /// in use, sending a request fails with [`Error::TagUnavailable`].
+    /// `None` removes the limit.
+    ///
+    /// See [`Stack::set_max_owned_tags()`].
+    pub async fn set_max_owned_tags(&self, max: Option<usize>) {
+        self.lock_inner().await.stack.set_max_owned_tags(max)
+    }
+
+    /// Only needs to be called for tags allocated with tag_expires=false
+    ///
+    /// Must only be called for owned tags.
+    async fn app_release_tag(&self, eid: Eid, tag: Tag) {
+        let Tag::Owned(tv) = tag else {
+            self.invariant_failed("release unowned tag");
+            return;
+        };
+        let mut inner = self.lock_inner().await;
+
+        if let Err(e) = inner.stack.cancel_flow(eid, tv) {
+            warn!("flow cancel failed {}", e);
+        }
+        let released = inner.stack.take_released();
+        drop(inner);
+        self.notify_released(released);
+    }
+
+    /// Handles a failed internal invariant.
+    ///
+    /// With the `checked-invariants` feature the failure is logged and
+    /// counted, returning `Error::InternalError` for the caller to
+    /// propagate.
+    #[cfg(feature = "checked-invariants")]
+    fn invariant_failed(&self, msg: &str) -> Error {
+        error!("Invariant failed: {}", msg);
+        self.state(|s| s.invariant_errors = s.invariant_errors.wrapping_add(1));
+        Error::InternalError
+    }
+
+    /// Handles a failed internal invariant.
+    ///
+    /// Without the `checked-invariants` feature this panics.
+    #[cfg(not(feature = "checked-invariants"))]
+    fn invariant_failed(&self, msg: &str) -> Error {
+        panic!("Invariant failed: {}", msg)
+    }
+
+    /// Handles a failed internal invariant that is only a debug
+    /// assertion without the `checked-invariants` feature.
+    ///
+    /// Release builds without the feature return `Error::InternalError`
+    /// without logging or counting.
+    fn debug_invariant_failed(&self, msg: &str) -> Error {
+        if cfg!(any(debug_assertions, feature = "checked-invariants")) {
+            self.invariant_failed(msg)
+        } else {
+            Error::InternalError
+        }
+    }
+
+    /// Returns the number of internal invariant failures.
+    ///
+    /// These indicate a bug in the `Router`, and have been returned
+    /// as `Error::InternalError` rather than panicking.
+    #[cfg(feature = "checked-invariants")]
+    pub fn invariant_errors(&self) -> u32 {
+        self.state(|s| s.invariant_errors)
+    }
+
+    /// Set application callbacks for `Router` events.
+    ///
+    /// `None` clears any existing hooks.
+    pub async fn set_hooks(&self, hooks: Option<&'r dyn RouterHooks>) {
+        // Wait for operations in progress
+        let _inner = self.lock_inner().await;
+        self.state(|s| s.hooks = hooks);
+    }
+
+    fn hooks(&self) -> Option<&'r dyn RouterHooks> {
+        self.state(|s| s.hooks)
+    }
+
+    fn state<T>(
+        &self,
+        f: impl FnOnce(&mut RouterState<'r, RECEIVERS>) -> T,
+    ) -> T {
+        self.state.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    /// Calls `on_tag_release` for tags released by the stack.
+    ///
+    /// Must be called without the `inner` lock held.
+    fn notify_released(&self, released: Vec<(Eid, TagValue), FLOWS>) {
+        if released.is_empty() {
+            return;
+        }
+        if let Some(hooks) = self.hooks() {
+            for (eid, tv) in released {
+                hooks.on_tag_release(eid, tv);
+            }
+        }
+    }
+
+    /// Create a `AsyncReqChannel` instance
+    pub fn req(
+        &'r self,
+        eid: Eid,
+    ) -> RouterAsyncReqChannel<'r, M, R, LISTENERS, RECEIVERS> {
+        RouterAsyncReqChannel::new(eid, self)
+    }
+
+    /// Create a session for repeated requests to a peer.
+    ///
+    /// See [`RouterSession`].
+    pub fn session(
+        &'r self,
+        eid: Eid,
+    ) -> RouterSession<'r, M, R, LISTENERS, RECEIVERS> {
+        let mut req = RouterAsyncReqChannel::new(eid, self);
+        // OK unwrap: nothing has been sent
+        req.tag_noexpire().unwrap();
+        RouterSession { req }
+    }
+
+    /// Create a `AsyncListener` instance
+    ///
+    /// Will receive incoming messages with the TO bit set for the given `typ`.
+    pub fn listener(
+        &'r self,
+        typ: MsgType,
+    ) -> Result<RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>> {
+        let cookie = self.app_bind(typ, false, false)?;
+        Ok(RouterAsyncListener {
+            cookie,
+            typ,
+            router: self,
+        })
+    }
+
+    /// Create a `AsyncListener` with a bounded queue.
+    ///
+    /// Received messages are held by the stack until `recv()`, each
+    /// occupying a shared reassembly slot. At most `depth` messages
+    /// are held for this listener, further messages are dropped on
+    /// arrival until the listener receives. This prevents a slow
+    /// listener from using reassembly slots needed by other listeners
+    /// and responses. Dropped messages are counted in
+    /// [`ListenerStats::queue_dropped`].
+    ///
+    /// Otherwise behaves as [`listener()`](Self::listener).
+    /// Returns [`Error::BadArgument`] if `depth` is 0.
+    pub fn listener_buffered(
+        &'r self,
+        typ: MsgType,
+        depth: usize,
+    ) -> Result<RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>> {
+        if depth == 0 {
+            return Err(Error::BadArgument);
+        }
+        let l = self.listener(typ)?;
+        self.app_listeners.lock(|a| {
+            if let Some(Some(bind)) = a.borrow_mut().get_mut(l.cookie.0) {
+                bind.depth = Some(depth);
+            }
+        });
+        Ok(l)
+    }
+
+    /// Create a `AsyncListener` that also receives unowned messages.
+    ///
+    /// This is intended for protocols that use the TO bit symmetrically,
+    /// where a peer may send a message with the TO bit clear that is not
+    /// a response to a local request.
+    ///
+    /// As well as messages with the TO bit set (as for [`listener()`](Self::listener)),
+    /// the listener will receive messages of type `typ` with the TO bit clear
+    /// when no request is outstanding for that peer and tag value.
+    /// The returned `Tag` indicates which was received.
+    ///
+    /// Note that the distinction is ambiguous: a response that arrives
+    /// after its request has timed out will be delivered to this listener
+    /// rather than discarded. Conversely an unowned message that
+    /// happens to match an outstanding request's tag is delivered to that
+    /// request channel.
+    ///
+    /// Replies through the returned response channel for an unowned message
+    /// are sent with the same unowned tag.
+    pub fn listener_with_unowned(
+        &'r self,
+        typ: MsgType,
+    ) -> Result<RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>> {
+        let cookie = self.app_bind(typ, true, false)?;
+        Ok(RouterAsyncListener {
+            cookie,
+            typ,
+            router: self,
+        })
+    }
+
+    /// Receive a request message of any type without a listener.
+    ///
+    /// Messages with the TO bit set are received here when no listener
+    /// (or [`prebind()`](Self::prebind)) exists for their type.
+    /// The return values are as for a listener's
+    /// [`recv()`](mctp::AsyncListener::recv), including the message type.
+    ///
+    /// The catch-all bind is created by the first call and remains
+    /// afterwards, so messages arriving between calls are held.
+    /// Messages arriving before the first call are dropped.
+    /// It uses one of the listener slots.
+    ///
+    /// Only a single task should call `recv_any()` at a time.
+    pub async fn recv_any<'f>(
+        &'r self,
+        buf: &'f mut [u8],
+    ) -> Result<(
+        &'f mut [u8],
+        RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>,
+        Tag,
+        MsgType,
+        bool,
+    )> {
+        let cookie = self.app_bind_any()?;
+        let (msg, eid, local_eid, typ, tag, ic) =
+            self.app_recv_message(Some(cookie), None, buf, None).await?;
+        let resp = RouterAsyncRespChannel {
+            eid,
+            local_eid,
+            tv: tag.tag(),
+            router: self,
+        };
+        Ok((msg, resp, tag, typ, ic))
+    }
+
+    /// Limit the number of received messages waiting for listeners.
+    ///
+    /// Complete messages are held by the stack until a listener calls
+    /// `recv()`, occupying a reassembly slot. If a listener is slow to
+    /// receive, incoming messages for listeners will be dropped once
+    /// `limit` are waiting, leaving space for other messages such as
+    /// responses. Dropped messages are counted by
+    /// [`deferred_dropped()`](Self::deferred_dropped).
+    ///
+    /// `None` (the default) applies no limit other than the number of
+    /// reassembly slots.
+    pub async fn set_deferred_limit(&self, limit: Option<usize>) {
+        let mut inner = self.lock_inner().await;
+        inner.deferred_limit = limit;
+    }
+
+    /// Set the handling of incoming packets with a null source EID.
+    ///
+    /// A null source EID is expected from endpoints prior to EID
+    /// assignment, but may be suspicious otherwise.
+    /// The default is [`NullSourcePolicy::Accept`].
+    pub async fn set_null_source_policy(&self, policy: NullSourcePolicy) {
+        let mut inner = self.lock_inner().await;
+        inner.null_source = policy;
+    }
+
+    /// Set the receive priority of a message type.
+    ///
+    /// When a listener has several messages waiting, those of a
+    /// higher priority type are received first. Messages of the same
+    /// priority are received in order of arrival.
+    /// This applies to listeners receiving multiple message types,
+    /// such as [`recv_any()`](Self::recv_any).
+    ///
+    /// The default priority is 0, setting 0 removes an existing entry.
+    /// Returns [`Error::NoSpace`] if too many types have a priority set.
+    pub async fn set_type_priority(
+        &self,
+        typ: MsgType,
+        priority: u8,
+    ) -> Result<()> {
+        let mut inner = self.lock_inner().await;
+        let prio = &mut inner.type_priority;
+        prio.retain(|(t, _p)| *t != typ);
+        if priority != 0 {
+            prio.push((typ, priority)).map_err(|_| Error::NoSpace)?;
+        }
+        Ok(())
+    }
+
+    /// Set whether to respond to unroutable control requests.
+    ///
+    /// When enabled, an MCTP Control request that is to be forwarded
+    /// but has no route is answered with an error response
+    /// (completion code `ERROR`) rather than silently dropped.
+    /// The response is sent from the router's own EID with the request's
+    /// tag, since the router generated it rather than the unreachable
+    /// destination.
+    ///
+    /// Only Control messages are answered, since other message types
+    /// have no common error format, and a response in the wrong format
+    /// could be misread by the requester. Other types can be observed
+    /// with [`RouterHooks::on_unroutable()`]. Forwarded packets aren't
+    /// reassembled, so the response is generated from the first packet
+    /// of the request alone, which holds the tag and Control header.
+    /// Later packets of a multi-packet request are dropped as usual.
+    ///
+    /// The default is disabled.
+    pub async fn set_unroutable_response(&self, enable: bool) {
+        let mut inner = self.lock_inner().await;
+        inner.unroutable_response = enable;
+    }
+
+    /// Set whether to drop forwarded packets with the local source EID.
+    ///
+    /// An incoming packet to forward that has the stack's own EID as
+    /// its source is likely to be looping or spoofed. When enabled these
+    /// packets are dropped and counted in
+    /// [`RouterStats::forward_dropped_self_source`]. Packets for local
+    /// delivery are not affected.
+    ///
+    /// The default is disabled.
+    pub async fn set_drop_self_source(&self, enable: bool) {
+        let mut inner = self.lock_inner().await;
+        inner.drop_self_source = enable;
+    }
+
+    /// Set whether incoming packets are only forwarded.
+    ///
+    /// When enabled, incoming packets skip the local delivery check and
+    /// go straight to the route lookup, for a router that doesn't
+    /// terminate any traffic. Local delivery of incoming packets is
+    /// disabled, including packets to the stack's own EID, EIDs claimed by
+    /// [`PortLookup::deliver_local()`], and broadcasts. Messages sent by
+    /// the local stack are not affected.
+    ///
+    /// The default is disabled.
+    pub async fn set_routing_only(&self, enable: bool) {
+        let mut inner = self.lock_inner().await;
+        inner.routing_only = enable;
+    }
+
+    /// Returns the count of messages dropped due to
+    /// [`set_deferred_limit()`](Self::set_deferred_limit).
+    pub async fn deferred_dropped(&self) -> u32 {
+        let inner = self.lock_inner().await;
+        inner.deferred_dropped
+    }
+
+    /// Set the MTU used when sending to the NULL EID.
+    ///
+    /// Messages sent to [`MCTP_ADDR_NULL`](mctp::MCTP_ADDR_NULL)
+    /// (physical addressing, for example during discovery before a
+    /// peer has an EID assigned) will be fragmented at no more than
+    /// this MTU, since the peer's capabilities are not yet known.
+    /// The port's MTU still applies if smaller.
+    ///
+    /// Defaults to the MCTP baseline MTU, 64 bytes of payload plus header.
+    pub async fn set_baseline_mtu(&self, mtu: usize) -> Result<()> {
+        let mut inner = self.lock_inner().await;
+        inner.set_baseline_mtu(mtu)
+    }
+
+    /// Set the MTU used when sending to a peer.
+    ///
+    /// This limits the packet size for messages sent to `eid`, for
+    /// example after learning a peer's MTU on a shared bus. The port's
+    /// MTU still applies if smaller.
+    /// `mtu` includes the MCTP header. `None` removes the limit.
+    ///
+    /// Returns [`Error::NoSpace`] if too many peers have an MTU set.
+    pub async fn set_peer_mtu(
+        &self,
+        eid: Eid,
+        mtu: Option<usize>,
+    ) -> Result<()> {
+        if let Some(mtu) = mtu {
+            if !(HEADER_LEN + 1..=MAX_MTU).contains(&mtu) {
+                debug!("Bad peer mtu {}", mtu);
+                return Err(Error::BadArgument);
+            }
+        }
+        let mut inner = self.lock_inner().await;
+        let peers = &mut inner.peer_mtu;
+        peers.retain(|(e, _m)| *e != eid);
+        if let Some(mtu) = mtu {
+            peers.push((eid, mtu)).map_err(|_| Error::NoSpace)?;
+        }
+        Ok(())
+    }
+
+    /// Set the MTU used when sending a message type.
+    ///
+    /// This limits the packet size for all messages of type `typ`,
+    /// for example to give small fragments with low latency for
+    /// interactive control messages while bulk transfers use the full
+    /// MTU. Port and peer MTUs still apply if smaller.
+    /// `mtu` includes the MCTP header. `None` removes the limit.
+    ///
+    /// Returns [`Error::NoSpace`] if too many types have an MTU set.
+    pub async fn set_type_mtu(
+        &self,
+        typ: MsgType,
+        mtu: Option<usize>,
+    ) -> Result<()> {
+        if let Some(mtu) = mtu {
+            if !(HEADER_LEN + 1..=MAX_MTU).contains(&mtu) {
+                debug!("Bad type mtu {}", mtu);
+                return Err(Error::BadArgument);
+            }
+        }
+        let mut inner = self.lock_inner().await;
+        let types = &mut inner.type_mtu;
+        types.retain(|(t, _m)| *t != typ);
+        if let Some(mtu) = mtu {
+            types.push((typ, mtu)).map_err(|_| Error::NoSpace)?;
+        }
+        Ok(())
+    }
+
+    /// Set the MTU of a port.
+    ///
+    /// This can be used when a transport renegotiates link parameters.
+    /// `mtu` includes the MCTP header, and must be no larger than
+    /// [`MAX_MTU`].
+    ///
+    /// Messages that have already started sending will complete
+    /// with the previous MTU.
+    pub fn set_port_mtu(&self, port: PortId, mtu: usize) -> Result<()> {
+        check_mtu(mtu)?;
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.settings(|s| s.mtu = mtu);
+        Ok(())
+    }
+
+    /// Returns whether a port's queue has at least `fragments` free slots.
+    ///
+    /// This allows a caller to defer a large send rather than waiting
+    /// for the queue part way through a message. The number of packets
+    /// for a message depends on the port MTU.
+    ///
+    /// Returns `Ok(None)` if the port's queue is currently in use by
+    /// another send, so the free space is unknown, or
+    /// `Error::BadArgument` if `port` is invalid. Other senders may fill
+    /// the queue before a subsequent send, so this is only advisory.
+    pub fn port_has_space(
+        &self,
+        port: PortId,
+        fragments: usize,
+    ) -> Result<Option<bool>> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        let Ok(sender) = top.packets.try_lock() else {
+            return Ok(None);
+        };
+        Ok(Some(top.queue_capacity - sender.len() >= fragments))
+    }
+
+    /// Returns the application data of a port.
+    ///
+    /// This is the value set with [`PortBuilder::set_user_data()`].
+    pub fn port_user_data(&self, port: PortId) -> Result<u32> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        Ok(top.user_data)
+    }
+
+    /// Returns a histogram of packet sizes enqueued for a port.
+    ///
+    /// Both sent and forwarded packets are counted. This can be used to
+    /// check that packets are fragmented to the port's MTU.
+    pub fn port_packet_sizes(&self, port: PortId) -> Result<PacketSizes> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        Ok(top.state(|s| s.packet_sizes))
+    }
+
+    /// Set the range of EIDs that a port may carry.
+    ///
+    /// This is a consistency check on the [`PortLookup`] routing.
+    /// Forwarded packets with a destination EID outside the range
+    /// will be dropped rather than enqueued to the port, with a warning
+    /// logged, and counted in [`RouterStats::forward_dropped_range`].
+    ///
+    /// `None` (the default) allows any EID.
+    pub fn set_port_eid_range(
+        &self,
+        port: PortId,
+        range: Option<RangeInclusive<Eid>>,
+    ) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.settings(|s| s.eid_range = range);
+        Ok(())
+    }
+
+    /// Enable or disable a port.
+    ///
+    /// A disabled port is not used for outbound traffic. Forwarded
+    /// packets routed to it are dropped and counted in
+    /// [`RouterStats::forward_dropped_disabled`], and sends routed to
+    /// it fail with [`Error::TxFailure`]. Packets already queued are
+    /// still sent, and packets received from the port are still handled.
+    ///
+    /// Ports are enabled by default.
+    pub fn set_port_enabled(&self, port: PortId, enable: bool) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.settings(|s| s.enabled = enable);
+        Ok(())
+    }
+
+    /// Returns traffic counters.
+    pub fn stats(&self) -> RouterStats {
+        self.state(|s| s.stats)
+    }
+
+    /// Resets all counters returned by [`stats()`](Self::stats) and
+    /// [`port_stats()`](Self::port_stats) to zero.
+    pub fn reset_stats(&self) {
+        for p in self.ports {
+            p.update_stats(|s| *s = PortStats::default());
+        }
+        self.update_stats(|s| {
+            // Tasks currently waiting are still counted
+            let waiting = s.inner_waiting;
+            *s = RouterStats {
+                inner_waiting: waiting,
+                inner_waiting_peak: waiting,
+                ..Default::default()
+            };
+        })
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut RouterStats)) {
+        self.state(|s| f(&mut s.stats))
+    }
+
+    /// Locks `inner`.
+    ///
+    /// When the lock is contended the waiting task is counted in
+    /// `RouterStats::inner_waiting`. Uncontended locks don't touch stats.
+    ///
+    /// Receive handles released by dropped `RouterRecvGuard`s are
+    /// finished once the lock is held.
+    async fn lock_inner(
+        &self,
+    ) -> InnerGuard<'_, 'r, M, R, LISTENERS, RECEIVERS> {
+        let mut inner = match self.inner.try_lock() {
+            Ok(inner) => inner,
+            Err(_) => self.lock_inner_contended().await,
+        };
+        self.finish_released(&mut inner);
+        inner
+    }
+
+    /// Finishes handles released by `RouterRecvGuard::drop()`.
+    fn finish_released(
+        &self,
+        inner: &mut RouterInner<'_, R, LISTENERS, RECEIVERS>,
+    ) {
+        let released = self.state(|s| core::mem::take(&mut s.released_handles));
+        for handle in released {
+            inner.stack.finished_receive(handle);
+        }
+    }
+
+    async fn lock_inner_contended(
+        &self,
+    ) -> InnerGuard<'_, 'r, M, R, LISTENERS, RECEIVERS> {
+        // Decrements the waiting count when the lock is acquired,
+        // or if the waiting future is dropped.
+        struct Waiting<'g, 'r, M: RawMutex, const RECEIVERS: usize>(
+            &'g BlockingMutex<M, RouterState<'r, RECEIVERS>>,
+        );
+        impl<M: RawMutex, const RECEIVERS: usize> Drop
+            for Waiting<'_, '_, M, RECEIVERS>
+        {
+            fn drop(&mut self) {
+                self.0.lock(|s| {
+                    let s = &mut s.borrow_mut().stats;
+                    s.inner_waiting = s.inner_waiting.saturating_sub(1)
+                });
+            }
+        }
+
+        self.update_stats(|s| {
+            s.inner_waiting = s.inner_waiting.saturating_add(1);
+            s.inner_waiting_peak = s.inner_waiting_peak.max(s.inner_waiting);
+        });
+        let _waiting = Waiting(&self.state);
+        self.inner.lock().await
+    }
+
+    /// Records a link-layer error detected by a port's transport binding.
+    ///
+    /// Bindings can call this when discarding received data, for example
+    /// after a CRC or framing error. Errors are counted for the port,
+    /// returned by [`port_link_errors()`](Self::port_link_errors),
+    /// and in total in [`stats()`](Self::stats).
+    pub fn note_link_error(&self, port: PortId, err: LinkError) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.state(|s| {
+            let l = &mut s.link_errors;
+            let c = match err {
+                LinkError::Crc => &mut l.crc,
+                LinkError::Framing => &mut l.framing,
+                LinkError::Overrun => &mut l.overrun,
+                LinkError::Other => &mut l.other,
+            };
+            *c = c.wrapping_add(1);
+        });
+        self.update_stats(|s| s.link_errors = s.link_errors.wrapping_add(1));
+        Ok(())
+    }
+
+    /// Returns the traffic counters of a port.
+    ///
+    /// Returns `None` if `port` is invalid. Counters are cleared by
+    /// [`reset_stats()`](Self::reset_stats).
+    pub fn port_stats(&self, port: PortId) -> Option<PortStats> {
+        let top = self.ports.get(port.0 as usize)?;
+        Some(top.state(|s| s.stats))
+    }
+
+    /// Returns the link-layer error counters of a port.
+    ///
+    /// See [`note_link_error()`](Self::note_link_error).
+    pub fn port_link_errors(&self, port: PortId) -> Result<LinkErrorStats> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        Ok(top.state(|s| s.link_errors))
+    }
+
+    /// Set whether to record dequeue latency for a port.
+    ///
+    /// When enabled, packets enqueued for the port are timestamped with
+    /// the time from [`update_time()`](Self::update_time), and
+    /// the time until [`PortBottom::outbound_done()`] is returned by
+    /// [`port_latency()`](Self::port_latency). Latency is only as precise
+    /// as the `update_time()` interval. Disabled by default.
+    pub fn set_port_latency(&self, port: PortId, enable: bool) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.shared.set_timestamps(enable);
+        Ok(())
+    }
+
+    /// Returns the dequeue latency of a port.
+    ///
+    /// See [`set_port_latency()`](Self::set_port_latency).
+    pub fn port_latency(&self, port: PortId) -> Result<DequeueLatency> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        Ok(top.shared.latency())
+    }
+
+    /// Returns the approximate memory used by the `Router` and its ports.
+    ///
+    /// This is the size of the `Router` itself (including the listener
+    /// and receiver tables), the [`Stack`] whether owned or borrowed
+    /// with [`new_borrowed()`](Self::new_borrowed), the reassembly
+    /// buffers, each [`PortTop`], each port's [`PortStorage`] queue and
+    /// scratch buffers, and buffers given to
+    /// [`set_recv_pool()`](Self::set_recv_pool).
+    /// The [`PortLookup`] and [`PortBuilder`]s are not included.
+    ///
+    /// Reassembly buffers held outside a custom [`ReassemblyPool`] are
+    /// only included if the pool implements
+    /// [`footprint()`](ReassemblyPool::footprint).
+    ///
+    /// This can be used to choose `PortStorage` parameters and
+    /// [`config`](crate::config) values.
+    pub async fn memory_footprint(&self) -> usize {
+        let inner = self.lock_inner().await;
+        let stack = match inner.stack {
+            // Included in the size of the Router
+            RouterStack::Owned(_) => 0,
+            RouterStack::Borrowed(_) => size_of::<Stack<R>>(),
+        };
+        let reassembly = inner.stack.pool.footprint();
+        drop(inner);
+
+        let recv_pool = self.recv_pool.lock(|p| p.borrow().total());
+        let ports: usize = self
+            .ports
+            .iter()
+            .map(|p| {
+                size_of::<PortTop<'_, M>>()
+                    + p.queue_capacity * size_of::<PktBuf>()
+                    + p.scratch_len
+            })
+            .sum();
+        size_of::<Self>() + stack + reassembly + recv_pool * MAX_PAYLOAD + ports
+    }
+
+    /// Returns the maximum message payload that can be sent to `eid`.
+    ///
+    /// This is the limit for a peer with the same stack configuration
+    /// to receive: [`MAX_PAYLOAD`], and if
+    /// [`Stack::set_max_fragments()`] is set, that many packets of the
+    /// send MTU for `eid` and `typ` (including peer and type MTUs).
+    /// Without a fragment limit the MTU doesn't limit the payload, since
+    /// messages are fragmented into as many packets as required.
+    /// The length does not include the MCTP type byte.
+    ///
+    /// Returns `None` if `eid` has no route.
+    pub async fn max_payload(&self, eid: Eid, typ: MsgType) -> Option<usize> {
+        let mut inner = self.lock_inner().await;
+        self.payload_route(&mut inner, eid, typ)
+            .map(|(_top, max)| max)
+    }
+
+    /// Returns the maximum payload for a `send_vectored()` with multiple
+    /// slices to `eid`.
+    ///
+    /// This is [`max_payload()`](Self::max_payload), further limited by
+    /// the route's port scratch buffer, which multiple slices are
+    /// copied into. Loopback sends don't use a scratch buffer.
+    ///
+    /// Returns `None` if `eid` has no route.
+    pub async fn max_vectored_payload(
+        &self,
+        eid: Eid,
+        typ: MsgType,
+    ) -> Option<usize> {
+        let mut inner = self.lock_inner().await;
+        let (top, max) = self.payload_route(&mut inner, eid, typ)?;
+        Some(top.map_or(max, |t| max.min(t.scratch_len)))
+    }
+
+    /// Returns the send route and maximum payload for `max_payload()`.
+    fn payload_route(
+        &self,
+        inner: &mut RouterInner<'r, R, LISTENERS, RECEIVERS>,
+        eid: Eid,
+        typ: MsgType,
+    ) -> Option<(Option<&'r PortTop<'r, M>>, usize)> {
+        let (top, mtu) = self.send_route(inner, eid, typ).ok()?;
+        let mut max = MAX_PAYLOAD;
+        if let Some(frags) = inner.stack.max_fragments {
+            // The type byte is carried in the first packet
+            let frag_max =
+                frags.saturating_mul(mtu - HEADER_LEN).saturating_sub(1);
+            max = max.min(frag_max);
+        }
+        Some((top, max))
+    }
+
+    /// Keep the packets of each sent message contiguous in a port's queue.
+    ///
+    /// By default concurrent sends to a port may interleave their packets,
+    /// which is permitted by MCTP but can confuse some peers.
+    /// With `atomic` set, a send holds the port queue for all of its
+    /// packets. That causes head-of-line blocking: other sends and
+    /// forwarded packets for the port wait until the whole message
+    /// has been enqueued, which may take a long time for a large message
+    /// and a slow port.
+    pub fn set_port_atomic_messages(
+        &self,
+        port: PortId,
+        atomic: bool,
+    ) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.settings(|s| s.atomic_messages = atomic);
+        Ok(())
+    }
+
+    /// Writes and reads back buffers, to confirm the memory is usable.
+    ///
+    /// This checks each port's message scratch buffer and each unused
+    /// reassembly slot. Port packet queues are checked separately by
+    /// [`PortStorage::pre_warm()`], since they aren't accessible once
+    /// the port is built.
+    ///
+    /// All memory is statically sized, so this is intended to be called
+    /// once at startup (for example to scrub ECC memory, or to fail early
+    /// in fault-injection setups).
+    /// Returns `Error::Other` if a buffer doesn't read back correctly.
+    pub async fn pre_warm(&self) -> Result<()> {
+        for p in self.ports {
+            let mut msg = p.message.lock().await;
+            crate::util::check_memory(&mut msg)
+                .inspect_err(|_| error!("Port memory check failed"))?;
+        }
+
+        let mut inner = self.lock_inner().await;
+        inner
+            .stack
+            .pre_warm()
+            .inspect_err(|_| error!("Reassembly memory check failed"))
+    }
+
+    /// Set the behaviour when forwarding to a port with a full queue.
+    ///
+    /// The default is [`OverflowPolicy::DropNewest`]. This only applies
+    /// to forwarded packets, local sends wait for queue space.
+    pub fn set_port_overflow(
+        &self,
+        port: PortId,
+        policy: OverflowPolicy,
+    ) -> Result<()> {
+        let top = self.ports.get(port.0 as usize).ok_or(Error::BadArgument)?;
+        top.settings(|s| s.overflow = policy);
+        Ok(())
+    }
+
+    /// Retrieve the EID assigned to the local stack
+    pub async fn get_eid(&self) -> Eid {
+        let inner = self.lock_inner().await;
+        inner.stack.own_eid
+    }
+
+    /// Set the EID assigned to the local stack
+    pub async fn set_eid(&self, eid: Eid) -> mctp::Result<()> {
+        let mut inner = self.lock_inner().await;
+        self.set_eid_locked(&mut inner, eid)?;
+        self.routes_changed();
+        Ok(())
+    }
+
+    fn set_eid_locked(
+        &self,
+        inner: &mut RouterInner<'_, R, LISTENERS, RECEIVERS>,
+        eid: Eid,
+    ) -> Result<()> {
+        inner.stack.set_eid(eid.0)?;
+        self.state(|s| s.eid = eid);
+        Ok(())
+    }
+
+    /// Add an additional local EID.
+    ///
+    /// Incoming packets to `eid` are delivered to local listeners,
+    /// and local sends to `eid` are looped back. Responses are sent
+    /// from the EID that a request was addressed to, see
+    /// [`RouterAsyncRespChannel::local_eid()`].
+    /// [`get_eid()`](Self::get_eid) still returns the primary EID.
+    ///
+    /// Returns [`Error::BadArgument`] if `eid` is not a normal EID or is
+    /// already local, or [`Error::NoSpace`] if too many EIDs are added.
+    pub async fn add_local_eid(&self, eid: Eid) -> Result<()> {
+        Eid::new_normal(eid.0)?;
+        let mut inner = self.lock_inner().await;
+        if eid == inner.stack.eid() || inner.local_eids.contains(&eid) {
+            return Err(Error::BadArgument);
+        }
+        inner.local_eids.push(eid).map_err(|_| Error::NoSpace)?;
+        self.routes_changed();
+        Ok(())
+    }
+
+    /// Remove a local EID added with [`add_local_eid()`](Self::add_local_eid).
+    ///
+    /// Returns [`Error::BadArgument`] if `eid` wasn't added.
+    pub async fn remove_local_eid(&self, eid: Eid) -> Result<()> {
+        let mut inner = self.lock_inner().await;
+        let i = inner
+            .local_eids
+            .iter()
+            .position(|e| *e == eid)
+            .ok_or(Error::BadArgument)?;
+        inner.local_eids.remove(i);
+        Ok(())
+    }
+
+    /// Returns all local EIDs.
+    ///
+    /// The primary EID from [`get_eid()`](Self::get_eid) is first,
+    /// followed by EIDs added with [`add_local_eid()`](Self::add_local_eid).
+    /// EIDs claimed by [`PortLookup::deliver_local()`] are not included.
+    pub async fn local_eids(&self) -> impl Iterator<Item = Eid> {
+        let inner = self.lock_inner().await;
+        let mut eids: Vec<Eid, { MAX_LOCAL_EIDS + 1 }> = Vec::new();
+        // OK unwrap: capacity fits the primary and additional EIDs
+        eids.push(inner.stack.eid()).unwrap();
+        eids.extend_from_slice(&inner.local_eids).unwrap();
+        eids.into_iter()
+    }
+
+    /// Returns true if the local stack has an EID assigned.
+    ///
+    /// This is false while the EID is [`MCTP_ADDR_NULL`](mctp::MCTP_ADDR_NULL),
+    /// before [`set_eid()`](Self::set_eid) has been called.
+    /// It doesn't wait for the `Router`'s lock.
+    pub fn is_eid_assigned(&self) -> bool {
+        self.state(|s| s.eid != mctp::MCTP_ADDR_NULL)
+    }
+
+    /// Apply several configuration changes together.
+    ///
+    /// `f` stages changes with the [`RouterConfig`] methods, which check
+    /// each change as it is made. If `f` returns `Ok`, all staged
+    /// changes are then applied with the `Router`'s lock held, so no
+    /// packet is received, forwarded or sent with only some of them
+    /// applied. If `f` returns an error, no changes are applied and
+    /// the error is returned.
+    ///
+    /// `f` is called with the lock held. It must be quick, and must not
+    /// call any `Router` methods.
+    ///
+    /// The `ports` slice passed to [`Router::new()`] can't be changed,
+    /// though port settings can.
+    /// Messages that have already started sending complete with the
+    /// previous configuration.
+    pub async fn reconfigure<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut RouterConfig<'_, 'r, M>) -> Result<T>,
+    {
+        let mut inner = self.lock_inner().await;
+        let mut cfg = RouterConfig {
+            ports: self.ports,
+            current_eid: inner.stack.eid(),
+            eid: None,
+            lookup: None,
+            baseline_mtu: None,
+            port_changes: Vec::new(),
+        };
+        let r = f(&mut cfg)?;
+
+        let RouterConfig {
+            eid,
+            lookup,
+            baseline_mtu,
+            port_changes,
+            ..
+        } = cfg;
+        // Checked by RouterConfig, so these won't fail part way
+        if let Some(eid) = eid {
+            self.set_eid_locked(&mut inner, eid)?;
+        }
+        if let Some(lookup) = lookup {
+            inner.lookup = lookup;
+        }
+        if let Some(mtu) = baseline_mtu {
+            inner.set_baseline_mtu(mtu)?;
+        }
+        for (port, change) in port_changes {
+            if let Some(mtu) = change.mtu {
+                self.set_port_mtu(port, mtu)?;
+            }
+            if let Some(range) = change.eid_range {
+                self.set_port_eid_range(port, range)?;
+            }
+            if let Some(enable) = change.enabled {
+                self.set_port_enabled(port, enable)?;
+            }
+        }
+
+        // Routes may have changed
+        self.routes_changed();
+        Ok(r)
+    }
+
+    /// Wakes tasks in `await_route()` to check their route again.
+    fn routes_changed(&self) {
+        self.state(|s| {
+            let w = &mut s.route_wakers;
+            w.changes = w.changes.wrapping_add(1);
+            w.wakers.wake();
+        });
+    }
+
+    /// Waits until `eid` is routable.
+    ///
+    /// Completes once the [`PortLookup`] returns a port for `eid`
+    /// (with no source port), or a send to `eid` would be delivered
+    /// locally. The lookup is checked again after each
+    /// [`reconfigure()`](Self::reconfigure), [`set_eid()`](Self::set_eid)
+    /// or [`add_local_eid()`](Self::add_local_eid).
+    /// A `PortLookup` that changes its routes internally should be
+    /// followed by a `reconfigure()` call, which may make no changes.
+    ///
+    /// Returns [`Error::NoSpace`] if too many tasks are waiting.
+    pub async fn await_route(&self, eid: Eid) -> Result<()> {
+        loop {
+            let changes = self.state(|s| s.route_wakers.changes);
+
+            let mut inner = self.lock_inner().await;
+            if inner.is_local_send(eid)
+                || inner.lookup.by_eid(eid, None).is_some()
+            {
+                return Ok(());
+            }
+            drop(inner);
+
+            poll_fn(|cx| {
+                self.state(|s| {
+                    let w = &mut s.route_wakers;
+                    if w.changes != changes {
+                        return Poll::Ready(Ok(()));
+                    }
+                    if let Err(e) = w.wakers.register(cx.waker()) {
+                        warn!("Too many route waiters");
+                        return Poll::Ready(Err(e));
+                    }
+                    Poll::Pending
+                })
+            })
+            .await?;
+        }
+    }
+}
+
+/// Configuration changes for a [`Router`], staged by
+/// [`Router::reconfigure()`].
+///
+/// Each method checks its arguments and records the change. Changes are
+/// applied together once the `reconfigure()` closure returns `Ok`.
+pub struct RouterConfig<'a, 'r, M: RawMutex = CriticalSectionRawMutex> {
+    ports: &'a [PortTop<'r, M>],
+    current_eid: Eid,
+    eid: Option<Eid>,
+    lookup: Option<&'r mut dyn PortLookup>,
+    baseline_mtu: Option<usize>,
+    port_changes: Vec<(PortId, PortChange), MAX_RECONFIGURE_PORTS>,
+}
+
+/// Staged changes to a port's settings, `None` leaves a setting as is.
+#[derive(Default)]
+struct PortChange {
+    mtu: Option<usize>,
+    eid_range: Option<Option<RangeInclusive<Eid>>>,
+    enabled: Option<bool>,
+}
+
+impl<'r, M: RawMutex> RouterConfig<'_, 'r, M> {
+    /// Returns the EID of the local stack, including a staged change.
+    pub fn eid(&self) -> Eid {
+        self.eid.unwrap_or(self.current_eid)
+    }
+
+    /// Set the EID of the local stack.
+    ///
+    /// As for [`Router::set_eid()`].
+    pub fn set_eid(&mut self, eid: Eid) -> Result<()> {
+        Eid::new_normal(eid.0)?;
+        self.eid = Some(eid);
+        Ok(())
+    }
+
+    /// Replace the routing table.
+    ///
+    /// The `Router` doesn't cache routes, each forwarded packet and
+    /// send queries the current [`PortLookup`]. The new `lookup` applies
+    /// to all packets after [`Router::reconfigure()`] returns, with no
+    /// need to flush previous routes.
+    pub fn set_lookup(&mut self, lookup: &'r mut dyn PortLookup) {
+        self.lookup = Some(lookup);
+    }
 
-        if let Err(e) = inner.stack.cancel_flow(eid, tv) {
-            warn!("flow cancel failed {}", e);
-        }
+    /// Set the MTU used when sending to the NULL EID.
+    ///
+    /// As for [`Router::set_baseline_mtu()`].
+    pub fn set_baseline_mtu(&mut self, mtu: usize) -> Result<()> {
+        check_mtu(mtu)?;
+        self.baseline_mtu = Some(mtu);
+        Ok(())
     }
 
-    /// Create a `AsyncReqChannel` instance
-    pub fn req(&'r self, eid: Eid) -> RouterAsyncReqChannel<'r> {
-        RouterAsyncReqChannel::new(eid, self)
+    /// Set the MTU of a port.
+    ///
+    /// As for [`Router::set_port_mtu()`].
+    pub fn set_port_mtu(&mut self, port: PortId, mtu: usize) -> Result<()> {
+        check_mtu(mtu)?;
+        self.port(port)?.mtu = Some(mtu);
+        Ok(())
     }
 
-    /// Create a `AsyncListener` instance
+    /// Set the range of EIDs that a port may carry.
     ///
-    /// Will receive incoming messages with the TO bit set for the given `typ`.
-    pub fn listener(&'r self, typ: MsgType) -> Result<RouterAsyncListener<'r>> {
-        let cookie = self.app_bind(typ)?;
-        Ok(RouterAsyncListener {
-            cookie,
-            router: self,
-        })
+    /// As for [`Router::set_port_eid_range()`].
+    pub fn set_port_eid_range(
+        &mut self,
+        port: PortId,
+        range: Option<RangeInclusive<Eid>>,
+    ) -> Result<()> {
+        self.port(port)?.eid_range = Some(range);
+        Ok(())
     }
 
-    /// Retrieve the EID assigned to the local stack
-    pub async fn get_eid(&self) -> Eid {
-        let inner = self.inner.lock().await;
-        inner.stack.own_eid
+    /// Enable or disable a port.
+    ///
+    /// As for [`Router::set_port_enabled()`].
+    pub fn set_port_enabled(
+        &mut self,
+        port: PortId,
+        enable: bool,
+    ) -> Result<()> {
+        self.port(port)?.enabled = Some(enable);
+        Ok(())
     }
 
-    /// Set the EID assigned to the local stack
-    pub async fn set_eid(&self, eid: Eid) -> mctp::Result<()> {
-        let mut inner = self.inner.lock().await;
-        inner.stack.set_eid(eid.0)
+    /// Returns the staged changes for `port`.
+    ///
+    /// Returns `Error::BadArgument` for an invalid port, or
+    /// `Error::NoSpace` if too many ports are changed at once.
+    fn port(&mut self, port: PortId) -> Result<&mut PortChange> {
+        if port.0 as usize >= self.ports.len() {
+            return Err(Error::BadArgument);
+        }
+        let pos = match self.port_changes.iter().position(|(p, _)| *p == port) {
+            Some(pos) => pos,
+            None => {
+                self.port_changes
+                    .push((port, PortChange::default()))
+                    .map_err(|_| Error::NoSpace)?;
+                self.port_changes.len() - 1
+            }
+        };
+        Ok(&mut self.port_changes[pos].1)
     }
 }
 
 /// A request channel.
-pub struct RouterAsyncReqChannel<'r> {
+pub struct RouterAsyncReqChannel<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
     eid: Eid,
     sent_tag: Option<Tag>,
-    router: &'r Router<'r>,
-    tag_expires: bool,
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+    /// `None` for a tag that doesn't expire
+    tag_lifetime: Option<u32>,
+    /// Set by `set_sent_waker()`
+    sent_waker: Option<Waker>,
 }
 
-impl<'r> RouterAsyncReqChannel<'r> {
-    fn new(eid: Eid, router: &'r Router<'r>) -> Self {
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterAsyncReqChannel<'r, M, R, LISTENERS, RECEIVERS>
+{
+    fn new(
+        eid: Eid,
+        router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+    ) -> Self {
         RouterAsyncReqChannel {
             eid,
             sent_tag: None,
-            tag_expires: true,
+            tag_lifetime: Some(crate::REASSEMBLY_EXPIRY_TIMEOUT),
             router,
+            sent_waker: None,
         }
     }
 
-    /// Set the tag to not expire. That allows multiple calls to `send()`.
+    /// Set a waker to notify when sent messages have been transmitted.
+    ///
+    /// `send()` returns once a message is enqueued to the port.
+    /// `waker` is woken later, when the port's [`PortBottom`] consumes
+    /// the last packet of the message with
+    /// [`outbound_done()`](PortBottom::outbound_done). This can be used
+    /// to start a response timeout after transmission. Messages delivered
+    /// locally wake `waker` before `send()` returns.
+    ///
+    /// The waker applies to each following send on this channel.
+    /// It isn't woken for messages that fail to send, or if the packet is
+    /// discarded by [`OverflowPolicy::DropOldest`].
+    /// `None` (the default) disables notification.
+    pub fn set_sent_waker(&mut self, waker: Option<Waker>) {
+        self.sent_waker = waker;
+    }
+
+    /// Set the tag to not expire. That allows multiple calls to `send()`,
+    /// and receiving multiple responses.
     ///
     /// `async_drop` must be called prior to drop.
     pub fn tag_noexpire(&mut self) -> Result<()> {
         if self.sent_tag.is_some() {
             return Err(Error::BadArgument);
         }
-        self.tag_expires = false;
+        self.tag_lifetime = None;
+        Ok(())
+    }
+
+    /// Set how long the tag is held waiting for a response.
+    ///
+    /// The owned tag allocated by `send()` is released `millis`
+    /// milliseconds after the send if no response arrives, as measured
+    /// by [`Router::update_time()`]. The default is 6 seconds.
+    /// Must be called before the first `send()`.
+    pub fn tag_lifetime(&mut self, millis: u32) -> Result<()> {
+        if self.sent_tag.is_some() {
+            return Err(Error::BadArgument);
+        }
+        self.tag_lifetime = Some(millis);
+        Ok(())
+    }
+
+    /// Send a message with the payload provided by a closure.
+    ///
+    /// This avoids buffering the full payload, for example when reading
+    /// from a memory-mapped source.
+    /// `total_len` is the length of the payload (after the type byte).
+    /// `producer(offset, buf)` is called as each packet is fragmented,
+    /// and must fill all of `buf` with payload bytes from `offset`, returning
+    /// the length written.
+    ///
+    /// A short write from `producer` fails with [`Error::InvalidInput`].
+    /// Any previous packets of the message will already have been sent.
+    ///
+    /// Otherwise behaves as [`send()`](mctp::AsyncReqChannel::send).
+    pub async fn send_lazy<F>(
+        &mut self,
+        typ: MsgType,
+        integrity_check: bool,
+        total_len: usize,
+        producer: F,
+    ) -> Result<()>
+    where
+        F: FnMut(usize, &mut [u8]) -> usize,
+    {
+        let tag = self
+            .router
+            .app_send_message_with(
+                self.eid,
+                typ,
+                self.sent_tag,
+                self.tag_lifetime,
+                integrity_check,
+                total_len,
+                self.sent_waker.as_ref(),
+                producer,
+            )
+            .await?;
+        if !matches!(tag, Tag::Owned(_)) {
+            return Err(self
+                .router
+                .debug_invariant_failed("send tag not owned"));
+        }
+        self.sent_tag = Some(tag);
         Ok(())
     }
 
+    /// Receive a response message without copying.
+    ///
+    /// The returned guard borrows the message from the `Router`, see
+    /// [`RouterRecvGuard`] for the costs of holding it.
+    ///
+    /// Otherwise behaves as [`recv()`](mctp::AsyncReqChannel::recv).
+    pub async fn recv_borrowed(
+        &mut self,
+    ) -> Result<RouterRecvGuard<'r, M, R, LISTENERS, RECEIVERS>> {
+        let Some(Tag::Owned(tv)) = self.sent_tag else {
+            debug!("recv without send");
+            return Err(Error::BadArgument);
+        };
+        let recv_tag = Tag::Unowned(tv);
+        self.router
+            .app_recv_borrowed(None, Some((recv_tag, self.eid)))
+            .await
+    }
+
+    /// Receive a response, with an absolute deadline.
+    ///
+    /// `deadline_millis` is compared against the time provided to
+    /// [`Router::update_time()`]. Once that reaches `deadline_millis`,
+    /// `Error::TimedOut` is returned.
+    /// A response that has already arrived is returned even if the
+    /// deadline has passed.
+    ///
+    /// Otherwise behaves as [`recv()`](mctp::AsyncReqChannel::recv).
+    pub async fn recv_deadline<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+        deadline_millis: u64,
+    ) -> Result<(&'f mut [u8], MsgType, Tag, bool)> {
+        self.recv_inner(buf, Some(deadline_millis)).await
+    }
+
+    async fn recv_inner<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+        deadline: Option<u64>,
+    ) -> Result<(&'f mut [u8], MsgType, Tag, bool)> {
+        let Some(Tag::Owned(tv)) = self.sent_tag else {
+            debug!("recv without send");
+            return Err(Error::BadArgument);
+        };
+        let recv_tag = Tag::Unowned(tv);
+        let (buf, eid, _dest, typ, tag, ic) = self
+            .router
+            .app_recv_message(None, Some((recv_tag, self.eid)), buf, deadline)
+            .await?;
+        debug_assert_eq!(tag, recv_tag);
+        debug_assert_eq!(eid, self.eid);
+        Ok((buf, typ, tag, ic))
+    }
+
     /// This must be called prior to drop whenever `tag_noexpire()` is used.
     ///
     /// A workaround until async drop is implemented in Rust itself.
     /// <https://github.com/rust-lang/rust/issues/126482>
     pub async fn async_drop(self) {
-        if !self.tag_expires {
+        if self.tag_lifetime.is_none() {
             if let Some(tag) = self.sent_tag {
                 self.router.app_release_tag(self.eid, tag).await;
             }
@@ -723,9 +4444,15 @@ impl<'r> RouterAsyncReqChannel<'r> {
     }
 }
 
-impl Drop for RouterAsyncReqChannel<'_> {
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Drop for RouterAsyncReqChannel<'_, M, R, LISTENERS, RECEIVERS>
+{
     fn drop(&mut self) {
-        if !self.tag_expires && self.sent_tag.is_some() {
+        if self.tag_lifetime.is_none() && self.sent_tag.is_some() {
             warn!("Didn't call async_drop()");
         }
     }
@@ -734,7 +4461,14 @@ impl Drop for RouterAsyncReqChannel<'_> {
 /// A request channel
 ///
 /// Created with [`Router::req()`](Router::req).
-impl mctp::AsyncReqChannel for RouterAsyncReqChannel<'_> {
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > mctp::AsyncReqChannel
+    for RouterAsyncReqChannel<'_, M, R, LISTENERS, RECEIVERS>
+{
     /// Send a message.
     ///
     /// This will async block until the message has been enqueued to the physical port.
@@ -756,13 +4490,19 @@ impl mctp::AsyncReqChannel for RouterAsyncReqChannel<'_> {
                 self.eid,
                 typ,
                 self.sent_tag,
-                self.tag_expires,
+                self.tag_lifetime,
                 integrity_check,
                 bufs,
                 None,
+                None,
+                self.sent_waker.as_ref(),
             )
             .await?;
-        debug_assert!(matches!(tag, Tag::Owned(_)));
+        if !matches!(tag, Tag::Owned(_)) {
+            return Err(self
+                .router
+                .debug_invariant_failed("send tag not owned"));
+        }
         self.sent_tag = Some(tag);
         Ok(())
     }
@@ -771,19 +4511,7 @@ impl mctp::AsyncReqChannel for RouterAsyncReqChannel<'_> {
         &mut self,
         buf: &'f mut [u8],
     ) -> Result<(&'f mut [u8], MsgType, Tag, bool)> {
-        let Some(Tag::Owned(tv)) = self.sent_tag else {
-            debug!("recv without send");
-            return Err(Error::BadArgument);
-        };
-        let recv_tag = Tag::Unowned(tv);
-        let (buf, eid, typ, tag, ic) = self
-            .router
-            .app_recv_message(None, Some((recv_tag, self.eid)), buf)
-            .await?;
-        debug_assert_eq!(tag, recv_tag);
-        debug_assert_eq!(eid, self.eid);
-        Ok((buf, typ, tag, ic))
-        // todo!()
+        self.recv_inner(buf, None).await
     }
 
     fn remote_eid(&self) -> Eid {
@@ -791,24 +4519,125 @@ impl mctp::AsyncReqChannel for RouterAsyncReqChannel<'_> {
     }
 }
 
+/// A session of request and response exchanges with a peer.
+///
+/// Created with [`Router::session()`]. A single owned tag is allocated
+/// by the first exchange and reused for subsequent exchanges, rather than
+/// allocating a tag for each request.
+///
+/// Since the tag is reused, a late response to an earlier exchange
+/// that failed may be received by a later exchange.
+///
+/// [`close()`](Self::close) must be called prior to drop to release
+/// the tag.
+pub struct RouterSession<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    req: RouterAsyncReqChannel<'r, M, R, LISTENERS, RECEIVERS>,
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterSession<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Send a request and wait for its response.
+    ///
+    /// Returns the response payload, message type and integrity check
+    /// bit. Fails with [`Error::NoSpace`] if the response doesn't fit
+    /// in `resp`.
+    pub async fn exchange<'f>(
+        &mut self,
+        typ: MsgType,
+        integrity_check: bool,
+        req: &[u8],
+        resp: &'f mut [u8],
+    ) -> Result<(&'f mut [u8], MsgType, bool)> {
+        use mctp::AsyncReqChannel;
+
+        self.req.send_vectored(typ, integrity_check, &[req]).await?;
+        let (buf, typ, _tag, ic) = self.req.recv(resp).await?;
+        Ok((buf, typ, ic))
+    }
+
+    /// Returns the peer's EID.
+    pub fn eid(&self) -> Eid {
+        self.req.eid
+    }
+
+    /// Release the session's tag.
+    pub async fn close(self) {
+        self.req.async_drop().await
+    }
+}
+
 /// A response channel.
 ///
 /// Returned by [`RouterAsyncListener::recv`](mctp::AsyncListener::recv).
-pub struct RouterAsyncRespChannel<'r> {
+///
+/// Responses are sent with the request's unowned tag, which isn't tracked
+/// by the local stack. `send()` may be called multiple times to send a
+/// multi-part response with the same tag. The requester must keep its
+/// tag allocated (for example with
+/// [`RouterAsyncReqChannel::tag_noexpire()`]) to receive more than
+/// one response.
+pub struct RouterAsyncRespChannel<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
     eid: Eid,
+    local_eid: Eid,
     tv: TagValue,
-    router: &'r Router<'r>,
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterAsyncRespChannel<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Returns the local EID that the request was addressed to.
+    ///
+    /// A listener receives messages for all local EIDs, including those
+    /// added by [`Router::add_local_eid()`] or
+    /// claimed by [`PortLookup::deliver_local()`]. This is
+    /// [`MCTP_ADDR_NULL`](mctp::MCTP_ADDR_NULL) for physically
+    /// addressed requests, or [`MCTP_ADDR_ANY`](mctp::MCTP_ADDR_ANY)
+    /// for broadcasts.
+    pub fn local_eid(&self) -> Eid {
+        self.local_eid
+    }
 }
 
-impl<'r> mctp::AsyncRespChannel for RouterAsyncRespChannel<'r> {
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > mctp::AsyncRespChannel
+    for RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>
+{
     type ReqChannel<'a>
-        = RouterAsyncReqChannel<'r>
+        = RouterAsyncReqChannel<'r, M, R, LISTENERS, RECEIVERS>
     where
         Self: 'a;
 
     /// Send a message.
     ///
     /// See description of `RouterAsyncReqChannel::send_vectored()`.
+    /// May be called multiple times, each message uses the same tag.
     async fn send_vectored(
         &mut self,
         typ: MsgType,
@@ -816,15 +4645,21 @@ impl<'r> mctp::AsyncRespChannel for RouterAsyncRespChannel<'r> {
         bufs: &[&[u8]],
     ) -> Result<()> {
         let tag = Some(Tag::Unowned(self.tv));
+        // Respond from an EID claimed by the PortLookup
+        let source = (self.local_eid != mctp::MCTP_ADDR_NULL
+            && self.local_eid != mctp::MCTP_ADDR_ANY)
+            .then_some(self.local_eid);
         self.router
             .app_send_message(
                 self.eid,
                 typ,
                 tag,
-                false,
+                None,
                 integrity_check,
                 bufs,
                 None,
+                source,
+                None,
             )
             .await?;
         Ok(())
@@ -839,46 +4674,355 @@ impl<'r> mctp::AsyncRespChannel for RouterAsyncRespChannel<'r> {
     }
 }
 
+/// A received message, left in the `Router`'s reassembly buffer.
+///
+/// Returned by [`RouterAsyncReqChannel::recv_borrowed()`].
+///
+/// This avoids copying the message. The message's reassembly slot is
+/// pinned until the guard is dropped, so it isn't available for other
+/// incoming messages. With [`NUM_RECEIVE`](crate::config::NUM_RECEIVE)
+/// slots in total, holding many guards will cause incoming messages
+/// to be dropped.
+///
+/// The guard doesn't hold the `Router`'s lock, other than briefly
+/// in [`with_message()`](Self::with_message). The slot is released the
+/// next time the `Router` is used after the guard is dropped.
+pub struct RouterRecvGuard<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+    // Always Some, taken on drop
+    handle: Option<ReceiveHandle>,
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterRecvGuard<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Calls `f` with the received message.
+    ///
+    /// The `Router`'s lock is held while `f` runs, so it should be
+    /// quick and must not call `Router` methods.
+    pub async fn with_message<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(MctpMessage<'_>) -> T,
+    {
+        let mut inner = self.router.lock_inner().await;
+        // OK unwrap: only taken on drop
+        let handle = self.handle.as_ref().unwrap();
+        f(inner.stack.fetch_message(handle))
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Drop for RouterRecvGuard<'_, M, R, LISTENERS, RECEIVERS>
+{
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let r = self.router.state(|s| s.released_handles.push(handle));
+            // Can't overflow, there are only NUM_RECEIVE handles
+            debug_assert!(r.is_ok());
+        }
+    }
+}
+
+/// Confirmation of a forwarded packet.
+///
+/// Returned by [`Router::inbound_confirmed()`] when a packet is
+/// enqueued to a port.
+pub struct ForwardConfirm<
+    'a,
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    router: &'a Router<'r, M, R, LISTENERS, RECEIVERS>,
+    top: &'r PortTop<'r, M>,
+    /// Sequence number of the packet in the port queue
+    seq: u32,
+    /// Router time when the packet was enqueued
+    enqueued: u64,
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > ForwardConfirm<'_, 'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Waits until the destination port has consumed the packet.
+    ///
+    /// Completes once the port's [`PortBottom`] has called
+    /// [`outbound_done()`](PortBottom::outbound_done) for the packet.
+    ///
+    /// `timeout` is in milliseconds from when the packet was enqueued,
+    /// measured by calls to [`Router::update_time()`]. Returns
+    /// [`Error::TimedOut`] if the packet hasn't been consumed by then,
+    /// the packet remains queued. Returns [`Error::TxFailure`] if the
+    /// `PortBottom` is detached.
+    pub async fn wait(self, timeout: u32) -> Result<()> {
+        let deadline = self.enqueued + timeout as u64;
+        let consumed = poll_fn(|cx| {
+            if self.top.shared.poll_consumed(self.seq, cx.waker()) {
+                return Poll::Ready(Ok(()));
+            }
+            if self.top.shared.is_detached() {
+                return Poll::Ready(Err(Error::TxFailure));
+            }
+            Poll::Pending
+        });
+        match select(consumed, self.router.wait_deadline(deadline)).await {
+            Either::First(r) => r,
+            Either::Second(e) => {
+                debug!("Timed out waiting for forward");
+                Err(e)
+            }
+        }
+    }
+}
+
 /// A listener.
 ///
 /// Created with [`Router::listener()`](Router::listener).
-pub struct RouterAsyncListener<'r> {
-    router: &'r Router<'r>,
+pub struct RouterAsyncListener<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
     cookie: AppCookie,
+    typ: MsgType,
 }
 
-impl<'r> mctp::AsyncListener for RouterAsyncListener<'r> {
-    // type RespChannel<'a> = RouterAsyncRespChannel<'a> where Self: 'a;
-    type RespChannel<'a>
-        = RouterAsyncRespChannel<'r>
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Returns the message type this listener is bound to.
+    pub fn msg_type(&self) -> MsgType {
+        self.typ
+    }
+
+    /// Set the requirement for the integrity check bit of received messages.
+    ///
+    /// Messages that don't satisfy `policy` are dropped on arrival.
+    /// The default is [`IcPolicy::Any`].
+    pub fn set_ic_policy(&self, policy: IcPolicy) {
+        self.router.app_listeners.lock(|a| {
+            let mut a = a.borrow_mut();
+            if let Some(Some(bind)) = a.get_mut(self.cookie.0) {
+                bind.ic_policy = policy;
+            } else {
+                debug_assert!(false, "bad listener cookie");
+            }
+        })
+    }
+
+    /// Receive a request and send a reply.
+    ///
+    /// `reply(msg, buf)` is called with the received request, and writes
+    /// the reply payload to `buf`, returning its length. The reply is sent
+    /// to the requester with the request's message type and tag, without
+    /// an integrity check.
+    ///
+    /// This avoids copying the request, and releasing the `Router`'s lock
+    /// between the receive and starting the reply. `reply` is called with
+    /// the lock held, so must be quick and must not call any `Router`
+    /// methods.
+    ///
+    /// Returns [`Error::BadArgument`] if the returned length is larger
+    /// than `buf`, in which case no reply is sent.
+    pub async fn recv_reply<F>(
+        &mut self,
+        buf: &mut [u8],
+        reply: F,
+    ) -> Result<()>
     where
-        Self: 'a;
+        F: FnOnce(&MctpMessage, &mut [u8]) -> usize,
+    {
+        self.router.app_recv_reply(self.cookie, buf, reply).await
+    }
 
-    async fn recv<'f>(
+    /// Handle all messages already waiting for this listener.
+    ///
+    /// `f(typ, source, payload)` is called for each waiting message,
+    /// which is then released. This allows a listener to process its
+    /// queued messages before being dropped, rather than leaving them
+    /// to expire. Responses can't be sent from `f`, which is called
+    /// with the `Router`'s lock held.
+    ///
+    /// Returns the number of messages handled.
+    pub async fn drain<F>(&mut self, f: F) -> usize
+    where
+        F: FnMut(MsgType, Eid, &[u8]),
+    {
+        self.router.app_drain(self.cookie, f).await
+    }
+
+    /// Returns receive counters for this listener.
+    ///
+    /// For a listener attached to a [`prebind()`](Router::prebind),
+    /// counters start from zero when the listener is created.
+    pub fn stats(&self) -> ListenerStats {
+        self.router.app_listeners.lock(|a| {
+            match a.borrow().get(self.cookie.0) {
+                Some(Some(bind)) => bind.stats,
+                _ => {
+                    debug_assert!(false, "bad listener cookie");
+                    ListenerStats::default()
+                }
+            }
+        })
+    }
+
+    /// Set whether the listener also receives broadcast messages.
+    ///
+    /// When enabled, incoming messages of the listener's type
+    /// sent to the broadcast EID ([`MCTP_ADDR_ANY`](mctp::MCTP_ADDR_ANY))
+    /// are received, in addition to any forwarding of the packets
+    /// by the [`PortLookup`]. Responses are sent from the local EID.
+    ///
+    /// The default is disabled.
+    pub fn set_broadcast(&self, enable: bool) {
+        self.router.app_listeners.lock(|a| {
+            let mut a = a.borrow_mut();
+            if let Some(Some(bind)) = a.get_mut(self.cookie.0) {
+                bind.broadcast = enable;
+            } else {
+                debug_assert!(false, "bad listener cookie");
+            }
+        })
+    }
+
+    /// Receive multiple messages into a single buffer.
+    ///
+    /// This waits for a message as for [`recv()`](mctp::AsyncListener::recv),
+    /// then also copies up to `max_msgs` total messages that have
+    /// already been received. It is intended for high rate streams of small
+    /// messages that don't need a response.
+    ///
+    /// Each message payload is written to `buf` preceded by its
+    /// length as a little endian `u16`. Returns the filled portion of
+    /// `buf` and the number of messages.
+    /// If a further message doesn't fit in the remaining space it is left to
+    /// be received by a later call.
+    /// As for `recv()`, if the first message doesn't fit it is discarded
+    /// and `Error::NoSpace` is returned.
+    ///
+    /// Source EID, tag and integrity check are not provided,
+    /// and no response channel is returned.
+    pub async fn recv_coalesced<'f>(
         &mut self,
         buf: &'f mut [u8],
-    ) -> mctp::Result<(&'f mut [u8], Self::RespChannel<'_>, Tag, MsgType, bool)>
-    {
-        let (msg, eid, typ, tag, ic) = self
+        max_msgs: usize,
+    ) -> Result<(&'f mut [u8], usize)> {
+        self.router
+            .app_recv_coalesced(self.cookie, buf, max_msgs)
+            .await
+    }
+
+    /// Receive a message, with an absolute deadline.
+    ///
+    /// `deadline_millis` is compared against the time provided to
+    /// [`Router::update_time()`]. Once that reaches `deadline_millis`,
+    /// `Error::TimedOut` is returned.
+    /// A message that has already arrived is returned even if the
+    /// deadline has passed.
+    ///
+    /// Otherwise behaves as [`recv()`](mctp::AsyncListener::recv).
+    pub async fn recv_deadline<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+        deadline_millis: u64,
+    ) -> Result<(
+        &'f mut [u8],
+        RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>,
+        Tag,
+        MsgType,
+        bool,
+    )> {
+        self.recv_inner(buf, Some(deadline_millis)).await
+    }
+
+    async fn recv_inner<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+        deadline: Option<u64>,
+    ) -> Result<(
+        &'f mut [u8],
+        RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>,
+        Tag,
+        MsgType,
+        bool,
+    )> {
+        let (msg, eid, local_eid, typ, tag, ic) = self
             .router
-            .app_recv_message(Some(self.cookie), None, buf)
+            .app_recv_message(Some(self.cookie), None, buf, deadline)
             .await?;
 
-        let Tag::Owned(tv) = tag else {
-            debug_assert!(false, "listeners only accept owned tags");
-            return Err(Error::InternalError);
-        };
-
+        // Unowned tags are only received by listener_with_unowned()
         let resp = RouterAsyncRespChannel {
             eid,
-            tv,
+            local_eid,
+            tv: tag.tag(),
             router: self.router,
         };
         Ok((msg, resp, tag, typ, ic))
     }
 }
 
-impl Drop for RouterAsyncListener<'_> {
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > mctp::AsyncListener
+    for RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>
+{
+    // type RespChannel<'a> = RouterAsyncRespChannel<'a> where Self: 'a;
+    type RespChannel<'a>
+        = RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>
+    where
+        Self: 'a;
+
+    async fn recv<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+    ) -> mctp::Result<(&'f mut [u8], Self::RespChannel<'_>, Tag, MsgType, bool)>
+    {
+        self.recv_inner(buf, None).await
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Drop for RouterAsyncListener<'_, M, R, LISTENERS, RECEIVERS>
+{
     fn drop(&mut self) {
         if self.router.app_unbind(self.cookie).is_err() {
             // should be infallible, cookie should be valid.
@@ -886,3 +5030,87 @@ impl Drop for RouterAsyncListener<'_> {
         }
     }
 }
+
+/// Returns `Error::BadArgument` if `mtu` can't hold a header and payload,
+/// or exceeds `MAX_MTU`.
+fn check_mtu(mtu: usize) -> Result<()> {
+    if !(HEADER_LEN + 1..=MAX_MTU).contains(&mtu) {
+        debug!("Bad mtu {}", mtu);
+        return Err(Error::BadArgument);
+    }
+    Ok(())
+}
+
+/// Returns an error response packet for an MCTP Control request packet,
+/// sent from `source`.
+///
+/// Returns `None` if `pkt` isn't the first packet of a Control request.
+fn control_error_response(
+    pkt: &[u8],
+    source: Eid,
+) -> Option<Vec<u8, { HEADER_LEN + 4 }>> {
+    let hdr = crate::peek_header(pkt).ok()?;
+    if !hdr.som || !hdr.tag.is_owner() {
+        return None;
+    }
+    // Type, then Control header
+    let (typ, _ic) = mctp::decode_type_ic(*pkt.get(HEADER_LEN)?);
+    let ctrl: [u8; 2] =
+        pkt.get(HEADER_LEN + 1..HEADER_LEN + 3)?.try_into().ok()?;
+    // Rq set, D clear
+    if typ != mctp::MCTP_TYPE_CONTROL || ctrl[0] & 0xc0 != 0x80 {
+        return None;
+    }
+
+    let mut header = crate::Header::new(mctp::MCTP_HEADER_VERSION_1);
+    header.set_dest_endpoint_id(hdr.source.0);
+    header.set_source_endpoint_id(source.0);
+    header.set_som(1);
+    header.set_eom(1);
+    header.set_msg_tag(hdr.tag.tag().0);
+    header.set_to(0);
+
+    let mut resp = Vec::new();
+    // OK unwrap: sized to fit
+    resp.extend_from_slice(&header.0).unwrap();
+    resp.extend_from_slice(&[
+        mctp::encode_type_ic(typ, false),
+        ctrl[0] & !0x80,
+        ctrl[1],
+        libmctp::control_packet::CompletionCode::Error as u8,
+    ])
+    .unwrap();
+    Some(resp)
+}
+
+/// Calls the `on_send_progress` hook after a packet is sent.
+fn send_progress(hooks: Option<&dyn RouterHooks>, fragmenter: &Fragmenter) {
+    if let Some(hooks) = hooks {
+        hooks.on_send_progress(
+            fragmenter.dest(),
+            fragmenter.tag(),
+            fragmenter.remaining(),
+        );
+    }
+}
+
+/// Copies from a vectored payload starting at `offset`.
+///
+/// Returns the number of bytes written to `out`.
+fn copy_vectored(bufs: &[&[u8]], mut offset: usize, out: &mut [u8]) -> usize {
+    let mut len = 0;
+    for b in bufs {
+        if len == out.len() {
+            break;
+        }
+        let Some(b) = b.get(offset..) else {
+            offset -= b.len();
+            continue;
+        };
+        offset = 0;
+        let l = b.len().min(out.len() - len);
+        out[len..][..l].copy_from_slice(&b[..l]);
+        len += l;
+    }
+    len
+}
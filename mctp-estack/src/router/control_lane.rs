@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Priority lane for MCTP Control messages, see
+//! `Router::set_control_lane()`
+
+use super::*;
+
+/// Number of forwarded multi-packet control messages tracked per port
+/// for the control lane
+pub(super) const MAX_CONTROL_FLOWS: usize = 4;
+
+impl<'a, M: RawMutex> PortTop<'a, M> {
+    /// Returns true if `control` traffic must leave the last queue
+    /// slot free.
+    pub(super) fn reserve_last(&self, control: bool) -> bool {
+        !control && self.queue_capacity > 1 && self.settings(|s| s.control_lane)
+    }
+
+    /// Locks the queue, waiting until more than one slot is free
+    /// with `reserve_last`.
+    pub(super) async fn lock_sender(
+        &self,
+        reserve_last: bool,
+    ) -> SenderGuard<'_, 'a, M> {
+        loop {
+            let sender = self.packets.lock().await;
+            if !reserve_last
+                || sender.len() + 1 < self.queue_capacity
+                || self.shared.is_detached()
+            {
+                return sender;
+            }
+            // Leave the last slot for control messages,
+            // wait for the PortBottom to consume a packet.
+            let seq = self.shared.consumed_count().wrapping_add(1);
+            drop(sender);
+            poll_fn(|cx| {
+                if self.shared.poll_consumed(seq, cx.waker())
+                    || self.shared.is_detached()
+                {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Returns true if a forwarded packet is part of an MCTP Control
+    /// message.
+    ///
+    /// Only the first packet carries the message type, so multi-packet
+    /// control messages are tracked until their last packet.
+    pub(super) fn is_control_packet(&self, pkt: &[u8]) -> bool {
+        let Ok(h) = peek_header(pkt) else {
+            return false;
+        };
+        let key = (h.source, h.dest, h.tag);
+        self.state(|s| {
+            let f = &mut s.control_flows;
+            let pos = f.iter().position(|k| *k == key);
+            if h.som {
+                // A new message replaces an unfinished one
+                if let Some(pos) = pos {
+                    f.remove(pos);
+                }
+                if !is_control_start(pkt) {
+                    return false;
+                }
+                if !h.eom {
+                    if f.is_full() {
+                        f.remove(0);
+                    }
+                    let _ = f.push(key);
+                }
+                true
+            } else if let Some(pos) = pos {
+                if h.eom {
+                    f.remove(pos);
+                }
+                true
+            } else {
+                false
+            }
+        })
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Router<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Give MCTP Control messages a priority lane.
+    ///
+    /// When enabled, control messages (type 0) make progress while other
+    /// traffic saturates the `Router`:
+    ///
+    /// - A reassembly slot is kept for incoming control messages,
+    ///   see [`Stack::set_control_reserve()`].
+    /// - Listeners receiving several types, such as
+    ///   [`recv_any()`](Self::recv_any), receive waiting control messages
+    ///   ahead of other types, regardless of
+    ///   [`set_type_priority()`](Self::set_type_priority).
+    /// - Other traffic leaves the last slot of each port queue free.
+    ///   Sends wait for a second free slot, and forwarded packets are
+    ///   dropped if only one slot is free. All packets of a forwarded
+    ///   control message may use the last slot.
+    ///
+    /// Ports with [atomic messages](Self::set_port_atomic_messages)
+    /// hold the queue for a whole message, so control messages
+    /// may still wait behind them.
+    pub async fn set_control_lane(&self, enable: bool) {
+        let mut inner = self.lock_inner().await;
+        inner.control_lane = enable;
+        inner.stack.set_control_reserve(enable);
+        for top in self.ports {
+            top.settings(|s| s.control_lane = enable);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Forwarding loop detection, see `Router::set_max_forwards()`
+
+use super::*;
+
+/// Milliseconds that a forwarded packet is tracked for loop detection
+const FORWARD_LOOP_EXPIRY: u64 = 1000;
+
+/// A recently forwarded packet, for loop detection.
+#[derive(Debug)]
+pub(super) struct ForwardSeen {
+    /// Source EID of the packet
+    source: Eid,
+    /// Tag of the packet
+    tag: Tag,
+    /// Packet sequence number
+    seq: u8,
+    /// Times a matching packet has been forwarded
+    count: u8,
+    /// Router time when the packet was first forwarded
+    first: u64,
+}
+
+impl<R: ReassemblyPool, const LISTENERS: usize, const RECEIVERS: usize>
+    RouterInner<'_, R, LISTENERS, RECEIVERS>
+{
+    /// Records a packet to forward, returning true if a packet with the
+    /// same source, tag and sequence number has been forwarded more than
+    /// `max_forwards` times recently.
+    ///
+    /// `now` is the router's clock, entries expire
+    /// `FORWARD_LOOP_EXPIRY` after they were first seen.
+    pub(super) fn forward_loop(&mut self, pkt: &[u8], now: u64) -> bool {
+        let Some(max) = self.max_forwards else {
+            return false;
+        };
+        if FORWARD_LOOP_WINDOW == 0 {
+            return false;
+        }
+        let Ok(PacketHeader {
+            source, tag, seq, ..
+        }) = peek_header(pkt)
+        else {
+            return false;
+        };
+        self.forward_seen
+            .retain(|f| now.saturating_sub(f.first) < FORWARD_LOOP_EXPIRY);
+
+        let pos = self
+            .forward_seen
+            .iter()
+            .position(|f| f.source == source && f.tag == tag && f.seq == seq);
+        let entry = match pos {
+            Some(i) => {
+                let mut f = self.forward_seen.remove(i);
+                f.count = f.count.saturating_add(1);
+                f
+            }
+            None => {
+                if self.forward_seen.is_full() {
+                    self.forward_seen.remove(0);
+                }
+                ForwardSeen {
+                    source,
+                    tag,
+                    seq,
+                    count: 1,
+                    first: now,
+                }
+            }
+        };
+        let count = entry.count;
+        // OK unwrap: space was made above
+        self.forward_seen.push(entry).unwrap();
+        count > max
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Router<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Limit how often the same packet may be forwarded.
+    ///
+    /// MCTP headers have no hop limit, so a misconfigured [`PortLookup`]
+    /// can route a packet around a loop indefinitely. When set, the
+    /// `Router` tracks the most recent
+    /// [`FORWARD_LOOP_WINDOW`](crate::config::FORWARD_LOOP_WINDOW)
+    /// forwarded packets, keyed by source EID, tag and packet sequence
+    /// number. A packet matching one that has already been forwarded
+    /// `max` times within one second of the first forward is dropped,
+    /// and counted in [`RouterStats::forward_dropped_loop`] and the
+    /// incoming port's [`PortStats::dropped_loop`]. Time is taken from
+    /// [`update_time()`](Self::update_time).
+    ///
+    /// This is a heuristic. The sequence number is only two bits, so
+    /// the packets of a message longer than four packets share keys,
+    /// and a requester retrying a request with the same tag repeats
+    /// keys too. `max` must allow for those repeats, otherwise
+    /// legitimate packets are dropped for the rest of the second.
+    ///
+    /// The default `None` doesn't track forwarded packets.
+    pub async fn set_max_forwards(&self, max: Option<u8>) {
+        let mut inner = self.lock_inner().await;
+        inner.max_forwards = max;
+        inner.forward_seen.clear();
+    }
+}
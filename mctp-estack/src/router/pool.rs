@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Receive buffer pool for `RouterAsyncListener::recv_pooled()`
+
+use super::*;
+
+/// Maximum buffers in a `Router`'s receive pool,
+/// see [`Router::set_recv_pool()`]
+pub const MAX_RECV_POOL: usize = 8;
+
+/// Storage for receive buffers managed by a [`Router`].
+///
+/// Provided with [`Router::set_recv_pool()`], and used by
+/// [`RouterAsyncListener::recv_pooled()`]. Each buffer holds a
+/// message of up to [`MAX_PAYLOAD`] bytes.
+pub struct RecvPool<const N: usize = 2> {
+    bufs: [[u8; MAX_PAYLOAD]; N],
+}
+
+impl<const N: usize> RecvPool<N> {
+    /// Create a new `RecvPool`.
+    ///
+    /// As for [`PortStorage::new()`] this is a `const fn`, so the pool
+    /// can be placed in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            bufs: [[0u8; MAX_PAYLOAD]; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for RecvPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Buffers of a `Router`'s receive pool.
+// Not a heapless::Vec, since its Drop would prevent `&'r Router<'r>`
+// borrows.
+pub(super) struct RecvPoolSlots<'r> {
+    /// Free buffers
+    bufs: [Option<&'r mut [u8; MAX_PAYLOAD]>; MAX_RECV_POOL],
+    /// Buffers held by a `PooledMessage`
+    lent: usize,
+}
+
+impl RecvPoolSlots<'_> {
+    pub(super) const fn new() -> Self {
+        Self {
+            bufs: [const { None }; MAX_RECV_POOL],
+            lent: 0,
+        }
+    }
+
+    /// Returns the number of buffers, both free and lent.
+    pub(super) fn total(&self) -> usize {
+        self.bufs.iter().filter(|b| b.is_some()).count() + self.lent
+    }
+}
+
+/// A received message in a buffer from the `Router`'s receive pool.
+///
+/// Returned by [`RouterAsyncListener::recv_pooled()`]. Dereferences to
+/// the message payload. The buffer is returned to the pool when this
+/// is dropped. Unlike [`RouterRecvGuard`] it doesn't pin a
+/// reassembly slot.
+pub struct PooledMessage<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    // Always Some, taken on drop
+    buf: Option<&'r mut [u8; MAX_PAYLOAD]>,
+    len: usize,
+    typ: MsgType,
+    tag: Tag,
+    ic: bool,
+    router: &'r Router<'r, M, R, LISTENERS, RECEIVERS>,
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > PooledMessage<'_, M, R, LISTENERS, RECEIVERS>
+{
+    /// Returns the message type.
+    pub fn typ(&self) -> MsgType {
+        self.typ
+    }
+
+    /// Returns the message tag.
+    pub fn tag(&self) -> Tag {
+        self.tag
+    }
+
+    /// Returns the integrity check bit.
+    pub fn ic(&self) -> bool {
+        self.ic
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > core::ops::Deref for PooledMessage<'_, M, R, LISTENERS, RECEIVERS>
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // OK unwrap: only taken on drop
+        &self.buf.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl<
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Drop for PooledMessage<'_, M, R, LISTENERS, RECEIVERS>
+{
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.router.put_pool_buffer(buf);
+        }
+    }
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > Router<'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Provide buffers for [`RouterAsyncListener::recv_pooled()`].
+    ///
+    /// The buffers of `pool` are added to the `Router`'s receive pool,
+    /// and remain borrowed for the `Router`'s lifetime.
+    ///
+    /// Returns `Error::NoSpace` if the pool would exceed
+    /// [`MAX_RECV_POOL`] buffers, in which case no buffers are added.
+    /// Buffers held by a [`PooledMessage`] are included in the count.
+    pub fn set_recv_pool<const N: usize>(
+        &self,
+        pool: &'r mut RecvPool<N>,
+    ) -> Result<()> {
+        self.recv_pool.lock(|p| {
+            let mut p = p.borrow_mut();
+            // Lent buffers need a free slot when they are returned
+            if p.total() + N > MAX_RECV_POOL {
+                return Err(Error::NoSpace);
+            }
+            let empty = p.bufs.iter_mut().filter(|b| b.is_none());
+            for (slot, b) in empty.zip(pool.bufs.iter_mut()) {
+                *slot = Some(b);
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the number of free buffers in the receive pool.
+    ///
+    /// See [`set_recv_pool()`](Self::set_recv_pool).
+    pub fn recv_pool_free(&self) -> usize {
+        self.recv_pool
+            .lock(|p| p.borrow().bufs.iter().filter(|b| b.is_some()).count())
+    }
+
+    /// Takes a free buffer from the receive pool.
+    fn take_pool_buffer(&self) -> Option<&'r mut [u8; MAX_PAYLOAD]> {
+        self.recv_pool.lock(|p| {
+            let mut p = p.borrow_mut();
+            let buf = p.bufs.iter_mut().find_map(|b| b.take())?;
+            p.lent += 1;
+            Some(buf)
+        })
+    }
+
+    /// Returns a buffer to the receive pool.
+    fn put_pool_buffer(&self, buf: &'r mut [u8; MAX_PAYLOAD]) {
+        self.recv_pool.lock(|p| {
+            let mut p = p.borrow_mut();
+            p.lent -= 1;
+            // OK unwrap: set_recv_pool() leaves a slot for each lent buffer
+            let slot = p.bufs.iter_mut().find(|b| b.is_none()).unwrap();
+            *slot = Some(buf);
+        })
+    }
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Receive a message into a buffer from the `Router`'s receive pool.
+    ///
+    /// This behaves as [`recv()`](mctp::AsyncListener::recv), but the
+    /// application doesn't need to provide a buffer. The returned
+    /// [`PooledMessage`] holds the buffer until it is dropped.
+    ///
+    /// Returns `Error::NoSpace` if no pool buffer is free when a message
+    /// arrives, or no pool was set with [`Router::set_recv_pool()`].
+    /// The message is left to be received later.
+    pub async fn recv_pooled(
+        &mut self,
+    ) -> Result<(
+        PooledMessage<'r, M, R, LISTENERS, RECEIVERS>,
+        RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>,
+    )> {
+        let router = self.router;
+        let (mut inner, handle) =
+            router.app_recv_handle(Some(self.cookie), None).await?;
+
+        let Some(buf) = router.take_pool_buffer() else {
+            debug!("No free receive pool buffer");
+            inner.stack.return_handle(handle);
+            return Err(Error::NoSpace);
+        };
+
+        let msg = inner.stack.fetch_message(&handle);
+        let len = msg.payload.len();
+        buf[..len].copy_from_slice(msg.payload);
+        let resp = RouterAsyncRespChannel {
+            eid: msg.source,
+            local_eid: msg.dest,
+            tv: msg.tag.tag(),
+            router,
+        };
+        let msg = PooledMessage {
+            buf: Some(buf),
+            len,
+            typ: msg.typ,
+            tag: msg.tag,
+            ic: msg.ic,
+            router,
+        };
+        inner.stack.finished_receive(handle);
+        drop(inner);
+        router.update_listener_stats(self.cookie, |s| {
+            s.received = s.received.wrapping_add(1)
+        });
+        Ok((msg, resp))
+    }
+}
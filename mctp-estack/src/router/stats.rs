@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Router and port counters
+
+#[cfg(doc)]
+use super::{PortBottom, PortLookup, Router, RouterAsyncListener, RouterHooks};
+
+/// Traffic counters for a [`Router`].
+///
+/// Returned by [`Router::stats()`]. Counters wrap on overflow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RouterStats {
+    /// Packets forwarded to a port
+    pub forwarded_total: u32,
+    /// Complete messages received for local EIDs
+    pub local_delivered_total: u32,
+    /// Packets dropped because the destination port's
+    /// [`PortBottom`] was detached
+    pub forward_dropped_detached: u32,
+    /// Peak number of request and response channels waiting
+    /// in `recv()` at once. The limit is the `RECEIVERS` parameter
+    /// of the [`Router`].
+    pub receivers_peak: u32,
+    /// Link-layer errors for all ports, reported with
+    /// [`Router::note_link_error()`]
+    pub link_errors: u32,
+    /// Packets to forward that were dropped since their source was
+    /// the local EID, see [`Router::set_drop_self_source()`]
+    pub forward_dropped_self_source: u32,
+    /// Packets to forward that were dropped as a likely forwarding
+    /// loop, see [`Router::set_max_forwards()`]
+    pub forward_dropped_loop: u32,
+    /// Packets to forward that were dropped since the destination EID
+    /// was outside the port's range, see [`Router::set_port_eid_range()`]
+    pub forward_dropped_range: u32,
+    /// Packets to forward that were dropped since the destination
+    /// port was disabled, see [`Router::set_port_enabled()`]
+    pub forward_dropped_disabled: u32,
+    /// Local packets dropped since they didn't have SOM set and
+    /// didn't continue a message being reassembled
+    pub local_dropped_no_som: u32,
+    /// Incoming packets copied to a mirror port, see
+    /// [`PortLookup::mirror()`]
+    pub mirrored: u32,
+    /// Incoming packets that couldn't be copied to a mirror port
+    pub mirror_dropped: u32,
+    /// Complete request messages for local EIDs dropped since no
+    /// listener was bound, see [`RouterHooks::on_no_listener()`]
+    pub local_no_listener: u32,
+    /// Number of tasks currently waiting for the router's internal lock.
+    /// This is not cleared by [`Router::reset_stats()`].
+    pub inner_waiting: u32,
+    /// Peak of `inner_waiting`. High values indicate contention
+    /// between tasks using the router.
+    pub inner_waiting_peak: u32,
+}
+
+/// Traffic counters for a port.
+///
+/// Returned by [`Router::port_stats()`]. Counters wrap on overflow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PortStats {
+    /// Packets enqueued to the port, both sent and forwarded
+    pub tx_packets: u32,
+    /// Bytes of `tx_packets`, including MCTP headers
+    pub tx_bytes: u64,
+    /// Incoming packets with a valid header that arrived on the port
+    pub rx_packets: u32,
+    /// Packets forwarded to the port
+    pub forwarded: u32,
+    /// Incoming packets that arrived on the port and were dropped
+    /// since they had no route
+    pub dropped_noroute: u32,
+    /// Packets dropped since the port's queue was full
+    pub dropped_queue_full: u32,
+    /// Packets dropped since they were larger than the port's MTU
+    pub dropped_too_large: u32,
+    /// Incoming packets that arrived on the port and were dropped
+    /// as a forwarding loop, see [`Router::set_max_forwards()`]
+    pub dropped_loop: u32,
+}
+
+/// Time that packets waited in a port's queue.
+///
+/// This is measured from when a packet is enqueued until the port's
+/// consumer calls [`PortBottom::outbound_done()`], using the clock from
+/// [`Router::update_time()`]. High values indicate a slow transport
+/// driver, rather than delays in the transport itself.
+///
+/// Returned by [`Router::port_latency()`]. Times are milliseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DequeueLatency {
+    /// Packets consumed with latency recorded
+    pub count: u32,
+    /// Sum of latencies
+    pub total: u64,
+    /// The largest latency
+    pub max: u64,
+}
+
+impl DequeueLatency {
+    /// Returns the mean latency, or `None` if no packets are counted.
+    pub fn mean(&self) -> Option<u64> {
+        (self.count > 0).then(|| self.total / self.count as u64)
+    }
+
+    pub(super) fn record(&mut self, elapsed: u64) {
+        self.count = self.count.wrapping_add(1);
+        self.total = self.total.wrapping_add(elapsed);
+        self.max = self.max.max(elapsed);
+    }
+}
+
+/// A link-layer error detected by a transport binding.
+///
+/// Reported with [`Router::note_link_error()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkError {
+    /// Bad checksum or CRC
+    Crc,
+    /// Bad framing, such as a malformed frame delimiter or escape
+    Framing,
+    /// Incoming data was lost, such as a receive overrun
+    Overrun,
+    /// Any other error
+    Other,
+}
+
+/// Link-layer error counters for a port.
+///
+/// Returned by [`Router::port_link_errors()`]. Counters wrap on overflow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LinkErrorStats {
+    /// [`LinkError::Crc`] count
+    pub crc: u32,
+    /// [`LinkError::Framing`] count
+    pub framing: u32,
+    /// [`LinkError::Overrun`] count
+    pub overrun: u32,
+    /// [`LinkError::Other`] count
+    pub other: u32,
+}
+
+/// Receive counters for a [`RouterAsyncListener`].
+///
+/// Returned by [`RouterAsyncListener::stats()`]. Counters wrap on overflow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerStats {
+    /// Messages received
+    pub received: u32,
+    /// Messages discarded since they didn't fit the receive buffer
+    pub too_small: u32,
+    /// Messages dropped on arrival since the queue of a
+    /// [buffered listener](Router::listener_buffered) was full
+    pub queue_dropped: u32,
+}
+
+/// Number of buckets in [`PacketSizes`]
+pub const PACKET_SIZE_BUCKETS: usize = 6;
+
+/// Histogram of packet sizes enqueued for a port.
+///
+/// Returned by [`Router::port_packet_sizes()`]. Sizes include the
+/// MCTP header. Counters wrap on overflow.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PacketSizes {
+    /// Packet counts by size.
+    ///
+    /// Bucket `i` counts packets up to
+    /// [`bucket_limit(i)`](Self::bucket_limit) bytes, that are larger
+    /// than the previous bucket's limit: up to 16 bytes, 32 bytes, and
+    /// so on. The last bucket counts packets larger than 256 bytes.
+    pub buckets: [u32; PACKET_SIZE_BUCKETS],
+    /// The largest packet size
+    pub max: usize,
+}
+
+impl PacketSizes {
+    /// Returns the largest packet size counted in bucket `i`.
+    ///
+    /// Returns `None` for the last bucket, which is unbounded.
+    pub fn bucket_limit(i: usize) -> Option<usize> {
+        (i + 1 < PACKET_SIZE_BUCKETS).then(|| 16 << i)
+    }
+
+    pub(super) fn record(&mut self, len: usize) {
+        let i = (0..PACKET_SIZE_BUCKETS)
+            .find(|&i| Self::bucket_limit(i).is_none_or(|l| len <= l))
+            .unwrap_or(PACKET_SIZE_BUCKETS - 1);
+        self.buckets[i] = self.buckets[i].wrapping_add(1);
+        self.max = self.max.max(len);
+    }
+}
@@ -0,0 +1,3908 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+use super::*;
+use crate::test_util::{
+    block_on_with, drain_port, fuzz_inbound, PacketBuilder,
+};
+use embassy_futures::{block_on, join::join, yield_now};
+use mctp::AsyncReqChannel;
+
+fn start_log() {
+    let _ = env_logger::Builder::new()
+        .filter(None, log::LevelFilter::Trace)
+        .is_test(true)
+        .try_init();
+}
+
+/// Routes every EID to a single port
+struct DefaultRoute(Option<PortId>);
+
+impl PortLookup for DefaultRoute {
+    fn by_eid(
+        &mut self,
+        _eid: Eid,
+        _source_port: Option<PortId>,
+    ) -> Option<PortId> {
+        self.0
+    }
+}
+
+/// Creates `router` with local EID 10 and a single port `bottom`,
+/// with a queue of `$queue` packets. All EIDs route to the port.
+macro_rules! one_port_router {
+    ($router:ident, $bottom:pat, $queue:literal, $mtu:literal) => {
+        let mut storage = PortStorage::<$queue>::new();
+        let mut pb = PortBuilder::new(&mut storage);
+        let (top, $bottom) = pb.build($mtu).unwrap();
+        let ports = [top];
+        let mut lookup = DefaultRoute(Some(PortId(0)));
+        let stack = Stack::new(Eid(10), $mtu, 0);
+        let $router: Router = Router::new(stack, &ports, &mut lookup);
+    };
+}
+
+/// A waker that counts its wakeups
+#[derive(Default)]
+struct CountWake(core::sync::atomic::AtomicUsize);
+
+impl CountWake {
+    fn count(&self) -> usize {
+        self.0.load(core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl std::task::Wake for CountWake {
+    fn wake(self: std::sync::Arc<Self>) {
+        self.0.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn send_yields() {
+    start_log();
+    one_port_router!(router, bottom, 64, 64);
+
+    // 17 packets at 64 MTU
+    let payload = [0x55u8; 1000];
+    let mut req = router.req(Eid(20));
+    let mut observed = None;
+    let (r, _) = block_on(join(req.send(MsgType(0x10), &payload), async {
+        yield_now().await;
+        observed = Some(bottom.packets.len());
+    }));
+    r.unwrap();
+
+    // The other task ran before the send completed
+    let observed = observed.unwrap();
+    assert!(observed > 0);
+    assert!(observed < 17);
+    assert_eq!(bottom.packets.len(), 17);
+}
+
+/// Returns a single-packet message
+fn packet(
+    src: Eid,
+    dest: Eid,
+    tag: Tag,
+    typ: MsgType,
+    payload: &[u8],
+) -> Vec<u8, MAX_MTU> {
+    PacketBuilder::new(src, dest, tag, typ)
+        .build(payload)
+        .unwrap()
+}
+
+#[derive(Default)]
+struct ReleaseHooks(std::sync::Mutex<std::vec::Vec<(Eid, TagValue)>>);
+
+impl RouterHooks for ReleaseHooks {
+    fn on_tag_release(&self, eid: Eid, tag: TagValue) {
+        self.0.lock().unwrap().push((eid, tag));
+    }
+}
+
+#[test]
+fn tag_release_hook() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let hooks = ReleaseHooks::default();
+    let typ = MsgType(0x10);
+
+    let released = || core::mem::take(&mut *hooks.0.lock().unwrap());
+
+    block_on(async {
+        router.set_hooks(Some(&hooks)).await;
+
+        // Response completion
+        let mut req = router.req(Eid(20));
+        req.send(typ, &[1]).await.unwrap();
+        let tv = req.sent_tag.unwrap().tag();
+        bottom.outbound_done();
+        assert!(released().is_empty());
+        let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[2]);
+        router.inbound(&resp, PortId(0)).await;
+        assert_eq!(released(), [(Eid(20), tv)]);
+
+        // Expiry
+        let mut req = router.req(Eid(21));
+        req.send(typ, &[1]).await.unwrap();
+        let tv = req.sent_tag.unwrap().tag();
+        bottom.outbound_done();
+        router.update_time(1).await.unwrap();
+        assert!(released().is_empty());
+        router.update_time(100_000).await.unwrap();
+        assert_eq!(released(), [(Eid(21), tv)]);
+
+        // Cancel
+        let mut req = router.req(Eid(22));
+        req.tag_noexpire().unwrap();
+        req.send(typ, &[1]).await.unwrap();
+        let tv = req.sent_tag.unwrap().tag();
+        bottom.outbound_done();
+        router.update_time(200_000).await.unwrap();
+        assert!(released().is_empty());
+        req.async_drop().await;
+        assert_eq!(released(), [(Eid(22), tv)]);
+    });
+}
+
+#[test]
+fn small_scratch() {
+    start_log();
+    let mut storage = PortStorage::<8, 16>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+
+    block_on(async {
+        let mut req = router.req(Eid(20));
+        let r = req.send_vectored(typ, false, &[&[1; 10], &[2; 10]]).await;
+        assert!(matches!(r, Err(Error::NoSpace)));
+        assert!(bottom.try_outbound().is_none());
+
+        // Fits in scratch
+        let mut req = router.req(Eid(20));
+        req.send_vectored(typ, false, &[&[1; 8], &[2; 8]])
+            .await
+            .unwrap();
+        bottom.outbound_done();
+
+        // Single slices don't use scratch
+        let mut req = router.req(Eid(20));
+        req.send(typ, &[3; 20]).await.unwrap();
+        let (pkt, _dest) = bottom.outbound().await;
+        assert_eq!(pkt.len(), HEADER_LEN + 1 + 20);
+    });
+}
+
+#[test]
+fn forward_confirm() {
+    start_log();
+    let mut storage0 = PortStorage::<4>::new();
+    let mut storage1 = PortStorage::<4>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, _bottom0) = pb0.build(255).unwrap();
+    let (top1, mut bottom1) = pb1.build(255).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = DefaultRoute(Some(PortId(1)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let pkt = packet(Eid(20), Eid(30), Tag::Owned(TagValue(1)), typ, &[1]);
+
+    let events = RefCell::new(std::vec::Vec::new());
+    block_on(async {
+        // Consumed
+        let (src, confirm) = router.inbound_confirmed(&pkt, PortId(0)).await;
+        assert_eq!(src, Some(Eid(20)));
+        join(
+            async {
+                confirm.unwrap().wait(1000).await.unwrap();
+                events.borrow_mut().push("forwarded");
+            },
+            async {
+                for _ in 0..3 {
+                    yield_now().await;
+                }
+                bottom1.outbound().await;
+                events.borrow_mut().push("consumed");
+                bottom1.outbound_done();
+            },
+        )
+        .await;
+        assert_eq!(*events.borrow(), ["consumed", "forwarded"]);
+        events.borrow_mut().clear();
+
+        // Other packets aren't blocked by a pending confirmation
+        let (_, confirm) = router.inbound_confirmed(&pkt, PortId(0)).await;
+        let (_, confirm2) = router.inbound_confirmed(&pkt, PortId(0)).await;
+        assert!(confirm2.is_some());
+
+        // Timeout with no consumer
+        join(
+            async {
+                let r = confirm.unwrap().wait(1000).await;
+                assert!(matches!(r, Err(Error::TimedOut)));
+                events.borrow_mut().push("timeout");
+            },
+            async {
+                router.update_time(500).await.unwrap();
+                yield_now().await;
+                events.borrow_mut().push("500");
+                router.update_time(1001).await.unwrap();
+            },
+        )
+        .await;
+        assert_eq!(*events.borrow(), ["500", "timeout"]);
+        assert_eq!(drain_port(&mut bottom1, |_, _| ()), 2);
+
+        // Not forwarded
+        let local =
+            packet(Eid(20), Eid(10), Tag::Owned(TagValue(1)), typ, &[1]);
+        let (_, confirm) = router.inbound_confirmed(&local, PortId(0)).await;
+        assert!(confirm.is_none());
+    });
+}
+
+#[test]
+fn suggested_interval() {
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+
+    block_on(async {
+        // Not updated yet
+        assert_eq!(router.suggested_interval().await, 0);
+
+        let next = router.update_time(10).await.unwrap();
+        assert_eq!(next, crate::TIMEOUT_INTERVAL as u64);
+        assert_eq!(router.suggested_interval().await, next);
+
+        // Skipped checks within the interval return the remainder
+        let next = router.update_time(60).await.unwrap();
+        assert_eq!(next, 50);
+        assert_eq!(router.suggested_interval().await, next);
+    });
+}
+
+#[test]
+fn receivers_overflow() {
+    use core::task::Context;
+
+    start_log();
+    let mut storage = PortStorage::<4>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    const RECEIVERS: usize = 4;
+    let router: Router<'_, _, _, 20, RECEIVERS> =
+        Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+
+    const N: usize = RECEIVERS + 1;
+    let mut reqs = std::vec::Vec::new();
+    for i in 0..N {
+        // 8 tags per EID
+        let mut req = router.req(Eid(20 + (i / 8) as u8));
+        block_on(req.send(typ, &[1])).unwrap();
+        bottom.outbound_done();
+        reqs.push(req);
+    }
+    let tags = reqs
+        .iter()
+        .map(|r| (r.eid, r.sent_tag.unwrap().tag()))
+        .collect::<std::vec::Vec<_>>();
+
+    let mut bufs = [[0u8; 4]; N];
+    let mut futs = reqs
+        .iter_mut()
+        .zip(bufs.iter_mut())
+        .map(|(r, b)| Box::pin(r.recv(b)))
+        .collect::<std::vec::Vec<_>>();
+
+    let waker = Waker::from(std::sync::Arc::new(CountWake::default()));
+    let mut cx = Context::from_waker(&waker);
+    for f in futs.iter_mut().take(RECEIVERS) {
+        assert!(f.as_mut().poll(&mut cx).is_pending());
+    }
+    // The excess receiver fails rather than losing a wakeup
+    let r = futs[RECEIVERS].as_mut().poll(&mut cx);
+    assert!(matches!(r, Poll::Ready(Err(Error::NoSpace))));
+
+    // Responses are delivered to the waiting receivers
+    for (i, (eid, tv)) in tags.iter().enumerate().take(RECEIVERS) {
+        let resp = packet(*eid, Eid(10), Tag::Unowned(*tv), typ, &[2]);
+        block_on(router.inbound(&resp, PortId(0)));
+        let r = futs[i].as_mut().poll(&mut cx);
+        let Poll::Ready(Ok((buf, ..))) = r else {
+            panic!("no response for {i}");
+        };
+        assert_eq!(buf, &[2]);
+    }
+}
+
+#[test]
+fn baseline_mtu() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+
+    let payload = [0x33u8; 200];
+    block_on(async {
+        // Peer has no EID assigned yet
+        let mut req = router.req(mctp::MCTP_ADDR_NULL);
+        req.send(MsgType(0x10), &payload).await.unwrap();
+        let (pkt, dest) = bottom.outbound().await;
+        assert_eq!(dest, mctp::MCTP_ADDR_NULL);
+        assert_eq!(pkt.len(), HEADER_LEN + mctp::MCTP_MIN_MTU);
+        while bottom.try_outbound().is_some() {
+            bottom.outbound_done();
+        }
+
+        // Assigned EIDs use the port MTU
+        let mut req = router.req(Eid(20));
+        req.send(MsgType(0x10), &payload).await.unwrap();
+        let (pkt, _dest) = bottom.outbound().await;
+        assert_eq!(pkt.len(), HEADER_LEN + 1 + payload.len());
+    });
+}
+
+#[test]
+fn unassigned_null_send() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(mctp::MCTP_ADDR_NULL, 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = mctp::MCTP_TYPE_CONTROL;
+
+    // Discovery before either side has an EID goes to the wire,
+    // rather than looping back to the unassigned local EID
+    let payload = [0x33u8; 100];
+    let mut req = router.req(mctp::MCTP_ADDR_NULL);
+    block_on(req.send(typ, &payload)).unwrap();
+    let mut n = 0;
+    drain_port(&mut bottom, |pkt, dest| {
+        assert_eq!(dest, mctp::MCTP_ADDR_NULL);
+        assert!(pkt.len() <= HEADER_LEN + mctp::MCTP_MIN_MTU);
+        n += 1;
+    });
+    assert_eq!(n, 2);
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+    assert_eq!(
+        block_on(router.max_payload(mctp::MCTP_ADDR_NULL, typ)),
+        Some(MAX_PAYLOAD)
+    );
+}
+
+#[test]
+fn listener_unowned() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let tv = TagValue(3);
+
+    // A normal listener doesn't receive unowned messages
+    let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[1]);
+    let l = router.listener(typ).unwrap();
+    block_on(router.inbound(&resp, PortId(0)));
+    drop(l);
+
+    let mut l = router.listener_with_unowned(typ).unwrap();
+    let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[2]);
+    block_on(async {
+        let mut buf = [0u8; 10];
+        let (r, _) =
+            join(l.recv(&mut buf), router.inbound(&resp, PortId(0))).await;
+        let (msg, mut ch, tag, rtyp, _ic) = r.unwrap();
+        assert_eq!(msg, &[2]);
+        assert_eq!(tag, Tag::Unowned(tv));
+        assert_eq!(rtyp, typ);
+
+        // Reply uses the same unowned tag
+        ch.send(typ, &[3]).await.unwrap();
+        let (pkt, dest) = bottom.outbound().await;
+        assert_eq!(dest, Eid(20));
+        let hdr = crate::peek_header(pkt).unwrap();
+        assert_eq!(hdr.tag, Tag::Unowned(tv));
+        bottom.outbound_done();
+    });
+}
+
+#[test]
+fn snapshot() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    let _l = router.listener(MsgType(0x01)).unwrap();
+    let initial = block_on(router.snapshot());
+    assert!(initial.flows.is_empty());
+    assert!(initial.reassembly.is_empty());
+    assert_eq!(initial.listeners.as_slice(), &[MsgType(0x01)]);
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &[1])).unwrap();
+    bottom.outbound_done();
+    let tv = req.sent_tag.unwrap().tag();
+
+    let sent = block_on(router.snapshot());
+    assert_eq!(sent.flows.as_slice(), &[(Eid(20), tv)]);
+    assert_eq!(sent.listeners, initial.listeners);
+
+    // Response is held for the request channel
+    let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[2]);
+    block_on(router.inbound(&resp, PortId(0)));
+    let received = block_on(router.snapshot());
+    assert!(received.flows.is_empty());
+    assert_eq!(
+        received.reassembly.as_slice(),
+        &[(Eid(20), Tag::Unowned(tv), true)]
+    );
+
+    let mut buf = [0u8; 4];
+    block_on(req.recv(&mut buf)).unwrap();
+    assert_eq!(block_on(router.snapshot()), initial);
+}
+
+#[test]
+fn borrowed_stack() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let mut stack = Stack::new(Eid(10), 255, 0);
+    let typ = MsgType(0x10);
+    {
+        let router: Router =
+            Router::new_borrowed(&mut stack, &ports, &mut lookup);
+        assert_eq!(block_on(router.get_eid()), Eid(10));
+
+        let mut req = router.req(Eid(20));
+        block_on(req.send(typ, &[1])).unwrap();
+        let (pkt, dest) = bottom.try_outbound().unwrap();
+        assert_eq!(dest, Eid(20));
+        let tv = crate::peek_header(pkt).unwrap().tag.tag();
+        bottom.outbound_done();
+
+        let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[2, 3]);
+        block_on(router.inbound(&resp, PortId(0)));
+        let mut buf = [0u8; 4];
+        let (payload, rtyp, _tag, _ic) = block_on(req.recv(&mut buf)).unwrap();
+        assert_eq!((&*payload, rtyp), (&[2, 3][..], typ));
+    }
+
+    // The stack is usable after the Router
+    assert_eq!(stack.eid(), Eid(10));
+    assert!(stack.flows.is_empty());
+}
+
+#[test]
+fn send_lazy() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+
+    let payload: std::vec::Vec<u8> = (0..150u8).collect();
+    let mut calls = 0;
+    let mut req = router.req(Eid(20));
+    block_on(req.send_lazy(typ, false, payload.len(), |offset, buf| {
+        calls += 1;
+        buf.copy_from_slice(&payload[offset..offset + buf.len()]);
+        buf.len()
+    }))
+    .unwrap();
+    assert_eq!(calls, 3);
+
+    // Reassemble at the peer
+    let mut peer = Stack::new(Eid(20), 64, 0);
+    let mut received = None;
+    while let Some((pkt, _dest)) = bottom.try_outbound() {
+        if let Some((msg, handle)) = peer.receive(pkt).unwrap() {
+            received = Some(std::vec::Vec::from(msg.payload));
+            peer.finished_receive(handle);
+        }
+        bottom.outbound_done();
+    }
+    assert_eq!(received.unwrap(), payload);
+
+    // Short producer fails
+    let mut req = router.req(Eid(20));
+    let r = block_on(req.send_lazy(typ, false, 150, |offset, buf| {
+        if offset > 0 {
+            0
+        } else {
+            buf.len()
+        }
+    }));
+    assert!(matches!(r, Err(Error::InvalidInput)));
+}
+
+#[test]
+fn set_port_mtu() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(100).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let payload = [0x44u8; 150];
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &payload)).unwrap();
+    let (pkt, _dest) = bottom.try_outbound().unwrap();
+    assert_eq!(pkt.len(), 100);
+    while bottom.try_outbound().is_some() {
+        bottom.outbound_done();
+    }
+
+    assert!(router.set_port_mtu(PortId(0), MAX_MTU + 1).is_err());
+    assert!(router.set_port_mtu(PortId(0), HEADER_LEN).is_err());
+    assert!(router.set_port_mtu(PortId(1), 64).is_err());
+    router.set_port_mtu(PortId(0), 64).unwrap();
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &payload)).unwrap();
+    let mut count = 0;
+    while let Some((pkt, _dest)) = bottom.try_outbound() {
+        assert!(pkt.len() <= 64);
+        count += 1;
+        bottom.outbound_done();
+    }
+    assert_eq!(count, 3);
+}
+
+#[test]
+fn recv_borrowed() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &[1])).unwrap();
+    let n = drain_port(&mut bottom, |_pkt, dest| assert_eq!(dest, Eid(20)));
+    assert_eq!(n, 1);
+    let tv = req.sent_tag.unwrap().tag();
+
+    // The response arrives while recv_borrowed() is pending
+    let resp = packet(Eid(20), Eid(10), Tag::Unowned(tv), typ, &[5, 6, 7]);
+    let mut pending = Some(resp);
+    let guard = block_on_with(req.recv_borrowed(), || {
+        let Some(resp) = pending.take() else {
+            return false;
+        };
+        block_on(router.inbound(&resp, PortId(0)));
+        true
+    })
+    .unwrap()
+    .unwrap();
+    assert!(pending.is_none());
+    block_on(guard.with_message(|msg| {
+        assert_eq!(msg.payload, &[5, 6, 7]);
+        assert_eq!(msg.source, Eid(20));
+        assert_eq!(msg.typ, typ);
+    }));
+
+    // The Router isn't locked while the guard is held
+    let mut req2 = router.req(Eid(21));
+    block_on(req2.send(typ, &[2])).unwrap();
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    // Slot is pinned
+    let snap = block_on(router.snapshot());
+    assert_eq!(snap.reassembly, [(Eid(20), Tag::Unowned(tv), true)]);
+
+    // Reassembly slot is released on drop
+    drop(guard);
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+}
+
+#[test]
+fn recv_pooled() {
+    use mctp::AsyncRespChannel;
+
+    start_log();
+    let mut pool = RecvPool::<2>::new();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let send = |i: u8| {
+        let tag = Tag::Owned(TagValue(i));
+        let pkt = packet(Eid(20), Eid(10), tag, typ, &[i; 3]);
+        block_on(router.inbound(&pkt, PortId(0)));
+    };
+
+    // No pool set
+    send(1);
+    assert!(matches!(block_on(l.recv_pooled()), Err(Error::NoSpace)));
+
+    router.set_recv_pool(&mut pool).unwrap();
+    assert_eq!(router.recv_pool_free(), 2);
+
+    send(2);
+    send(3);
+    let (m1, _) = block_on(l.recv_pooled()).unwrap();
+    let (m2, mut resp) = block_on(l.recv_pooled()).unwrap();
+    assert_eq!((&*m1, &*m2), (&[1; 3][..], &[2; 3][..]));
+    assert_eq!(
+        (m2.typ(), m2.tag(), m2.ic()),
+        (typ, Tag::Owned(TagValue(2)), false)
+    );
+    assert_eq!(router.recv_pool_free(), 0);
+
+    // Pool is exhausted, the message is kept
+    assert!(matches!(block_on(l.recv_pooled()), Err(Error::NoSpace)));
+    drop(m1);
+    assert_eq!(router.recv_pool_free(), 1);
+    let (m3, _) = block_on(l.recv_pooled()).unwrap();
+    assert_eq!(&*m3, &[3; 3]);
+
+    // Buffers are recycled
+    drop(m2);
+    drop(m3);
+    for i in 4..8 {
+        send(i);
+        let (m, _) = block_on(l.recv_pooled()).unwrap();
+        assert_eq!(&*m, &[i; 3]);
+    }
+    assert_eq!(router.recv_pool_free(), 2);
+    assert_eq!(l.stats().received, 7);
+
+    block_on(resp.send(typ, &[9])).unwrap();
+    let (_pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(20));
+}
+
+#[test]
+fn recv_pool_add_while_lent() {
+    start_log();
+    let mut pool1 = RecvPool::<4>::new();
+    let mut pool2 = RecvPool::<4>::new();
+    let mut pool3 = RecvPool::<1>::new();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    router.set_recv_pool(&mut pool1).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+    let pkt = packet(Eid(20), Eid(10), tag, typ, &[1; 3]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (m, _) = block_on(l.recv_pooled()).unwrap();
+    assert_eq!(router.recv_pool_free(), 3);
+
+    // The lent buffer still counts towards MAX_RECV_POOL
+    router.set_recv_pool(&mut pool2).unwrap();
+    assert_eq!(router.recv_pool_free(), 7);
+    let r = router.set_recv_pool(&mut pool3);
+    assert!(matches!(r, Err(Error::NoSpace)));
+
+    drop(m);
+    assert_eq!(router.recv_pool_free(), 8);
+}
+
+/// Feeds garbage to a router with routes to `port`
+fn fuzz_router(port: PortId, seed: u32) {
+    let mut storage = PortStorage::<4>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(port));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let _l = router.listener_with_unowned(MsgType(0x01)).unwrap();
+
+    // Simple LCG for repeatable garbage
+    let mut x = seed;
+    let mut next = || {
+        x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (x >> 16) as u8
+    };
+
+    for i in 0..5000 {
+        let len = next() as usize % (MAX_MTU + 20);
+        let mut bytes: std::vec::Vec<u8> = (0..len).map(|_| next()).collect();
+        if i % 2 == 0 && len >= HEADER_LEN {
+            // Make it more likely to reach reassembly
+            bytes[0] = 1;
+            bytes[1] = if i % 4 == 0 { 10 } else { 0 };
+        }
+        let _ = fuzz_inbound(&router, &bytes);
+    }
+}
+
+#[test]
+fn fuzz_garbage() {
+    start_log();
+    fuzz_router(PortId(0), 0x1234_5678);
+    // Non-existent port
+    fuzz_router(PortId(7), 0x8765_4321);
+}
+
+#[test]
+fn prebind() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x01);
+
+    router.prebind(typ).unwrap();
+    assert!(matches!(router.prebind(typ), Err(Error::AddrInUse)));
+
+    // Arrives before any listener exists
+    let tv = TagValue(2);
+    let req = packet(Eid(20), Eid(10), Tag::Owned(tv), typ, &[7]);
+    block_on(router.inbound(&req, PortId(0)));
+
+    let mut l = router.listener(typ).unwrap();
+    // Only a single listener may attach
+    assert!(matches!(router.listener(typ), Err(Error::AddrInUse)));
+
+    let mut buf = [0u8; 4];
+    let (msg, _resp, tag, _typ, _ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[7]);
+    assert_eq!(tag, Tag::Owned(tv));
+
+    // The bind is kept after the listener is dropped
+    drop(l);
+    let req = packet(Eid(20), Eid(10), Tag::Owned(tv), typ, &[8]);
+    block_on(router.inbound(&req, PortId(0)));
+    let mut l = router.listener(typ).unwrap();
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[8]);
+}
+
+#[test]
+fn port_eid_range() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    router
+        .set_port_eid_range(PortId(0), Some(Eid(20)..=Eid(29)))
+        .unwrap();
+    assert!(router.set_port_eid_range(PortId(1), None).is_err());
+
+    let pkt = packet(Eid(8), Eid(25), Tag::Owned(TagValue(1)), typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(1)));
+    let (out, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(25));
+    assert_eq!(out, pkt.as_slice());
+    bottom.outbound_done();
+
+    // Outside the range is dropped
+    let pkt = packet(Eid(8), Eid(30), Tag::Owned(TagValue(1)), typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(1)));
+    assert!(bottom.try_outbound().is_none());
+    assert_eq!(router.stats().forward_dropped_range, 1);
+
+    router.set_port_eid_range(PortId(0), None).unwrap();
+    block_on(router.inbound(&pkt, PortId(1)));
+    assert!(bottom.try_outbound().is_some());
+    assert_eq!(router.stats().forward_dropped_range, 1);
+}
+
+#[test]
+fn recv_coalesced() {
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x20);
+
+    let mut l = router.listener(typ).unwrap();
+    // Within the default NUM_RECEIVE
+    for i in 0..4 {
+        let t = Tag::Owned(TagValue(i % 8));
+        let pkt = packet(Eid(20 + i), Eid(10), t, typ, &[i; 3]);
+        block_on(router.inbound(&pkt, PortId(0)));
+    }
+
+    let mut buf = [0u8; 12];
+    let (b, count) = block_on(l.recv_coalesced(&mut buf, 10)).unwrap();
+    // Third message doesn't fit
+    assert_eq!(count, 2);
+    assert_eq!(b, &[3, 0, 0, 0, 0, 3, 0, 1, 1, 1]);
+
+    let (b, count) = block_on(l.recv_coalesced(&mut buf, 1)).unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(b, &[3, 0, 2, 2, 2]);
+
+    // Too small for the first message
+    let mut small = [0u8; 4];
+    let r = block_on(l.recv_coalesced(&mut small, 2));
+    assert!(matches!(r, Err(Error::NoSpace)));
+}
+
+#[test]
+fn detached_port() {
+    start_log();
+    one_port_router!(router, bottom, 2, 255);
+    let typ = MsgType(0x10);
+    router.set_port_mtu(PortId(0), 64).unwrap();
+
+    block_on(async {
+        // Fills the queue then waits
+        let mut req = router.req(Eid(20));
+        let (r, _) = join(req.send(typ, &[1; 200]), async {
+            yield_now().await;
+            bottom.detach();
+        })
+        .await;
+        assert!(matches!(r, Err(Error::TxFailure)));
+
+        // Fails immediately
+        let mut req = router.req(Eid(20));
+        let r = req.send(typ, &[1]).await;
+        assert!(matches!(r, Err(Error::TxFailure)));
+    });
+}
+
+#[cfg(feature = "checked-invariants")]
+#[test]
+fn checked_invariant() {
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let router: Router =
+        Router::new(Stack::new(Eid(10), 255, 0), &[], &mut lookup);
+
+    // Releasing an unowned tag is an internal error
+    block_on(router.app_release_tag(Eid(20), Tag::Unowned(TagValue(1))));
+    assert_eq!(router.invariant_errors(), 1);
+}
+
+#[cfg(not(feature = "checked-invariants"))]
+#[test]
+#[should_panic(expected = "Invariant failed")]
+fn unchecked_invariant() {
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let router: Router =
+        Router::new(Stack::new(Eid(10), 255, 0), &[], &mut lookup);
+
+    block_on(router.app_release_tag(Eid(20), Tag::Unowned(TagValue(1))));
+}
+
+#[test]
+fn deliver_local() {
+    use mctp::AsyncListener;
+
+    /// Claims a single extra EID
+    struct Claim(Eid);
+
+    impl PortLookup for Claim {
+        fn by_eid(
+            &mut self,
+            _eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            Some(PortId(0))
+        }
+
+        fn deliver_local(&mut self, eid: Eid) -> bool {
+            eid == self.0
+        }
+    }
+
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = Claim(Eid(50));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    // Not claimed, forwarded
+    let t = Tag::Owned(TagValue(1));
+    let pkt = packet(Eid(20), Eid(51), t, typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (_pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(51));
+    bottom.outbound_done();
+
+    // Claimed, delivered locally
+    let pkt = packet(Eid(20), Eid(50), t, typ, &[2]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+    let mut buf = [0u8; 4];
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[2]);
+}
+
+#[test]
+fn local_eids() {
+    use mctp::{AsyncListener, AsyncReqChannel, AsyncRespChannel};
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    block_on(router.add_local_eid(Eid(50))).unwrap();
+    block_on(router.add_local_eid(Eid(51))).unwrap();
+    assert!(block_on(router.add_local_eid(Eid(51))).is_err());
+    assert!(block_on(router.add_local_eid(Eid(10))).is_err());
+    assert!(block_on(router.add_local_eid(Eid(0xff))).is_err());
+    assert_eq!(block_on(router.get_eid()), Eid(10));
+    assert!(block_on(router.local_eids()).eq([Eid(10), Eid(50), Eid(51)]));
+
+    // Requests to any local EID are received and answered
+    // from that EID
+    let t = Tag::Owned(TagValue(1));
+    for (dest, payload) in [(Eid(10), 1), (Eid(51), 2)] {
+        let pkt = packet(Eid(20), dest, t, typ, &[payload]);
+        block_on(router.inbound(&pkt, PortId(0)));
+        assert!(bottom.try_outbound().is_none());
+        let mut buf = [0u8; 4];
+        let (msg, mut resp, ..) = block_on(l.recv(&mut buf)).unwrap();
+        assert_eq!(msg, &[payload]);
+        assert_eq!(resp.local_eid(), dest);
+        block_on(resp.send(typ, &[payload + 10])).unwrap();
+        let mut n = 0;
+        drain_port(&mut bottom, |pkt, pkt_dest| {
+            let h = crate::peek_header(pkt).unwrap();
+            assert_eq!(pkt_dest, Eid(20));
+            assert_eq!(h.source, dest);
+            n += 1;
+        });
+        assert_eq!(n, 1);
+    }
+
+    // Local sends are looped back
+    let mut req = router.req(Eid(50));
+    block_on(req.send(typ, &[3])).unwrap();
+    assert!(bottom.try_outbound().is_none());
+    let mut buf = [0u8; 4];
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[3]);
+
+    // Removed EIDs are forwarded
+    block_on(router.remove_local_eid(Eid(51))).unwrap();
+    assert!(block_on(router.remove_local_eid(Eid(51))).is_err());
+    assert!(block_on(router.local_eids()).eq([Eid(10), Eid(50)]));
+    let pkt = packet(Eid(20), Eid(51), t, typ, &[4]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (_pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(51));
+    bottom.outbound_done();
+}
+
+#[test]
+fn deferred_limit() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    block_on(router.set_deferred_limit(Some(2)));
+    // Listener isn't receiving
+    for i in 0..4 {
+        let t = Tag::Owned(TagValue(1));
+        let pkt = packet(Eid(20 + i), Eid(10), t, typ, &[i]);
+        block_on(router.inbound(&pkt, PortId(0)));
+    }
+    assert_eq!(block_on(router.snapshot()).reassembly.len(), 2);
+    assert_eq!(block_on(router.deferred_dropped()), 2);
+
+    let mut buf = [0u8; 4];
+    for i in 0..2 {
+        let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+        assert_eq!(msg, &[i]);
+    }
+}
+
+#[test]
+fn zero_port_loopback() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    // No route to other EIDs
+    let mut req = router.req(Eid(20));
+    assert!(block_on(req.send(typ, &[1])).is_err());
+
+    // Multiple fragments, split across slices
+    let a = [0x11u8; 200];
+    let b = [0x22u8; 150];
+    let mut req = router.req(Eid(10));
+    block_on(async {
+        req.send_vectored(typ, false, &[&a, &b]).await.unwrap();
+
+        let mut buf = [0u8; 400];
+        let (msg, mut ch, tag, _typ, _ic) = l.recv(&mut buf).await.unwrap();
+        assert!(tag.is_owner());
+        assert_eq!(msg.len(), a.len() + b.len());
+        assert_eq!(&msg[..a.len()], &a);
+        assert_eq!(&msg[a.len()..], &b);
+        assert_eq!(ch.remote_eid(), Eid(10));
+        ch.send(typ, &[3, 4]).await.unwrap();
+
+        let (msg, rtyp, _tag, _ic) = req.recv(&mut buf).await.unwrap();
+        assert_eq!(rtyp, typ);
+        assert_eq!(msg, &[3, 4]);
+    });
+}
+
+#[test]
+fn alias_loopback() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    struct Claim;
+
+    impl PortLookup for Claim {
+        fn by_eid(
+            &mut self,
+            _eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            None
+        }
+
+        fn deliver_local(&mut self, eid: Eid) -> bool {
+            eid == Eid(30)
+        }
+    }
+
+    start_log();
+    let mut lookup = Claim;
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    let mut req = router.req(Eid(30));
+    block_on(async {
+        req.send_vectored(typ, true, &[&[1, 2]]).await.unwrap();
+
+        let mut buf = [0u8; 10];
+        let (msg, mut ch, tag, rtyp, ic) = l.recv(&mut buf).await.unwrap();
+        assert_eq!(msg, &[1, 2]);
+        assert!(tag.is_owner());
+        assert_eq!((rtyp, ic), (typ, true));
+        assert_eq!(ch.remote_eid(), Eid(10));
+        assert_eq!(ch.local_eid(), Eid(30));
+        ch.send(typ, &[3]).await.unwrap();
+
+        let (msg, rtyp, rtag, _ic) = req.recv(&mut buf).await.unwrap();
+        assert_eq!((&*msg, rtyp), (&[3][..], typ));
+        assert_eq!(rtag, Tag::Unowned(tag.tag()));
+    });
+    assert!(block_on(router.snapshot()).flows.is_empty());
+}
+
+#[test]
+fn multipart_response() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    let mut req = router.req(Eid(10));
+    req.tag_noexpire().unwrap();
+    block_on(async {
+        req.send(typ, &[1]).await.unwrap();
+
+        let mut buf = [0u8; 10];
+        let (_msg, mut ch, tag, _typ, _ic) = l.recv(&mut buf).await.unwrap();
+        ch.send(typ, &[2]).await.unwrap();
+        ch.send(typ, &[3]).await.unwrap();
+
+        for i in [2, 3] {
+            let (msg, _typ, rtag, _ic) = req.recv(&mut buf).await.unwrap();
+            assert_eq!(msg, &[i]);
+            assert_eq!(rtag, Tag::Unowned(tag.tag()));
+        }
+        // Tag is still held until released
+        assert_eq!(router.snapshot().await.flows.len(), 1);
+        req.async_drop().await;
+        assert!(router.snapshot().await.flows.is_empty());
+    });
+}
+
+#[test]
+fn recv_deadline() {
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let mut buf = [0u8; 10];
+
+    block_on(async {
+        router.update_time(100).await.unwrap();
+
+        // Clock advances past the deadline while waiting
+        let mut reached = false;
+        let (r, _) = join(l.recv_deadline(&mut buf, 150), async {
+            router.update_time(120).await.unwrap();
+            yield_now().await;
+            router.update_time(149).await.unwrap();
+            yield_now().await;
+            reached = true;
+            router.update_time(150).await.unwrap();
+        })
+        .await;
+        assert!(reached);
+        assert!(matches!(r, Err(Error::TimedOut)));
+
+        // Already past
+        let r = l.recv_deadline(&mut buf, 140).await;
+        assert!(matches!(r, Err(Error::TimedOut)));
+
+        // A waiting message is still received
+        let mut req = router.req(Eid(10));
+        req.send(typ, &[1]).await.unwrap();
+        let (msg, ..) = l.recv_deadline(&mut buf, 140).await.unwrap();
+        assert_eq!(msg, &[1]);
+
+        // Request channel with no response
+        let (r, _) = join(req.recv_deadline(&mut buf, 300), async {
+            yield_now().await;
+            router.update_time(300).await.unwrap();
+        })
+        .await;
+        assert!(matches!(r, Err(Error::TimedOut)));
+    });
+}
+
+struct ProgressHooks(std::sync::Mutex<std::vec::Vec<usize>>);
+
+impl RouterHooks for ProgressHooks {
+    fn on_send_progress(&self, eid: Eid, tag: Tag, remaining: usize) {
+        assert_eq!(eid, Eid(20));
+        assert!(tag.is_owner());
+        self.0.lock().unwrap().push(remaining);
+    }
+}
+
+#[test]
+fn send_progress() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let hooks = ProgressHooks(Default::default());
+    block_on(router.set_hooks(Some(&hooks)));
+
+    // 60 bytes per packet, the first packet has a type byte
+    let payload = [0x55u8; 500];
+    let mut req = router.req(Eid(20));
+    let (r, _) = block_on(join(req.send(MsgType(0x10), &payload), async {
+        for _ in 0..9 {
+            bottom.outbound().await;
+            bottom.outbound_done();
+        }
+    }));
+    r.unwrap();
+
+    let progress = hooks.0.lock().unwrap();
+    assert_eq!(progress.len(), 9);
+    assert_eq!(progress[0], 500 - 59);
+    assert!(progress.windows(2).all(|w| w[0] > w[1]));
+    assert_eq!(progress.last(), Some(&0));
+}
+
+#[test]
+fn port_overflow() {
+    start_log();
+    one_port_router!(router, mut bottom, 2, 255);
+    let typ = MsgType(0x10);
+    let pkts: std::vec::Vec<_> = (0..3)
+        .map(|i| packet(Eid(8), Eid(20), Tag::Owned(TagValue(1)), typ, &[i]))
+        .collect();
+
+    // Default drops the newest
+    for p in &pkts {
+        block_on(router.inbound(p, PortId(0)));
+    }
+    for p in &pkts[..2] {
+        assert_eq!(bottom.try_outbound().unwrap().0, p.as_slice());
+        bottom.outbound_done();
+    }
+    assert!(bottom.try_outbound().is_none());
+
+    router
+        .set_port_overflow(PortId(0), OverflowPolicy::DropOldest)
+        .unwrap();
+    assert!(router
+        .set_port_overflow(PortId(1), OverflowPolicy::DropOldest)
+        .is_err());
+    let dropped = |router: &Router| {
+        router.port_stats(PortId(0)).unwrap().dropped_queue_full
+    };
+    let before = dropped(&router);
+
+    // Forwarding never waits for the PortBottom. Taking the last
+    // slot marks the oldest for discard, a full queue drops the newest.
+    for p in &pkts {
+        let r = embassy_futures::poll_once(router.inbound(p, PortId(0)));
+        assert!(r.is_ready());
+    }
+    assert_eq!(dropped(&router), before + 2);
+    let (out, _) = block_on(bottom.outbound());
+    assert_eq!(out, pkts[1].as_slice());
+    // Peeking again doesn't discard
+    assert_eq!(bottom.try_outbound().unwrap().0, pkts[1].as_slice());
+    bottom.outbound_done();
+    assert!(bottom.try_outbound().is_none());
+
+    // A peeked packet is being transmitted, so isn't discarded
+    block_on(router.inbound(&pkts[0], PortId(0)));
+    assert_eq!(bottom.try_outbound().unwrap().0, pkts[0].as_slice());
+    block_on(router.inbound(&pkts[1], PortId(0)));
+    block_on(router.inbound(&pkts[2], PortId(0)));
+    assert_eq!(dropped(&router), before + 3);
+    bottom.outbound_done();
+    assert_eq!(bottom.try_outbound().unwrap().0, pkts[1].as_slice());
+    bottom.outbound_done();
+    assert!(bottom.try_outbound().is_none());
+}
+
+#[test]
+fn pre_warm() {
+    start_log();
+    let mut storage = PortStorage::<4>::new();
+    storage.pre_warm().unwrap();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let _l = router.listener(typ).unwrap();
+
+    // An occupied reassembly slot is left alone
+    let pkt = packet(Eid(8), Eid(10), Tag::Owned(TagValue(1)), typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let before = block_on(router.snapshot());
+    block_on(router.pre_warm()).unwrap();
+    assert_eq!(block_on(router.snapshot()), before);
+}
+
+#[test]
+fn noop_mutex() {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use mctp::{AsyncListener, AsyncReqChannel};
+
+    start_log();
+    let mut storage = PortStorage::<4>::new();
+    let mut pb: PortBuilder<'_, NoopRawMutex> =
+        PortBuilder::new_with_mutex(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router<'_, NoopRawMutex> =
+        Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+    let mut l = router.listener(typ).unwrap();
+    let mut buf = [0u8; 10];
+
+    let pkt = packet(Eid(20), Eid(10), tag, typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[1]);
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &[2])).unwrap();
+    let (out, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(20));
+    assert_eq!(&out[HEADER_LEN..], &[typ.0, 2]);
+    bottom.outbound_done();
+}
+
+#[test]
+fn custom_pool() {
+    use crate::ReassemblyPool;
+    use mctp::AsyncListener;
+
+    struct SlicePool<'a>(&'a mut [[u8; 20]]);
+
+    impl ReassemblyPool for SlicePool<'_> {
+        fn buffer(&mut self, index: usize) -> &mut [u8] {
+            &mut self.0[index]
+        }
+    }
+
+    start_log();
+    let mut storage = PortStorage::<4>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let mut mem = [[0u8; 20]; crate::NUM_RECEIVE];
+    let stack = Stack::new_with_pool(SlicePool(&mut mem), Eid(10), 64, 0);
+    let router: Router<'_, _, SlicePool<'_>> =
+        Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+    let mut l = router.listener(typ).unwrap();
+    let mut buf = [0u8; 30];
+
+    // Too large for the pool's buffers
+    let pkt = packet(Eid(20), Eid(10), tag, typ, &[7; 21]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let pkt = packet(Eid(21), Eid(10), tag, typ, &[8; 20]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (msg, _resp, _tag, _typ, _ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[8; 20]);
+}
+
+#[test]
+fn max_payload() {
+    /// Only routes EID 20
+    struct OneRoute;
+
+    impl PortLookup for OneRoute {
+        fn by_eid(
+            &mut self,
+            eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            (eid == Eid(20)).then_some(PortId(0))
+        }
+    }
+
+    start_log();
+    let mut storage = PortStorage::<8, 100>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = OneRoute;
+    let mut stack = Stack::new(Eid(10), 64, 0);
+    stack.set_max_fragments(Some(3));
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    // A peer with the same configuration
+    let mut peer = Stack::new(Eid(20), 64, 0);
+    peer.set_max_fragments(Some(3));
+
+    block_on(async {
+        assert_eq!(router.max_payload(Eid(21), typ).await, None);
+        assert_eq!(router.max_vectored_payload(Eid(21), typ).await, None);
+
+        // Three packets of the 64 byte MTU, less the type byte
+        let max = router.max_payload(Eid(20), typ).await.unwrap();
+        assert_eq!(max, (3 * (64 - HEADER_LEN) - 1).min(MAX_PAYLOAD));
+        // Loopback isn't limited by the port MTU
+        let local = router.max_payload(Eid(10), typ).await.unwrap();
+        assert_eq!(local, (3 * (MAX_MTU - HEADER_LEN) - 1).min(MAX_PAYLOAD));
+        assert_eq!(
+            router.max_vectored_payload(Eid(10), typ).await,
+            Some(local)
+        );
+
+        // The peer receives the largest message
+        let payload = [3u8; MAX_PAYLOAD];
+        let mut req = router.req(Eid(20));
+        req.send(typ, &payload[..max]).await.unwrap();
+        let mut received = None;
+        drain_port(&mut bottom, |pkt, _dest| {
+            if let Some((msg, handle)) = peer.receive(pkt).unwrap() {
+                received = Some(msg.payload.len());
+                peer.finished_receive(handle);
+            }
+        });
+        assert_eq!(received, Some(max));
+
+        // One more byte needs another packet, which the peer drops
+        if max < MAX_PAYLOAD {
+            let mut req = router.req(Eid(20));
+            req.send(typ, &payload[..max + 1]).await.unwrap();
+            let mut failed = false;
+            drain_port(&mut bottom, |pkt, _dest| {
+                if let Err(Error::RxFailure) = peer.receive(pkt) {
+                    failed = true;
+                }
+            });
+            assert!(failed);
+        }
+
+        // Multiple slices are limited by the port's scratch buffer
+        let max = router.max_vectored_payload(Eid(20), typ).await.unwrap();
+        assert_eq!(max, MAX_PAYLOAD.min(100));
+
+        let a = [1u8; 60];
+        let b = [2u8; 41];
+        let mut req = router.req(Eid(20));
+        let r = req.send_vectored(typ, false, &[&a, &b]).await;
+        assert!(matches!(r, Err(Error::NoSpace)));
+        assert!(bottom.try_outbound().is_none());
+
+        let mut req = router.req(Eid(20));
+        req.send_vectored(typ, false, &[&a, &b[..max - a.len()]])
+            .await
+            .unwrap();
+        assert!(bottom.try_outbound().is_some());
+    });
+}
+
+/// Modifies the sequence number of the second packet, or
+/// corrupts the header of every packet.
+struct FaultHooks {
+    count: std::sync::atomic::AtomicUsize,
+    bad_header: std::sync::atomic::AtomicBool,
+}
+
+impl RouterHooks for FaultHooks {
+    fn filter_outbound(&self, pkt: &mut [u8]) {
+        use std::sync::atomic::Ordering;
+        if self.bad_header.load(Ordering::Relaxed) {
+            // header version
+            pkt[0] = 0xff;
+        } else if self.count.fetch_add(1, Ordering::Relaxed) == 1 {
+            pkt[3] ^= 0x10;
+        }
+    }
+}
+
+#[test]
+fn filter_outbound() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let hooks = FaultHooks {
+        count: Default::default(),
+        bad_header: Default::default(),
+    };
+    block_on(router.set_hooks(Some(&hooks)));
+
+    let mut peer = Stack::new(Eid(20), 64, 0);
+    block_on(async {
+        let mut req = router.req(Eid(20));
+        req.send(typ, &[0x33; 100]).await.unwrap();
+
+        let (pkt, _) = bottom.try_outbound().unwrap();
+        assert!(peer.receive(pkt).unwrap().is_none());
+        bottom.outbound_done();
+
+        // Reassembler rejects the bad sequence number
+        let (pkt, _) = bottom.try_outbound().unwrap();
+        assert!(peer.receive(pkt).is_err());
+        bottom.outbound_done();
+        assert!(bottom.try_outbound().is_none());
+    });
+
+    hooks
+        .bad_header
+        .store(true, std::sync::atomic::Ordering::Relaxed);
+    block_on(async {
+        let mut req = router.req(Eid(20));
+        let r = req.send(typ, &[0x33; 10]).await;
+        assert!(matches!(r, Err(Error::InvalidInput)));
+        assert!(bottom.try_outbound().is_none());
+    });
+}
+
+/// Corrupts the header of the third packet sent.
+struct ThirdBadHooks(std::sync::atomic::AtomicUsize);
+
+impl RouterHooks for ThirdBadHooks {
+    fn filter_outbound(&self, pkt: &mut [u8]) {
+        use std::sync::atomic::Ordering;
+        if self.0.fetch_add(1, Ordering::Relaxed) == 2 {
+            pkt[0] = 0xff;
+        }
+    }
+}
+
+#[test]
+fn filter_outbound_partial() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let hooks = ThirdBadHooks(Default::default());
+    block_on(router.set_hooks(Some(&hooks)));
+
+    // A forwarded packet queued ahead of the send is kept
+    let fwd = packet(Eid(8), Eid(21), Tag::Owned(TagValue(1)), typ, &[1]);
+    block_on(router.inbound(&fwd, PortId(0)));
+
+    // Two packets are queued before the third is rejected
+    let mut req = router.req(Eid(20));
+    let r = block_on(req.send(typ, &[0x33; 200]));
+    assert!(matches!(r, Err(Error::InvalidInput)));
+
+    let (pkt, _) = bottom.try_outbound().unwrap();
+    assert_eq!(pkt, fwd.as_slice());
+    bottom.outbound_done();
+    assert!(bottom.try_outbound().is_none());
+
+    // Later sends are unaffected
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &[0x44; 10])).unwrap();
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    // The cancelled message is no longer tracked
+    let shared = router.ports[0].shared;
+    assert!(shared.inner.lock(|s| s.borrow().cancelled.is_empty()));
+}
+
+#[test]
+fn atomic_messages() {
+    start_log();
+    one_port_router!(router, mut bottom, 64, 64);
+    let typ = MsgType(0x10);
+    // 17 packets each
+    let payload = [0x55u8; 1000];
+
+    // Returns the destination of each queued packet
+    let mut send_two = || {
+        let mut a = router.req(Eid(20));
+        let mut b = router.req(Eid(21));
+        let (ra, rb) =
+            block_on(join(a.send(typ, &payload), b.send(typ, &payload)));
+        ra.unwrap();
+        rb.unwrap();
+        let mut dests = std::vec::Vec::new();
+        while let Some((_pkt, dest)) = bottom.try_outbound() {
+            dests.push(dest);
+            bottom.outbound_done();
+        }
+        assert_eq!(dests.len(), 34);
+        dests
+    };
+
+    // Interleaved once the first send yields
+    let dests = send_two();
+    assert!(dests.windows(2).filter(|w| w[0] != w[1]).count() > 1);
+
+    router.set_port_atomic_messages(PortId(0), true).unwrap();
+    assert!(router.set_port_atomic_messages(PortId(1), true).is_err());
+    let dests = send_two();
+    assert!(dests[..17].iter().all(|d| *d == Eid(20)));
+    assert!(dests[17..].iter().all(|d| *d == Eid(21)));
+}
+
+#[test]
+fn ic_policy() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    l.set_ic_policy(IcPolicy::Forbid);
+
+    let tag = Tag::Owned(TagValue(1));
+    let mut ic_pkt = packet(Eid(20), Eid(10), tag, typ, &[1]);
+    // Set the IC bit
+    ic_pkt[HEADER_LEN] |= 0x80;
+    let pkt = packet(Eid(21), Eid(10), tag, typ, &[2]);
+
+    block_on(router.inbound(&ic_pkt, PortId(0)));
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+    block_on(router.inbound(&pkt, PortId(0)));
+
+    let mut buf = [0u8; 4];
+    let (msg, _ch, _tag, _typ, ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[2]);
+    assert!(!ic);
+
+    l.set_ic_policy(IcPolicy::Require);
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+    block_on(router.inbound(&ic_pkt, PortId(0)));
+    let (msg, _ch, _tag, _typ, ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[1]);
+    assert!(ic);
+}
+
+#[test]
+fn release_persistent_tags() {
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    block_on(async {
+        // Leaked, without async_drop()
+        let mut req = router.req(Eid(20));
+        req.tag_noexpire().unwrap();
+        req.send(typ, &[1]).await.unwrap();
+        drop(req);
+
+        // Expiring tag is kept
+        let mut req = router.req(Eid(21));
+        req.send(typ, &[2]).await.unwrap();
+
+        assert_eq!(router.snapshot().await.flows.len(), 2);
+        assert_eq!(router.release_all_persistent_tags().await, 1);
+        let flows = router.snapshot().await.flows;
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].0, Eid(21));
+        assert_eq!(router.release_all_persistent_tags().await, 0);
+    });
+}
+
+#[test]
+fn null_source_policy() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let _l = router.listener(typ).unwrap();
+
+    let null = mctp::MCTP_ADDR_NULL;
+
+    let mut held = 0;
+    for (i, (policy, accepted)) in [
+        (NullSourcePolicy::Accept, true),
+        (NullSourcePolicy::Log, true),
+        (NullSourcePolicy::Drop, false),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        block_on(router.set_null_source_policy(policy));
+        // Distinct tags, messages are held by the listener
+        let tag = Tag::Owned(TagValue(i as u8));
+        let local = packet(null, Eid(10), tag, typ, &[1]);
+        let fwd = packet(null, Eid(20), tag, typ, &[1]);
+
+        let src = block_on(router.inbound(&local, PortId(0)));
+        assert_eq!(src, Some(null));
+        if accepted {
+            held += 1;
+        }
+        assert_eq!(block_on(router.snapshot()).reassembly.len(), held);
+
+        block_on(router.inbound(&fwd, PortId(0)));
+        assert_eq!(bottom.try_outbound().is_some(), accepted);
+        if accepted {
+            bottom.outbound_done();
+        }
+    }
+}
+
+#[test]
+fn stats() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let _l = router.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    // Two packet local message
+    let local = || PacketBuilder::new(Eid(8), Eid(10), tag, typ);
+    let p1 = local().eom(false).build(&[1; 59]).unwrap();
+    let p2 = local().som(false).seq(1).build(&[2; 50]).unwrap();
+    block_on(router.inbound(&p1, PortId(0)));
+    block_on(router.inbound(&p2, PortId(0)));
+
+    for i in 0..3 {
+        let fwd = packet(Eid(8), Eid(20), tag, typ, &[i]);
+        block_on(router.inbound(&fwd, PortId(0)));
+        bottom.try_outbound().unwrap();
+        bottom.outbound_done();
+    }
+
+    // Counted without a listener
+    let other = packet(Eid(8), Eid(10), tag, MsgType(0x11), &[1]);
+    block_on(router.inbound(&other, PortId(0)));
+
+    let stats = router.stats();
+    assert_eq!(stats.local_delivered_total, 2);
+    assert_eq!(stats.forwarded_total, 3);
+}
+
+#[test]
+fn inbound_parsed() {
+    start_log();
+    let mut storage1 = PortStorage::<8>::new();
+    let mut storage2 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let mut pb2 = PortBuilder::new(&mut storage2);
+    let (top1, mut bottom1) = pb1.build(64).unwrap();
+    let (top2, mut bottom2) = pb2.build(64).unwrap();
+    let ports1 = [top1];
+    let ports2 = [top2];
+    let mut lookup1 = DefaultRoute(Some(PortId(0)));
+    let mut lookup2 = DefaultRoute(Some(PortId(0)));
+    let router1: Router =
+        Router::new(Stack::new(Eid(10), 64, 0), &ports1, &mut lookup1);
+    let router2: Router =
+        Router::new(Stack::new(Eid(10), 64, 0), &ports2, &mut lookup2);
+    let typ = MsgType(0x10);
+    let _l1 = router1.listener(typ).unwrap();
+    let _l2 = router2.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    let local = || PacketBuilder::new(Eid(8), Eid(10), tag, typ);
+    let pkts = [
+        // Two packet local message
+        local().eom(false).build(&[1; 59]).unwrap(),
+        local().som(false).seq(1).build(&[2; 50]).unwrap(),
+        // Forwarded
+        packet(Eid(8), Eid(20), tag, typ, &[3]),
+        // Unhandled local type
+        packet(Eid(8), Eid(10), tag, MsgType(0x11), &[4]),
+    ];
+
+    for p in pkts.iter() {
+        let hdr = crate::peek_header(p).unwrap();
+        let r1 = block_on(router1.inbound(p, PortId(0)));
+        let r2 = block_on(router2.inbound_parsed(p, &hdr, PortId(0)));
+        assert_eq!(r1, r2);
+        assert_eq!(r2, Some(Eid(8)));
+
+        let mut out1 = std::vec::Vec::new();
+        let mut out2 = std::vec::Vec::new();
+        drain_port(&mut bottom1, |p, dest| out1.push((p.to_vec(), dest)));
+        drain_port(&mut bottom2, |p, dest| out2.push((p.to_vec(), dest)));
+        assert_eq!(out1, out2);
+    }
+
+    assert_eq!(router1.stats(), router2.stats());
+    assert_eq!(block_on(router1.snapshot()), block_on(router2.snapshot()));
+    assert_eq!(router2.stats().forwarded_total, 1);
+    assert_eq!(router2.stats().local_delivered_total, 2);
+}
+
+#[test]
+fn recv_any() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let bound = MsgType(0x01);
+    let mut l = router.listener(bound).unwrap();
+
+    // The first call creates the catch-all bind
+    let mut buf = [0u8; 4];
+    let req = packet(
+        Eid(20),
+        Eid(10),
+        Tag::Owned(TagValue(1)),
+        MsgType(0x10),
+        &[7],
+    );
+    let (r, _) = block_on(join(
+        router.recv_any(&mut buf),
+        router.inbound(&req, PortId(0)),
+    ));
+    let (msg, mut resp, tag, typ, _ic) = r.unwrap();
+    assert_eq!(msg, &[7]);
+    assert_eq!(typ, MsgType(0x10));
+    assert_eq!(tag, Tag::Owned(TagValue(1)));
+
+    // Responses use the request's tag
+    block_on(resp.send(typ, &[1])).unwrap();
+    let (pkt, _dest) = bottom.try_outbound().unwrap();
+    let hdr = crate::peek_header(pkt).unwrap();
+    assert_eq!(hdr.tag, Tag::Unowned(TagValue(1)));
+    bottom.outbound_done();
+
+    // A bound type goes to its listener, another type is held
+    // for the next recv_any()
+    let req = packet(Eid(20), Eid(10), Tag::Owned(TagValue(2)), bound, &[8]);
+    block_on(router.inbound(&req, PortId(0)));
+    let req = packet(
+        Eid(21),
+        Eid(10),
+        Tag::Owned(TagValue(3)),
+        MsgType(0x7e),
+        &[9],
+    );
+    block_on(router.inbound(&req, PortId(0)));
+
+    let (msg, _resp, _tag, typ, _ic) =
+        block_on(router.recv_any(&mut buf)).unwrap();
+    assert_eq!(msg, &[9]);
+    assert_eq!(typ, MsgType(0x7e));
+    let (msg, _resp, _tag, typ, _ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[8]);
+    assert_eq!(typ, bound);
+
+    // Not a listener type
+    let snap = block_on(router.snapshot());
+    assert_eq!(snap.listeners.as_slice(), &[bound]);
+}
+
+#[test]
+fn port_user_data() {
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    pb1.set_user_data(0x1234_5678);
+    let (top0, _bottom0) = pb0.build(255).unwrap();
+    let (top1, _bottom1) = pb1.build(255).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+
+    assert_eq!(router.port_user_data(PortId(0)).unwrap(), 0);
+    assert_eq!(router.port_user_data(PortId(1)).unwrap(), 0x1234_5678);
+    assert!(matches!(
+        router.port_user_data(PortId(2)),
+        Err(Error::BadArgument)
+    ));
+}
+
+#[test]
+fn forward_detached() {
+    start_log();
+    one_port_router!(router, bottom, 1, 255);
+    let typ = MsgType(0x10);
+    router
+        .set_port_overflow(PortId(0), OverflowPolicy::DropOldest)
+        .unwrap();
+
+    let fwd = packet(Eid(8), Eid(20), Tag::Owned(TagValue(1)), typ, &[1]);
+    bottom.detach();
+
+    // Completes without waiting for queue space, with no confirmation
+    for _ in 0..3 {
+        let r = embassy_futures::poll_once(
+            router.inbound_confirmed(&fwd, PortId(0)),
+        );
+        assert!(matches!(r, Poll::Ready((Some(Eid(8)), None))));
+    }
+
+    let stats = router.stats();
+    assert_eq!(stats.forward_dropped_detached, 3);
+    assert_eq!(stats.forwarded_total, 0);
+}
+
+#[test]
+fn listener_stats() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let mut other = router.listener(MsgType(0x11)).unwrap();
+
+    let mut buf = [0u8; 4];
+    for len in [1, 4, 5, 2, 10] {
+        let req = packet(
+            Eid(20),
+            Eid(10),
+            Tag::Owned(TagValue(1)),
+            typ,
+            &[1; 10][..len],
+        );
+        block_on(router.inbound(&req, PortId(0)));
+        let r = block_on(l.recv(&mut buf));
+        assert_eq!(r.is_ok(), len <= buf.len());
+    }
+
+    let stats = l.stats();
+    assert_eq!(stats.received, 3);
+    assert_eq!(stats.too_small, 2);
+    assert_eq!(other.stats(), ListenerStats::default());
+
+    // Coalesced receives are counted
+    for _ in 0..2 {
+        let req = packet(
+            Eid(20),
+            Eid(10),
+            Tag::Owned(TagValue(1)),
+            MsgType(0x11),
+            &[2],
+        );
+        block_on(router.inbound(&req, PortId(0)));
+    }
+    let (_, count) = block_on(other.recv_coalesced(&mut [0u8; 20], 4)).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(other.stats().received, 2);
+}
+
+#[test]
+fn recv_reply() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    let tv = TagValue(3);
+    let req = packet(Eid(20), Eid(10), Tag::Owned(tv), typ, &[1, 2, 3]);
+    block_on(router.inbound(&req, PortId(0)));
+
+    // Echo
+    let mut buf = [0u8; 10];
+    block_on(l.recv_reply(&mut buf, |msg, out| {
+        assert_eq!(msg.source, Eid(20));
+        out[..msg.payload.len()].copy_from_slice(msg.payload);
+        msg.payload.len()
+    }))
+    .unwrap();
+
+    let (pkt, _dest) = bottom.try_outbound().unwrap();
+    let hdr = crate::peek_header(pkt).unwrap();
+    assert_eq!(hdr.dest, Eid(20));
+    assert_eq!(hdr.tag, Tag::Unowned(tv));
+    assert_eq!(&pkt[HEADER_LEN..], &[typ.0, 1, 2, 3]);
+    bottom.outbound_done();
+    assert_eq!(l.stats().received, 1);
+
+    // A bad length sends nothing
+    block_on(router.inbound(&req, PortId(0)));
+    let r = block_on(l.recv_reply(&mut buf, |_msg, _out| 11));
+    assert!(matches!(r, Err(Error::BadArgument)));
+    assert!(bottom.try_outbound().is_none());
+}
+
+#[test]
+fn receivers_peak() {
+    use core::task::Context;
+    use std::sync::Arc;
+
+    start_log();
+    one_port_router!(router, mut bottom, 4, 255);
+    let typ = MsgType(0x10);
+
+    let mut reqs = std::vec::Vec::new();
+    for i in 0..3 {
+        let mut req = router.req(Eid(20 + i));
+        block_on(req.send(typ, &[1])).unwrap();
+        bottom.outbound_done();
+        reqs.push(req);
+    }
+    let tags = reqs
+        .iter()
+        .map(|r| (r.eid, r.sent_tag.unwrap().tag()))
+        .collect::<std::vec::Vec<_>>();
+
+    let mut bufs = [[0u8; 4]; 3];
+    let mut futs = reqs
+        .iter_mut()
+        .zip(bufs.iter_mut())
+        .map(|(r, b)| Box::pin(r.recv(b)))
+        .collect::<std::vec::Vec<_>>();
+
+    // Each receiver has a distinct waker
+    let wakers = (0..3)
+        .map(|_| Waker::from(Arc::new(CountWake::default())))
+        .collect::<std::vec::Vec<_>>();
+    for (f, w) in futs.iter_mut().zip(wakers.iter()) {
+        let mut cx = Context::from_waker(w);
+        assert!(f.as_mut().poll(&mut cx).is_pending());
+    }
+    assert_eq!(router.stats().receivers_peak, 3);
+
+    // Delivering a response wakes all receivers, peak is kept
+    let (eid, tv) = tags[0];
+    let resp = packet(eid, Eid(10), Tag::Unowned(tv), typ, &[2]);
+    block_on(router.inbound(&resp, PortId(0)));
+    let mut cx = Context::from_waker(&wakers[0]);
+    assert!(futs[0].as_mut().poll(&mut cx).is_ready());
+    assert_eq!(router.stats().receivers_peak, 3);
+
+    router.reset_stats();
+    assert_eq!(router.stats().receivers_peak, 0);
+    let mut cx = Context::from_waker(&wakers[1]);
+    assert!(futs[1].as_mut().poll(&mut cx).is_pending());
+    assert_eq!(router.stats().receivers_peak, 1);
+}
+
+#[test]
+fn type_priority() {
+    start_log();
+    one_port_router!(router, _bottom, 8, 255);
+    let control = mctp::MCTP_TYPE_CONTROL;
+    let bulk = MsgType(0x7e);
+    block_on(router.set_type_priority(control, 1)).unwrap();
+
+    // Create the catch-all bind
+    let mut buf = [0u8; 4];
+    let r = embassy_futures::poll_once(router.recv_any(&mut buf));
+    assert!(r.is_pending());
+
+    let tag = Tag::Owned(TagValue(1));
+    for (src, typ) in [(20, bulk), (21, bulk), (22, control), (23, control)] {
+        let req = packet(Eid(src), Eid(10), tag, typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+        // Separate timestamps
+        block_on(router.update_time(src as u64)).unwrap();
+    }
+
+    // Control first, FIFO within a priority
+    for (src, typ) in [(22, control), (23, control), (20, bulk), (21, bulk)] {
+        let (msg, _resp, _tag, rtyp, _ic) =
+            block_on(router.recv_any(&mut buf)).unwrap();
+        assert_eq!(msg, &[src]);
+        assert_eq!(rtyp, typ);
+    }
+
+    // Priority removed
+    block_on(router.set_type_priority(control, 0)).unwrap();
+    for (src, typ) in [(30, bulk), (31, control)] {
+        let req = packet(Eid(src), Eid(10), tag, typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+        block_on(router.update_time(src as u64)).unwrap();
+    }
+    let (msg, ..) = block_on(router.recv_any(&mut buf)).unwrap();
+    assert_eq!(msg, &[30]);
+    let (msg, ..) = block_on(router.recv_any(&mut buf)).unwrap();
+    assert_eq!(msg, &[31]);
+}
+
+#[test]
+fn reconfigure() {
+    start_log();
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, mut bottom0) = pb0.build(255).unwrap();
+    let (top1, mut bottom1) = pb1.build(255).unwrap();
+    let ports = [top0, top1];
+    let mut lookup_b = DefaultRoute(Some(PortId(1)));
+    let mut lookup_a = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup_a);
+    let typ = MsgType(0x10);
+    let _l = router.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    let traffic = async {
+        for i in 0..6 {
+            // Forwarded by either config
+            let fwd = packet(Eid(8), Eid(20), tag, typ, &[i]);
+            router.inbound(&fwd, PortId(0)).await;
+            // Local with the new config
+            let new = packet(Eid(8), Eid(12), tag, typ, &[i]);
+            router.inbound(&new, PortId(0)).await;
+            yield_now().await;
+        }
+    };
+    let reconf = async {
+        yield_now().await;
+        yield_now().await;
+        router
+            .reconfigure(|cfg| {
+                cfg.set_eid(Eid(12))?;
+                cfg.set_lookup(&mut lookup_b);
+                cfg.set_port_eid_range(PortId(1), Some(Eid(20)..=Eid(20)))
+            })
+            .await
+    };
+    let ((), r) = block_on(join(traffic, reconf));
+    r.unwrap();
+    assert_eq!(block_on(router.get_eid()), Eid(12));
+
+    let mut port0 = std::vec::Vec::new();
+    while let Some((pkt, dest)) = bottom0.try_outbound() {
+        port0.push((dest, pkt[HEADER_LEN + 1]));
+        bottom0.outbound_done();
+    }
+    let mut port1 = std::vec::Vec::new();
+    while let Some((pkt, dest)) = bottom1.try_outbound() {
+        port1.push((dest, pkt[HEADER_LEN + 1]));
+        bottom1.outbound_done();
+    }
+
+    // The old config forwarded both EIDs to port 0, the new config
+    // forwards only EID 20 to port 1.
+    let k = port1.first().unwrap().1;
+    assert!((1..6).contains(&k));
+    let old: std::vec::Vec<_> =
+        (0..k).flat_map(|i| [(Eid(20), i), (Eid(12), i)]).collect();
+    let new: std::vec::Vec<_> = (k..6).map(|i| (Eid(20), i)).collect();
+    assert_eq!(port0, old);
+    assert_eq!(port1, new);
+}
+
+#[test]
+fn reconfigure_lookup() {
+    start_log();
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, mut bottom0) = pb0.build(255).unwrap();
+    let (top1, mut bottom1) = pb1.build(255).unwrap();
+    let ports = [top0, top1];
+    let mut lookup_b = DefaultRoute(Some(PortId(1)));
+    let mut lookup_a = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup_a);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+    let fwd = packet(Eid(8), Eid(20), tag, typ, &[1]);
+
+    // Routed by lookup_a, repeated for the same EID
+    for _ in 0..2 {
+        block_on(router.inbound(&fwd, PortId(1)));
+        block_on(router.req(Eid(20)).send(typ, &[2])).unwrap();
+    }
+    let mut n = 0;
+    drain_port(&mut bottom0, |_, dest| {
+        assert_eq!(dest, Eid(20));
+        n += 1;
+    });
+    assert_eq!(n, 4);
+
+    block_on(router.reconfigure(|cfg| {
+        cfg.set_lookup(&mut lookup_b);
+        Ok(())
+    }))
+    .unwrap();
+
+    // The new mapping applies immediately
+    block_on(router.inbound(&fwd, PortId(0)));
+    block_on(router.req(Eid(20)).send(typ, &[2])).unwrap();
+    assert!(bottom0.try_outbound().is_none());
+    let mut n = 0;
+    drain_port(&mut bottom1, |_, dest| {
+        assert_eq!(dest, Eid(20));
+        n += 1;
+    });
+    assert_eq!(n, 2);
+}
+
+#[test]
+fn reconfigure_failed() {
+    start_log();
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, mut bottom0) = pb0.build(255).unwrap();
+    let (top1, mut bottom1) = pb1.build(255).unwrap();
+    let ports = [top0, top1];
+    let mut lookup_b = DefaultRoute(Some(PortId(1)));
+    let mut lookup_a = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup_a);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+
+    // A later error discards the earlier changes
+    let r = block_on(router.reconfigure(|cfg| {
+        cfg.set_eid(Eid(12))?;
+        assert_eq!(cfg.eid(), Eid(12));
+        cfg.set_lookup(&mut lookup_b);
+        cfg.set_port_enabled(PortId(0), false)?;
+        cfg.set_port_mtu(PortId(0), 2)
+    }));
+    assert!(matches!(r, Err(Error::BadArgument)));
+    assert_eq!(block_on(router.get_eid()), Eid(10));
+
+    let fwd = packet(Eid(8), Eid(20), tag, typ, &[1]);
+    block_on(router.inbound(&fwd, PortId(1)));
+    let (pkt, _) = bottom0.try_outbound().unwrap();
+    assert_eq!(pkt.len(), fwd.len());
+    bottom0.outbound_done();
+    assert!(bottom1.try_outbound().is_none());
+
+    // Bad arguments are rejected when staged
+    let r = block_on(router.reconfigure(|cfg| {
+        assert!(cfg.set_eid(Eid(0xff)).is_err());
+        assert!(cfg.set_baseline_mtu(MAX_MTU + 1).is_err());
+        assert!(cfg.set_port_enabled(PortId(2), false).is_err());
+        cfg.set_port_mtu(PortId(1), 100)
+    }));
+    r.unwrap();
+    assert_eq!(block_on(router.get_eid()), Eid(10));
+    assert_eq!(ports[1].mtu(), 100);
+}
+
+#[test]
+fn port_enabled() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+    let fwd = packet(Eid(8), Eid(20), tag, typ, &[1]);
+
+    assert!(router.set_port_enabled(PortId(1), false).is_err());
+    router.set_port_enabled(PortId(0), false).unwrap();
+
+    block_on(router.inbound(&fwd, PortId(0)));
+    assert_eq!(router.stats().forward_dropped_disabled, 1);
+    let r = block_on(router.req(Eid(20)).send(typ, &[2]));
+    assert!(matches!(r, Err(Error::TxFailure)));
+    assert!(bottom.try_outbound().is_none());
+
+    block_on(router.reconfigure(|cfg| cfg.set_port_enabled(PortId(0), true)))
+        .unwrap();
+    block_on(router.inbound(&fwd, PortId(0)));
+    block_on(router.req(Eid(20)).send(typ, &[2])).unwrap();
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 2);
+    assert_eq!(router.stats().forward_dropped_disabled, 1);
+}
+
+#[test]
+fn static_route_table() {
+    let mut t = StaticRouteTable::<3>::new();
+    assert_eq!(t.by_eid(Eid(20), None), None);
+
+    t.add_route(Eid(20), Eid(29), PortId(1)).unwrap();
+    t.add_route(Eid(30), Eid(30), PortId(2)).unwrap();
+    // Reversed range
+    assert!(matches!(
+        t.add_route(Eid(50), Eid(40), PortId(0)),
+        Err(Error::BadArgument)
+    ));
+    // Overlapping ranges
+    assert!(matches!(
+        t.add_route(Eid(10), Eid(20), PortId(0)),
+        Err(Error::BadArgument)
+    ));
+    assert!(matches!(
+        t.add_route(Eid(25), Eid(26), PortId(0)),
+        Err(Error::BadArgument)
+    ));
+    t.add_route(Eid(40), Eid(49), PortId(3)).unwrap();
+    assert!(matches!(
+        t.add_route(Eid(60), Eid(69), PortId(0)),
+        Err(Error::NoSpace)
+    ));
+
+    assert_eq!(t.by_eid(Eid(19), None), None);
+    assert_eq!(t.by_eid(Eid(20), None), Some(PortId(1)));
+    assert_eq!(t.by_eid(Eid(29), None), Some(PortId(1)));
+    assert_eq!(t.by_eid(Eid(30), None), Some(PortId(2)));
+    assert_eq!(t.by_eid(Eid(45), Some(PortId(1))), Some(PortId(3)));
+
+    t.set_default_route(Some(PortId(0)));
+    assert_eq!(t.by_eid(Eid(19), None), Some(PortId(0)));
+    assert_eq!(t.by_eid(Eid(30), None), Some(PortId(2)));
+
+    // Must match an added range
+    assert!(matches!(
+        t.remove_route(Eid(40), Eid(45)),
+        Err(Error::BadArgument)
+    ));
+    assert_eq!(t.remove_route(Eid(40), Eid(49)).unwrap(), PortId(3));
+    assert_eq!(t.by_eid(Eid(45), None), Some(PortId(0)));
+    t.set_default_route(None);
+    assert_eq!(t.by_eid(Eid(45), None), None);
+}
+
+#[test]
+fn eid_assigned() {
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(mctp::MCTP_ADDR_NULL, 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    assert!(!router.is_eid_assigned());
+
+    assert!(block_on(router.set_eid(Eid(0xff))).is_err());
+    assert!(!router.is_eid_assigned());
+
+    block_on(router.set_eid(Eid(10))).unwrap();
+    assert!(router.is_eid_assigned());
+
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(mctp::MCTP_ADDR_NULL, 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    block_on(router.reconfigure(|cfg| cfg.set_eid(Eid(11)))).unwrap();
+    assert!(router.is_eid_assigned());
+}
+
+#[test]
+fn reassemble_across_ports() {
+    use mctp::AsyncListener;
+
+    start_log();
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, _bottom0) = pb0.build(64).unwrap();
+    let (top1, _bottom1) = pb1.build(64).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+
+    // Reassembly is keyed on source EID and tag, not the incoming port
+    let b = PacketBuilder::new(Eid(20), Eid(10), Tag::Owned(TagValue(1)), typ);
+    let payload: std::vec::Vec<u8> = (0..150).collect();
+    let pkts = b
+        .fragments(&payload, 64)
+        .collect::<Result<std::vec::Vec<_>>>();
+    let pkts = pkts.unwrap();
+    assert_eq!(pkts.len(), 3);
+    for (i, p) in pkts.iter().enumerate() {
+        block_on(router.inbound(p, PortId((i % 2) as u8)));
+    }
+
+    let mut buf = [0u8; 200];
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, payload.as_slice());
+}
+
+#[test]
+fn peer_mtu() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    let mtu = HEADER_LEN + 32;
+    block_on(router.set_peer_mtu(Eid(20), Some(mtu))).unwrap();
+    assert!(block_on(router.set_peer_mtu(Eid(20), Some(HEADER_LEN))).is_err());
+
+    let mut lens = |eid| {
+        let mut req = router.req(eid);
+        block_on(req.send(typ, &[1; 100])).unwrap();
+        let mut lens = std::vec::Vec::new();
+        while let Some((pkt, _dest)) = bottom.try_outbound() {
+            lens.push(pkt.len());
+            bottom.outbound_done();
+        }
+        lens
+    };
+
+    // Limited by the peer MTU, 101 bytes including the type
+    assert_eq!(lens(Eid(20)), [mtu, mtu, mtu, HEADER_LEN + 5]);
+    // Other peers use the port MTU
+    assert_eq!(lens(Eid(21)), [HEADER_LEN + 101]);
+
+    block_on(router.set_peer_mtu(Eid(20), None)).unwrap();
+    assert_eq!(lens(Eid(20)), [HEADER_LEN + 101]);
+}
+
+#[test]
+fn await_route() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup_some = DefaultRoute(Some(PortId(0)));
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+
+    // The local EID doesn't need a route
+    block_on(router.await_route(Eid(10))).unwrap();
+
+    let mut wait = core::pin::pin!(router.await_route(Eid(20)));
+    assert!(embassy_futures::poll_once(wait.as_mut()).is_pending());
+
+    // Unrelated change
+    block_on(router.reconfigure(|cfg| cfg.set_baseline_mtu(100))).unwrap();
+    assert!(embassy_futures::poll_once(wait.as_mut()).is_pending());
+
+    block_on(router.reconfigure(|cfg| {
+        cfg.set_lookup(&mut lookup_some);
+        Ok(())
+    }))
+    .unwrap();
+    let r = embassy_futures::poll_once(wait.as_mut());
+    assert!(matches!(r, Poll::Ready(Ok(()))));
+}
+
+#[test]
+fn await_route_local() {
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(mctp::MCTP_ADDR_NULL, 255, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+
+    // Woken by set_eid()
+    let mut wait = core::pin::pin!(router.await_route(Eid(10)));
+    assert!(embassy_futures::poll_once(wait.as_mut()).is_pending());
+    block_on(router.set_eid(Eid(10))).unwrap();
+    let r = embassy_futures::poll_once(wait.as_mut());
+    assert!(matches!(r, Poll::Ready(Ok(()))));
+
+    // Woken by add_local_eid()
+    let mut wait = core::pin::pin!(router.await_route(Eid(11)));
+    assert!(embassy_futures::poll_once(wait.as_mut()).is_pending());
+    block_on(router.add_local_eid(Eid(11))).unwrap();
+    let r = embassy_futures::poll_once(wait.as_mut());
+    assert!(matches!(r, Poll::Ready(Ok(()))));
+
+    // Additional local EIDs are routable
+    block_on(router.await_route(Eid(11))).unwrap();
+}
+
+#[test]
+fn unroutable_response() {
+    /// Only routes EID 20
+    struct OneRoute;
+
+    impl PortLookup for OneRoute {
+        fn by_eid(
+            &mut self,
+            eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            (eid == Eid(20)).then_some(PortId(0))
+        }
+    }
+
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = OneRoute;
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let control = mctp::MCTP_TYPE_CONTROL;
+    let tv = TagValue(5);
+
+    // Get Endpoint ID request, instance 3
+    let req = packet(Eid(20), Eid(30), Tag::Owned(tv), control, &[0x83, 0x02]);
+
+    // Dropped by default
+    block_on(router.inbound(&req, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+
+    block_on(router.set_unroutable_response(true));
+    block_on(router.inbound(&req, PortId(0)));
+    let (pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(20));
+    let hdr = crate::peek_header(pkt).unwrap();
+    assert_eq!(hdr.source, Eid(10));
+    assert_eq!(hdr.dest, Eid(20));
+    assert_eq!(hdr.tag, Tag::Unowned(tv));
+    assert!(hdr.som && hdr.eom);
+    assert_eq!(&pkt[HEADER_LEN..], &[0x00, 0x03, 0x02, 0x01]);
+    bottom.outbound_done();
+
+    // Other types and responses are not answered
+    let other = packet(
+        Eid(20),
+        Eid(30),
+        Tag::Owned(tv),
+        MsgType(0x10),
+        &[0x83, 0x02],
+    );
+    block_on(router.inbound(&other, PortId(0)));
+    let resp =
+        packet(Eid(20), Eid(30), Tag::Unowned(tv), control, &[0x03, 0x02]);
+    block_on(router.inbound(&resp, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+}
+
+#[test]
+fn port_has_space() {
+    start_log();
+    one_port_router!(router, mut bottom, 4, 64);
+    let typ = MsgType(0x10);
+
+    assert_eq!(router.port_has_space(PortId(0), 4).unwrap(), Some(true));
+    assert_eq!(router.port_has_space(PortId(0), 5).unwrap(), Some(false));
+    assert!(matches!(
+        router.port_has_space(PortId(1), 0),
+        Err(Error::BadArgument)
+    ));
+
+    // 3 packets queued
+    let mut req = router.req(Eid(20));
+    block_on(req.send(typ, &[1; 150])).unwrap();
+    assert_eq!(router.port_has_space(PortId(0), 1).unwrap(), Some(true));
+    assert_eq!(router.port_has_space(PortId(0), 2).unwrap(), Some(false));
+
+    bottom.try_outbound().unwrap();
+    bottom.outbound_done();
+    assert_eq!(router.port_has_space(PortId(0), 2).unwrap(), Some(true));
+
+    // Unknown while another send holds the queue
+    let sender = router.ports[0].packets.try_lock().unwrap();
+    assert_eq!(router.port_has_space(PortId(0), 1).unwrap(), None);
+    drop(sender);
+    assert_eq!(router.port_has_space(PortId(0), 1).unwrap(), Some(true));
+}
+
+#[test]
+fn unroutable_hook() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Unroutable(Mutex<std::vec::Vec<(PacketHeader, PortId)>>);
+
+    impl RouterHooks for Unroutable {
+        fn on_unroutable(&self, header: &PacketHeader, port: PortId) {
+            self.0.lock().unwrap().push((*header, port));
+        }
+    }
+
+    start_log();
+    let hooks = Unroutable::default();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    block_on(router.set_hooks(Some(&hooks)));
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(2));
+
+    // Not called for local packets
+    let local = packet(Eid(20), Eid(10), tag, typ, &[1]);
+    block_on(router.inbound(&local, PortId(0)));
+    assert!(hooks.0.lock().unwrap().is_empty());
+
+    let fwd = PacketBuilder::new(Eid(20), Eid(30), tag, typ)
+        .eom(false)
+        .build(&[1; 10])
+        .unwrap();
+    block_on(router.inbound(&fwd, PortId(0)));
+    let seen = hooks.0.lock().unwrap();
+    assert_eq!(
+        seen.as_slice(),
+        &[(
+            PacketHeader {
+                source: Eid(20),
+                dest: Eid(30),
+                tag,
+                som: true,
+                eom: false,
+                seq: 0,
+            },
+            PortId(0)
+        )]
+    );
+}
+
+#[test]
+fn no_listener() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct NoListener(Mutex<std::vec::Vec<(Eid, MsgType)>>);
+
+    impl RouterHooks for NoListener {
+        fn on_no_listener(&self, source: Eid, typ: MsgType) {
+            self.0.lock().unwrap().push((source, typ));
+        }
+    }
+
+    start_log();
+    let hooks = NoListener::default();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    block_on(router.set_hooks(Some(&hooks)));
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(2));
+    let l = router.listener(typ).unwrap();
+    l.set_ic_policy(IcPolicy::Forbid);
+
+    // Bound type, counted by the listener rather than here
+    let bound = PacketBuilder::new(Eid(20), Eid(10), tag, typ)
+        .ic(true)
+        .build(&[1])
+        .unwrap();
+    block_on(router.inbound(&bound, PortId(0)));
+    assert_eq!(router.stats().local_no_listener, 0);
+
+    // Unbound type, two packets
+    let other = || PacketBuilder::new(Eid(20), Eid(10), tag, MsgType(0x11));
+    let p1 = other().eom(false).build(&[1; 59]).unwrap();
+    let p2 = other().som(false).seq(1).build(&[2; 10]).unwrap();
+    block_on(router.inbound(&p1, PortId(0)));
+    assert_eq!(router.stats().local_no_listener, 0);
+    block_on(router.inbound(&p2, PortId(0)));
+    assert_eq!(router.stats().local_no_listener, 1);
+    assert_eq!(
+        hooks.0.lock().unwrap().as_slice(),
+        &[(Eid(20), MsgType(0x11))]
+    );
+    // The reassembly slot is released
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+}
+
+#[test]
+fn const_port_storage() {
+    // Evaluated at compile time
+    const STORAGE: PortStorage<2, 64> = PortStorage::new();
+
+    start_log();
+    let mut storage = STORAGE;
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send_vectored(MsgType(0x10), false, &[&[1, 2], &[3]]))
+        .unwrap();
+    let (pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(20));
+    assert_eq!(&pkt[HEADER_LEN..], &[0x10, 1, 2, 3]);
+}
+
+#[test]
+fn broadcast_listener() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(2));
+    let bcast = packet(Eid(20), mctp::MCTP_ADDR_ANY, tag, typ, &[5]);
+    let mut buf = [0u8; 4];
+
+    // Forwarded only
+    block_on(router.inbound(&bcast, PortId(0)));
+    bottom.try_outbound().unwrap();
+    bottom.outbound_done();
+    let r = embassy_futures::poll_once(l.recv(&mut buf));
+    assert!(r.is_pending());
+
+    // Received and forwarded
+    l.set_broadcast(true);
+    block_on(router.inbound(&bcast, PortId(0)));
+    let (pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, mctp::MCTP_ADDR_ANY);
+    assert_eq!(pkt, bcast.as_slice());
+    bottom.outbound_done();
+    let (msg, mut resp, rtag, _typ, _ic) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[5]);
+    assert_eq!(rtag, tag);
+
+    // Response from the local EID
+    block_on(resp.send(typ, &[6])).unwrap();
+    let (pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(20));
+    assert_eq!(crate::peek_header(pkt).unwrap().source, Eid(10));
+    bottom.outbound_done();
+
+    // Other types aren't received
+    let other = packet(Eid(20), mctp::MCTP_ADDR_ANY, tag, MsgType(0x11), &[5]);
+    let mut l2 = router.listener(MsgType(0x11)).unwrap();
+    block_on(router.inbound(&other, PortId(0)));
+    let r = embassy_futures::poll_once(l2.recv(&mut buf));
+    assert!(r.is_pending());
+}
+
+#[test]
+fn free_tags() {
+    start_log();
+    one_port_router!(router, mut bottom, 16, 64);
+    let typ = MsgType(0x10);
+
+    block_on(async {
+        let total = mctp::MCTP_TAG_MAX as usize + 1;
+        assert_eq!(router.free_tags(Eid(20)).await, total);
+
+        let mut reqs: std::vec::Vec<_> =
+            (0..total).map(|_| router.req(Eid(20))).collect();
+        for (i, r) in reqs.iter_mut().enumerate() {
+            r.send(typ, &[1]).await.unwrap();
+            bottom.try_outbound().unwrap();
+            bottom.outbound_done();
+            assert_eq!(router.free_tags(Eid(20)).await, total - i - 1);
+        }
+
+        // Exhausted, other peers are unaffected
+        let r = router.req(Eid(20)).send(typ, &[1]).await;
+        assert!(matches!(r, Err(Error::TagUnavailable)));
+        assert_eq!(router.free_tags(Eid(21)).await, total);
+    });
+}
+
+#[test]
+fn control_lane() {
+    use core::future::Future;
+    use core::task::Context;
+    use mctp::{AsyncListener, AsyncRespChannel};
+    use std::sync::Arc;
+
+    start_log();
+    one_port_router!(router, mut bottom, 4, 64);
+    let control = mctp::MCTP_TYPE_CONTROL;
+    let bulk = MsgType(0x7e);
+    block_on(router.set_control_lane(true));
+
+    let mut bulk_l = router.listener(bulk).unwrap();
+    let mut control_l = router.listener(control).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+    let mut buf = [0u8; 100];
+
+    // Incomplete bulk messages fill all but the last reassembler
+    for src in 20..20 + crate::NUM_RECEIVE as u8 {
+        let b = PacketBuilder::new(Eid(src), Eid(10), tag, bulk);
+        let first = b.fragments(&[src; 100], 64).next().unwrap().unwrap();
+        block_on(router.inbound(&first, PortId(0)));
+    }
+    assert_eq!(
+        block_on(router.snapshot()).reassembly.len(),
+        crate::NUM_RECEIVE - 1
+    );
+
+    // Control is still received
+    let req = packet(Eid(30), Eid(10), tag, control, &[1]);
+    block_on(router.inbound(&req, PortId(0)));
+    let (msg, mut resp, ..) = block_on(control_l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[1]);
+
+    // Bulk sends fill all but the last queue slot
+    let mut reqs: std::vec::Vec<_> =
+        (0..4).map(|_| router.req(Eid(40))).collect();
+    for r in reqs.iter_mut().take(3) {
+        block_on(r.send(bulk, &[2])).unwrap();
+    }
+    let woken = Arc::new(CountWake::default());
+    let waker = woken.clone().into();
+    let mut cx = Context::from_waker(&waker);
+    let mut send = Box::pin(reqs[3].send(bulk, &[2]));
+    assert!(send.as_mut().poll(&mut cx).is_pending());
+    // Waits for the port rather than spinning
+    assert_eq!(woken.count(), 0);
+    // Forwarded bulk is dropped
+    let fwd = packet(Eid(30), Eid(40), tag, bulk, &[3]);
+    block_on(router.inbound(&fwd, PortId(0)));
+
+    // Control response is sent
+    block_on(resp.send(control, &[4])).unwrap();
+    for _ in 0..3 {
+        let (pkt, _dest) = bottom.try_outbound().unwrap();
+        assert_eq!(pkt[HEADER_LEN], bulk.0);
+        bottom.outbound_done();
+    }
+    assert!(woken.count() > 0);
+    drop(send);
+    let (pkt, dest) = bottom.try_outbound().unwrap();
+    assert_eq!(dest, Eid(30));
+    assert_eq!(&pkt[HEADER_LEN..], &[control.0, 4]);
+    bottom.outbound_done();
+    assert!(bottom.try_outbound().is_none());
+
+    // Bulk traffic continues once there is room
+    block_on(reqs[3].send(bulk, &[2])).unwrap();
+    let r = embassy_futures::poll_once(bulk_l.recv(&mut buf));
+    assert!(r.is_pending());
+}
+
+#[test]
+fn control_lane_forward() {
+    start_log();
+    one_port_router!(router, mut bottom, 4, 64);
+    let control = mctp::MCTP_TYPE_CONTROL;
+    let bulk = MsgType(0x7e);
+    let tag = Tag::Owned(TagValue(1));
+    block_on(router.set_control_lane(true));
+
+    for i in 0..2 {
+        let fwd = packet(Eid(30), Eid(40), tag, bulk, &[i]);
+        block_on(router.inbound(&fwd, PortId(0)));
+    }
+    // A two packet control message. The continuation packet
+    // uses the reserved slot.
+    let b = PacketBuilder::new(Eid(31), Eid(40), tag, control);
+    let frags: std::vec::Vec<_> =
+        b.fragments(&[5u8; 100], 64).map(|f| f.unwrap()).collect();
+    assert_eq!(frags.len(), 2);
+    for f in &frags {
+        block_on(router.inbound(f, PortId(0)));
+    }
+
+    let mut out = std::vec::Vec::new();
+    drain_port(&mut bottom, |pkt, _dest| out.push(pkt.to_vec()));
+    assert_eq!(out.len(), 4);
+    assert_eq!(out[2], frags[0].as_slice());
+    assert_eq!(out[3], frags[1].as_slice());
+
+    // A bulk continuation from the same source isn't a control packet
+    let b = PacketBuilder::new(Eid(31), Eid(40), tag, bulk);
+    let frags: std::vec::Vec<_> =
+        b.fragments(&[6u8; 100], 64).map(|f| f.unwrap()).collect();
+    for i in 0..2 {
+        let fwd = packet(Eid(30), Eid(40), tag, bulk, &[i]);
+        block_on(router.inbound(&fwd, PortId(0)));
+    }
+    for f in &frags {
+        block_on(router.inbound(f, PortId(0)));
+    }
+    let mut out = std::vec::Vec::new();
+    drain_port(&mut bottom, |pkt, _dest| out.push(pkt.to_vec()));
+    assert_eq!(out.len(), 3);
+    assert_eq!(out[2], frags[0].as_slice());
+}
+
+#[test]
+fn listener_drain() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    for src in 20..23 {
+        let req = packet(Eid(src), Eid(10), tag, typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+    }
+
+    let mut got = std::vec::Vec::new();
+    let n = block_on(l.drain(|t, eid, payload| {
+        assert_eq!(t, typ);
+        got.push((eid, payload.to_vec()));
+    }));
+    assert_eq!(n, 3);
+    assert_eq!(
+        got,
+        [
+            (Eid(20), std::vec![20]),
+            (Eid(21), std::vec![21]),
+            (Eid(22), std::vec![22]),
+        ]
+    );
+    assert_eq!(l.stats().received, 3);
+
+    // Nothing is left waiting
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+    let mut buf = [0u8; 4];
+    let r = embassy_futures::poll_once(l.recv(&mut buf));
+    assert!(r.is_pending());
+    assert_eq!(block_on(l.drain(|_, _, _| ())), 0);
+    drop(l);
+}
+
+#[test]
+fn port_packet_sizes() {
+    start_log();
+    one_port_router!(router, mut bottom, 16, 255);
+    let typ = MsgType(0x10);
+    router.set_port_mtu(PortId(0), 64).unwrap();
+
+    block_on(async {
+        // 6, 30, then 64 + 64 + 14 bytes with the 64 byte MTU
+        for len in [1, 25, 129] {
+            let mut req = router.req(Eid(20));
+            req.send(typ, &[0u8; 129][..len]).await.unwrap();
+        }
+        // Forwarded, 200 bytes
+        router.set_port_mtu(PortId(0), 255).unwrap();
+        let tag = Tag::Owned(TagValue(1));
+        let fwd = packet(Eid(30), Eid(20), tag, typ, &[0u8; 195]);
+        router.inbound(&fwd, PortId(0)).await;
+    });
+    while bottom.try_outbound().is_some() {
+        bottom.outbound_done();
+    }
+
+    let sizes = router.port_packet_sizes(PortId(0)).unwrap();
+    assert_eq!(sizes.buckets, [2, 1, 2, 0, 1, 0]);
+    assert_eq!(sizes.max, 200);
+    assert_eq!(PacketSizes::bucket_limit(2), Some(64));
+    assert_eq!(PacketSizes::bucket_limit(5), None);
+    assert!(router.port_packet_sizes(PortId(1)).is_err());
+}
+
+#[test]
+fn port_latency() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    // Not recorded by default
+    block_on(router.update_time(1000)).unwrap();
+    block_on(router.req(Eid(20)).send(typ, &[1])).unwrap();
+    block_on(router.update_time(1500)).unwrap();
+    drain_port(&mut bottom, |_, _| ());
+    assert_eq!(router.port_latency(PortId(0)).unwrap().mean(), None);
+
+    router.set_port_latency(PortId(0), true).unwrap();
+
+    // A prompt consumer
+    block_on(router.req(Eid(20)).send(typ, &[1])).unwrap();
+    drain_port(&mut bottom, |_, _| ());
+    let l = router.port_latency(PortId(0)).unwrap();
+    assert_eq!((l.count, l.mean(), l.max), (1, Some(0), 0));
+
+    // A slow consumer, taking 100ms then 300ms
+    block_on(router.req(Eid(20)).send(typ, &[2])).unwrap();
+    block_on(router.req(Eid(20)).send(typ, &[3])).unwrap();
+    block_on(router.update_time(1600)).unwrap();
+    bottom.try_outbound().unwrap();
+    bottom.outbound_done();
+    block_on(router.update_time(1800)).unwrap();
+    bottom.try_outbound().unwrap();
+    bottom.outbound_done();
+
+    let l = router.port_latency(PortId(0)).unwrap();
+    assert_eq!(l.count, 3);
+    assert_eq!(l.total, 400);
+    assert_eq!(l.max, 300);
+    assert_eq!(l.mean(), Some(133));
+    assert!(router.port_latency(PortId(1)).is_err());
+}
+
+#[test]
+fn tag_lifetime() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+
+    block_on(async {
+        let mut short = router.req(Eid(20));
+        short.tag_lifetime(200).unwrap();
+        short.send(typ, &[1]).await.unwrap();
+        let mut default = router.req(Eid(20));
+        default.send(typ, &[1]).await.unwrap();
+        // Can't change after sending
+        assert!(short.tag_lifetime(100).is_err());
+        assert_eq!(router.snapshot().await.flows.len(), 2);
+
+        router.update_time(150).await.unwrap();
+        assert_eq!(router.snapshot().await.flows.len(), 2);
+
+        // Only the short lifetime tag is reclaimed
+        router.update_time(300).await.unwrap();
+        let flows = router.snapshot().await.flows;
+        assert_eq!(flows.len(), 1);
+        assert_eq!(Tag::Owned(flows[0].1), default.sent_tag.unwrap());
+    });
+    while bottom.try_outbound().is_some() {
+        bottom.outbound_done();
+    }
+}
+
+#[test]
+fn dump() {
+    start_log();
+    one_port_router!(router, mut bottom, 4, 64);
+    let typ = MsgType(0x10);
+    let _l = router.listener(typ).unwrap();
+
+    let mut req = router.req(Eid(20));
+    let tv = block_on(async {
+        req.send(typ, &[1]).await.unwrap();
+        req.sent_tag.unwrap().tag()
+    });
+    // Incomplete incoming message
+    let first = PacketBuilder::new(Eid(30), Eid(10), Tag::Owned(tv), typ)
+        .fragments(&[0u8; 100], 64)
+        .next()
+        .unwrap()
+        .unwrap();
+    block_on(router.inbound(&first, PortId(0)));
+
+    let mut s = heapless::String::<500>::new();
+    block_on(router.dump(&mut s)).unwrap();
+    assert!(s.contains("EID 10\n"), "{s}");
+    assert!(s.contains("port 0 mtu 64 queue 1/4 max packet 6\n"), "{s}");
+    assert!(s.contains("listener 0 type 16 received 0"), "{s}");
+    assert!(s.contains(&std::format!("flow eid 20 tag {}\n", tv.0)));
+    assert!(s.contains("reassembly eid 30"), "{s}");
+    assert!(s.contains("active len 59\n"), "{s}");
+    assert!(s.contains("reassembly 1/4 used"), "{s}");
+
+    // Too small a sink fails
+    let mut small = heapless::String::<10>::new();
+    assert!(block_on(router.dump(&mut small)).is_err());
+    bottom.outbound_done();
+}
+
+#[test]
+fn sent_waker() {
+    use std::sync::Arc;
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let count = Arc::new(CountWake::default());
+    let sent = || count.count();
+
+    let mut req = router.req(Eid(20));
+    req.tag_noexpire().unwrap();
+    req.set_sent_waker(Some(count.clone().into()));
+    // Three packets
+    block_on(req.send(typ, &[1u8; 150])).unwrap();
+    // A forwarded packet behind it
+    let t = Tag::Owned(TagValue(1));
+    let fwd = packet(Eid(30), Eid(21), t, typ, &[2]);
+    block_on(router.inbound(&fwd, PortId(0)));
+    assert_eq!(sent(), 0);
+
+    for _ in 0..2 {
+        bottom.try_outbound().unwrap();
+        bottom.outbound_done();
+        assert_eq!(sent(), 0);
+    }
+    // Peeking doesn't complete the send
+    bottom.try_outbound().unwrap();
+    assert_eq!(sent(), 0);
+    bottom.outbound_done();
+    assert_eq!(sent(), 1);
+
+    // Forwarded packet doesn't wake
+    bottom.try_outbound().unwrap();
+    bottom.outbound_done();
+    assert_eq!(sent(), 1);
+
+    // Each send wakes
+    block_on(req.send(typ, &[3])).unwrap();
+    assert_eq!(sent(), 1);
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    assert_eq!(sent(), 2);
+
+    // Disabled
+    req.set_sent_waker(None);
+    block_on(req.send(typ, &[4])).unwrap();
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    assert_eq!(sent(), 2);
+    block_on(req.async_drop());
+}
+
+#[test]
+fn expiry_wakeups() {
+    use core::task::Context;
+    use std::sync::Arc;
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+
+    // Three short lifetime tags, one default
+    let mut reqs = std::vec::Vec::new();
+    for i in 0..4 {
+        let mut req = router.req(Eid(20 + i));
+        if i < 3 {
+            req.tag_lifetime(100).unwrap();
+        }
+        block_on(req.send(typ, &[1])).unwrap();
+        reqs.push(req);
+    }
+    while bottom.try_outbound().is_some() {
+        bottom.outbound_done();
+    }
+
+    let mut bufs = [[0u8; 4]; 4];
+    let mut futs = reqs
+        .iter_mut()
+        .zip(bufs.iter_mut())
+        .map(|(r, b)| Box::pin(r.recv(b)))
+        .collect::<std::vec::Vec<_>>();
+    let counts = (0..4)
+        .map(|_| Arc::new(CountWake::default()))
+        .collect::<std::vec::Vec<_>>();
+    for (fut, c) in futs.iter_mut().zip(counts.iter()) {
+        let waker = c.clone().into();
+        let mut cx = Context::from_waker(&waker);
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+    }
+
+    // All three expire in the same tick
+    block_on(router.update_time(50)).unwrap();
+    block_on(router.update_time(500)).unwrap();
+    assert_eq!(block_on(router.snapshot()).flows.len(), 1);
+    let n = counts
+        .iter()
+        .map(|c| c.count())
+        .collect::<std::vec::Vec<_>>();
+    assert_eq!(n, [1, 1, 1, 0]);
+
+    // No further wakeups
+    block_on(router.update_time(1000)).unwrap();
+    assert_eq!(counts[0].count(), 1);
+}
+
+#[test]
+fn link_errors() {
+    start_log();
+    let mut storage0 = PortStorage::<4>::new();
+    let mut storage1 = PortStorage::<4>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, _bottom0) = pb0.build(64).unwrap();
+    let (top1, _bottom1) = pb1.build(64).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+
+    router.note_link_error(PortId(0), LinkError::Crc).unwrap();
+    router.note_link_error(PortId(0), LinkError::Crc).unwrap();
+    router
+        .note_link_error(PortId(0), LinkError::Framing)
+        .unwrap();
+    router
+        .note_link_error(PortId(1), LinkError::Overrun)
+        .unwrap();
+    assert!(router.note_link_error(PortId(2), LinkError::Other).is_err());
+
+    let e0 = router.port_link_errors(PortId(0)).unwrap();
+    assert_eq!(
+        e0,
+        LinkErrorStats {
+            crc: 2,
+            framing: 1,
+            ..Default::default()
+        }
+    );
+    let e1 = router.port_link_errors(PortId(1)).unwrap();
+    assert_eq!(e1.overrun, 1);
+    assert_eq!(router.stats().link_errors, 4);
+}
+
+#[test]
+fn port_stats() {
+    struct OneRoute;
+
+    impl PortLookup for OneRoute {
+        fn by_eid(
+            &mut self,
+            eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            (eid == Eid(20)).then_some(PortId(1))
+        }
+    }
+
+    start_log();
+    let mut storage0 = PortStorage::<4>::new();
+    let mut storage1 = PortStorage::<2>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top0, _bottom0) = pb0.build(64).unwrap();
+    let (top1, mut bottom1) = pb1.build(64).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = OneRoute;
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+
+    // Local send of 2 packets, 64 + 14 bytes
+    block_on(router.req(Eid(20)).send(typ, &[0; 69])).unwrap();
+    drain_port(&mut bottom1, |_, _| ());
+
+    // Forwarded from port 0 to port 1, 7 bytes each.
+    // The third is dropped with the queue full.
+    for i in 0..3 {
+        let fwd = packet(Eid(8), Eid(20), tag, typ, &[i, i]);
+        block_on(router.inbound(&fwd, PortId(0)));
+    }
+    // Too large after reducing the MTU
+    router.set_port_mtu(PortId(1), 6).unwrap();
+    let fwd = packet(Eid(8), Eid(20), tag, typ, &[3, 3]);
+    block_on(router.inbound(&fwd, PortId(0)));
+    // No route
+    let fwd = packet(Eid(8), Eid(30), tag, typ, &[4]);
+    block_on(router.inbound(&fwd, PortId(0)));
+
+    let s0 = router.port_stats(PortId(0)).unwrap();
+    assert_eq!(
+        s0,
+        PortStats {
+            rx_packets: 5,
+            dropped_noroute: 1,
+            ..Default::default()
+        }
+    );
+    let s1 = router.port_stats(PortId(1)).unwrap();
+    assert_eq!(
+        s1,
+        PortStats {
+            tx_packets: 4,
+            tx_bytes: 64 + 14 + 7 + 7,
+            forwarded: 2,
+            dropped_queue_full: 1,
+            dropped_too_large: 1,
+            ..Default::default()
+        }
+    );
+    assert!(router.port_stats(PortId(2)).is_none());
+
+    router.reset_stats();
+    assert_eq!(router.port_stats(PortId(1)), Some(PortStats::default()));
+}
+
+#[test]
+fn listener_buffered() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, _bottom, 8, 64);
+    let slow_typ = MsgType(0x10);
+    let other_typ = MsgType(0x11);
+    assert!(router.listener_buffered(slow_typ, 0).is_err());
+    let mut slow = router.listener_buffered(slow_typ, 2).unwrap();
+    let mut other = router.listener(other_typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    // Flood the buffered listener
+    for src in 20..30 {
+        let req = packet(Eid(src), Eid(10), tag, slow_typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+    }
+    assert_eq!(slow.stats().queue_dropped, 8);
+    assert_eq!(block_on(router.snapshot()).reassembly.len(), 2);
+
+    // Other listeners still receive
+    for src in 30..32 {
+        let req = packet(Eid(src), Eid(10), tag, other_typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+        let mut buf = [0u8; 4];
+        let (msg, ..) = block_on(other.recv(&mut buf)).unwrap();
+        assert_eq!(msg, &[src]);
+    }
+
+    // The first messages were kept
+    let mut buf = [0u8; 4];
+    let (msg, ..) = block_on(slow.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[20]);
+    let (msg, ..) = block_on(slow.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[21]);
+}
+
+#[test]
+fn pending_types() {
+    use mctp::AsyncListener;
+
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &[], &mut lookup);
+    let typ_a = MsgType(0x10);
+    let typ_b = MsgType(0x11);
+    let typ_idle = MsgType(0x12);
+    let mut list_a = router.listener(typ_a).unwrap();
+    let mut list_b = router.listener(typ_b).unwrap();
+    let _list_idle = router.listener(typ_idle).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+    assert!(block_on(router.pending_types()).is_empty());
+
+    for (src, typ) in [(20, typ_b), (21, typ_a), (22, typ_b)] {
+        let req = packet(Eid(src), Eid(10), tag, typ, &[src]);
+        block_on(router.inbound(&req, PortId(0)));
+    }
+    // Unbound type isn't listed
+    let req = packet(Eid(23), Eid(10), tag, MsgType(0x13), &[23]);
+    block_on(router.inbound(&req, PortId(0)));
+
+    let mut pending = block_on(router.pending_types());
+    pending.sort_unstable();
+    assert_eq!(pending, [typ_a, typ_b]);
+
+    let mut buf = [0u8; 4];
+    block_on(list_a.recv(&mut buf)).unwrap();
+    assert_eq!(block_on(router.pending_types()), [typ_b]);
+    block_on(list_b.recv(&mut buf)).unwrap();
+    assert_eq!(block_on(router.pending_types()), [typ_b]);
+    block_on(list_b.recv(&mut buf)).unwrap();
+    assert!(block_on(router.pending_types()).is_empty());
+}
+
+#[test]
+fn routing_only() {
+    use mctp::AsyncListener;
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let typ = MsgType(0x10);
+    let mut listener = router.listener(typ).unwrap();
+    let tag = Tag::Owned(TagValue(1));
+
+    block_on(router.set_routing_only(true));
+    let req = packet(Eid(20), Eid(10), tag, typ, &[1]);
+    block_on(router.inbound(&req, PortId(0)));
+    assert!(block_on(router.snapshot()).reassembly.is_empty());
+    let mut n = 0;
+    drain_port(&mut bottom, |pkt, dest| {
+        assert_eq!(dest, Eid(10));
+        assert_eq!(pkt, req.as_slice());
+        n += 1;
+    });
+    assert_eq!(n, 1);
+
+    // Delivered locally once disabled
+    block_on(router.set_routing_only(false));
+    block_on(router.inbound(&req, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+    let mut buf = [0u8; 4];
+    let (msg, ..) = block_on(listener.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[1]);
+}
+
+#[test]
+fn max_forwards() {
+    start_log();
+    let mut storage = PortStorage::<16>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(64).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let tag = Tag::Owned(TagValue(1));
+    block_on(router.set_max_forwards(Some(2)));
+
+    // A packet looping back to the router
+    let mut pkt = packet(Eid(8), Eid(20), tag, typ, &[1]);
+    let mut forwarded = 0;
+    for _ in 0..5 {
+        block_on(router.inbound(&pkt, PortId(0)));
+        if let Some((p, _dest)) = bottom.try_outbound() {
+            pkt = Vec::from_slice(p).unwrap();
+            bottom.outbound_done();
+            forwarded += 1;
+        }
+    }
+    assert_eq!(forwarded, 2);
+    assert_eq!(router.stats().forward_dropped_loop, 3);
+    let stats = router.port_stats(PortId(0)).unwrap();
+    assert_eq!(stats.dropped_loop, 3);
+
+    // Same source and tag, different sequence number
+    let other = PacketBuilder::new(Eid(8), Eid(20), tag, typ)
+        .seq(1)
+        .build(&[1])
+        .unwrap();
+    block_on(router.inbound(&other, PortId(0)));
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+
+    // The payload isn't compared
+    let other = packet(Eid(8), Eid(20), tag, typ, &[9]);
+    block_on(router.inbound(&other, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+    assert_eq!(router.stats().forward_dropped_loop, 4);
+
+    // Packets from other sources are forwarded, and push the
+    // looping packet out of the window
+    for i in 0..FORWARD_LOOP_WINDOW {
+        let other = packet(Eid(30 + i as u8), Eid(20), tag, typ, &[1]);
+        block_on(router.inbound(&other, PortId(0)));
+    }
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), FORWARD_LOOP_WINDOW);
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert!(bottom.try_outbound().is_some());
+    bottom.outbound_done();
+    assert_eq!(router.stats().forward_dropped_loop, 4);
+
+    // Entries expire, so a repeated packet isn't dropped forever
+    for _ in 0..4 {
+        block_on(router.inbound(&pkt, PortId(0)));
+    }
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    assert_eq!(router.stats().forward_dropped_loop, 7);
+    block_on(router.update_time(999)).unwrap();
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert!(bottom.try_outbound().is_none());
+    block_on(router.update_time(1000)).unwrap();
+    for _ in 0..3 {
+        block_on(router.inbound(&pkt, PortId(0)));
+    }
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 2);
+    assert_eq!(router.stats().forward_dropped_loop, 9);
+
+    // Disabled
+    block_on(router.set_max_forwards(None));
+    for _ in 0..4 {
+        block_on(router.inbound(&pkt, PortId(0)));
+    }
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 4);
+    assert_eq!(router.stats().forward_dropped_loop, 9);
+}
+
+#[test]
+fn drop_self_source() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 64);
+    let tag = Tag::Owned(TagValue(1));
+    let pkt = packet(Eid(10), Eid(20), tag, MsgType(0x10), &[1]);
+
+    // Forwarded by default
+    assert_eq!(block_on(router.inbound(&pkt, PortId(0))), Some(Eid(10)));
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+
+    block_on(router.set_drop_self_source(true));
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 0);
+    assert_eq!(router.stats().forward_dropped_self_source, 1);
+
+    // Other sources are still forwarded
+    let pkt = packet(Eid(11), Eid(20), tag, MsgType(0x10), &[1]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 1);
+    assert_eq!(router.stats().forward_dropped_self_source, 1);
+}
+
+#[test]
+fn type_mtu() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let control = MsgType(0x00);
+    let bulk = MsgType(0x7e);
+    let mtu = HEADER_LEN + 32;
+    block_on(router.set_type_mtu(control, Some(mtu))).unwrap();
+    assert!(block_on(router.set_type_mtu(bulk, Some(MAX_MTU + 1))).is_err());
+
+    let mut lens = |typ| {
+        let mut req = router.req(Eid(20));
+        block_on(req.send(typ, &[1; 100])).unwrap();
+        let mut lens = std::vec::Vec::new();
+        drain_port(&mut bottom, |pkt, _dest| lens.push(pkt.len()));
+        lens
+    };
+
+    // Limited by the type MTU, 101 bytes including the type
+    assert_eq!(lens(control), [mtu, mtu, mtu, HEADER_LEN + 5]);
+    // Other types use the port MTU
+    assert_eq!(lens(bulk), [HEADER_LEN + 101]);
+
+    // The smaller of peer and type MTUs applies
+    block_on(router.set_peer_mtu(Eid(20), Some(HEADER_LEN + 64))).unwrap();
+    assert_eq!(lens(control), [mtu, mtu, mtu, HEADER_LEN + 5]);
+    assert_eq!(lens(bulk), [HEADER_LEN + 64, HEADER_LEN + 37]);
+
+    block_on(router.set_type_mtu(control, None)).unwrap();
+    assert_eq!(lens(control), [HEADER_LEN + 64, HEADER_LEN + 37]);
+}
+
+#[test]
+fn inner_waiting() {
+    start_log();
+    one_port_router!(router, _bottom, 8, 64);
+
+    // Uncontended locks aren't counted
+    block_on(router.set_drop_self_source(true));
+    assert_eq!(router.stats().inner_waiting_peak, 0);
+
+    // Hold the lock while other operations contend for it
+    let held = router.inner.try_lock().unwrap();
+    let mut futs = (0..3)
+        .map(|_| Box::pin(router.set_control_lane(true)))
+        .collect::<std::vec::Vec<_>>();
+    for f in futs.iter_mut() {
+        assert!(embassy_futures::poll_once(f).is_pending());
+    }
+    assert_eq!(router.stats().inner_waiting, 3);
+    assert_eq!(router.stats().inner_waiting_peak, 3);
+
+    // Reset keeps the current waiters
+    router.reset_stats();
+    assert_eq!(router.stats().inner_waiting, 3);
+
+    // A cancelled waiter is no longer counted
+    futs.pop();
+    assert_eq!(router.stats().inner_waiting, 2);
+
+    drop(held);
+    for f in futs {
+        block_on(f);
+    }
+    assert_eq!(router.stats().inner_waiting, 0);
+    assert_eq!(router.stats().inner_waiting_peak, 3);
+}
+
+#[test]
+fn listener_local_eid() {
+    use mctp::{AsyncListener, AsyncRespChannel};
+
+    /// Claims a single extra EID
+    struct Claim(Eid);
+
+    impl PortLookup for Claim {
+        fn by_eid(
+            &mut self,
+            _eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            Some(PortId(0))
+        }
+
+        fn deliver_local(&mut self, eid: Eid) -> bool {
+            eid == self.0
+        }
+    }
+
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = Claim(Eid(50));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let t = Tag::Owned(TagValue(1));
+    let mut buf = [0u8; 4];
+
+    for (dest, payload) in [(Eid(10), 1), (Eid(50), 2), (Eid(10), 3)] {
+        let pkt = packet(Eid(20), dest, t, typ, &[payload]);
+        block_on(router.inbound(&pkt, PortId(0)));
+        let (msg, mut resp, ..) = block_on(l.recv(&mut buf)).unwrap();
+        assert_eq!(msg, &[payload]);
+        assert_eq!(resp.local_eid(), dest);
+        assert_eq!(resp.remote_eid(), Eid(20));
+
+        // Responses are sent from the requested EID
+        block_on(resp.send(typ, &[payload])).unwrap();
+        let (pkt, _dest) = bottom.try_outbound().unwrap();
+        assert_eq!(crate::peek_header(pkt).unwrap().source, dest);
+        bottom.outbound_done();
+    }
+}
+
+#[derive(Default)]
+struct MessageHooks(
+    std::sync::Mutex<std::vec::Vec<(Eid, MsgType, std::vec::Vec<u8>)>>,
+);
+
+impl RouterHooks for MessageHooks {
+    fn on_message(&self, eid: Eid, typ: MsgType, payload: &[u8]) {
+        self.0.lock().unwrap().push((eid, typ, payload.to_vec()));
+    }
+}
+
+#[test]
+fn message_hook() {
+    use mctp::AsyncReqChannel;
+
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(HEADER_LEN + 4).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let hooks = MessageHooks::default();
+    let typ = MsgType(0x10);
+    block_on(router.set_hooks(Some(&hooks)));
+
+    let mut req = router.req(Eid(20));
+    block_on(req.send_vectored(typ, false, &[&[1, 2], &[], &[3, 4, 5]]))
+        .unwrap();
+    assert_eq!(
+        *hooks.0.lock().unwrap(),
+        [(Eid(20), typ, std::vec![1, 2, 3, 4, 5])]
+    );
+    // Called once for the message, not per packet
+    assert_eq!(drain_port(&mut bottom, |_, _| ()), 2);
+}
+
+#[test]
+fn send_raw() {
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, mut bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+
+    let mut sent = |tag| {
+        block_on(router.send_raw(Eid(20), typ, tag, true, &[&[1], &[2]]))
+            .unwrap();
+        let mut hdrs = std::vec::Vec::new();
+        drain_port(&mut bottom, |pkt, _dest| {
+            hdrs.push((crate::peek_header(pkt).unwrap(), pkt.to_vec()))
+        });
+        assert_eq!(hdrs.len(), 1);
+        hdrs.pop().unwrap()
+    };
+
+    // Owned tag, not allocated by the stack
+    let (hdr, pkt) = sent(Tag::Owned(TagValue(7)));
+    assert_eq!(hdr.tag, Tag::Owned(TagValue(7)));
+    assert_eq!(&pkt[HEADER_LEN..], &[0x90, 1, 2]);
+    // The same owned tag can be sent again
+    let (hdr, _pkt) = sent(Tag::Owned(TagValue(7)));
+    assert_eq!(hdr.tag, Tag::Owned(TagValue(7)));
+    assert_eq!(block_on(router.free_tags(Eid(20))), 8);
+
+    // An unsolicited unowned tag
+    let (hdr, _pkt) = sent(Tag::Unowned(TagValue(3)));
+    assert_eq!(hdr.tag, Tag::Unowned(TagValue(3)));
+    assert_eq!(hdr.source, Eid(10));
+    assert_eq!(hdr.dest, Eid(20));
+
+    assert!(block_on(router.send_raw(
+        Eid(20),
+        typ,
+        Tag::Owned(TagValue(8)),
+        false,
+        &[&[1]]
+    ))
+    .is_err());
+}
+
+#[test]
+fn max_owned_tags() {
+    use mctp::AsyncReqChannel;
+
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+    block_on(router.set_max_owned_tags(Some(3)));
+    assert_eq!(block_on(router.free_tags(Eid(20))), 3);
+
+    // Spread across destinations
+    let mut reqs = std::vec::Vec::new();
+    for eid in [20, 21, 20] {
+        let mut req = router.req(Eid(eid));
+        block_on(req.send(typ, &[1])).unwrap();
+        reqs.push(req);
+    }
+    drain_port(&mut bottom, |_, _| ());
+    assert_eq!(block_on(router.free_tags(Eid(22))), 0);
+
+    let mut req = router.req(Eid(22));
+    let r = block_on(req.send(typ, &[1]));
+    assert!(matches!(r, Err(Error::TagUnavailable)));
+
+    // A response releases a tag
+    let r = &reqs[1];
+    let tv = r.sent_tag.unwrap().tag();
+    let resp = packet(Eid(21), Eid(10), Tag::Unowned(tv), typ, &[2]);
+    block_on(router.inbound(&resp, PortId(0)));
+    assert_eq!(block_on(router.free_tags(Eid(22))), 1);
+    let mut req = router.req(Eid(22));
+    block_on(req.send(typ, &[1])).unwrap();
+
+    block_on(router.set_max_owned_tags(None));
+    let mut req = router.req(Eid(23));
+    block_on(req.send(typ, &[1])).unwrap();
+}
+
+#[test]
+fn orphan_fragment() {
+    use mctp::AsyncListener;
+
+    start_log();
+    let mut storage = PortStorage::<8>::new();
+    let mut pb = PortBuilder::new(&mut storage);
+    let (top, _bottom) = pb.build(255).unwrap();
+    let ports = [top];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let b = PacketBuilder::new(Eid(20), Eid(10), Tag::Owned(TagValue(1)), typ);
+
+    // Middle and end fragments with no start
+    let pkt = b.clone().som(false).eom(false).seq(1).build(&[1]).unwrap();
+    block_on(router.inbound(&pkt, PortId(0)));
+    let pkt = b.clone().som(false).seq(2).build(&[2]).unwrap();
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert_eq!(router.stats().local_dropped_no_som, 2);
+    {
+        let inner = block_on(router.lock_inner());
+        assert!(inner.stack.reassemblers.iter().all(|r| r.is_none()));
+    }
+
+    // A complete message continuing from SOM is received
+    let pkt = b.clone().eom(false).build(&[3]).unwrap();
+    block_on(router.inbound(&pkt, PortId(0)));
+    let pkt = b.clone().som(false).seq(1).build(&[4]).unwrap();
+    block_on(router.inbound(&pkt, PortId(0)));
+    let mut buf = [0u8; 4];
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[3, 4]);
+    assert_eq!(router.stats().local_dropped_no_som, 2);
+}
+
+#[test]
+fn memory_footprint() {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    start_log();
+    let mut pool = RecvPool::<2>::new();
+    let mut storage1 = PortStorage::<4, 64>::new();
+    let mut pb1 = PortBuilder::new_with_mutex(&mut storage1);
+    let (top1, _bottom1) = pb1.build(64).unwrap();
+    let mut storage2 = PortStorage::<2>::new();
+    let mut pb2 = PortBuilder::new_with_mutex(&mut storage2);
+    let (top2, _bottom2) = pb2.build(64).unwrap();
+    let ports = [top1, top2];
+    let mut lookup = DefaultRoute(Some(PortId(0)));
+    let stack = Stack::new(Eid(10), 64, 0);
+    let router: Router<'_, NoopRawMutex> =
+        Router::new(stack, &ports, &mut lookup);
+
+    let expect = size_of::<Router<'_, NoopRawMutex>>()
+        + 2 * size_of::<PortTop<'_, NoopRawMutex>>()
+        + size_of::<PortStorage<4, 64>>()
+        + size_of::<PortStorage<2>>();
+    assert_eq!(block_on(router.memory_footprint()), expect);
+    // Includes the reassembly buffers
+    assert!(expect > MAX_PAYLOAD * crate::config::NUM_RECEIVE);
+
+    router.set_recv_pool(&mut pool).unwrap();
+    assert_eq!(
+        block_on(router.memory_footprint()),
+        expect + size_of::<RecvPool<2>>()
+    );
+}
+
+#[test]
+fn memory_footprint_borrowed() {
+    use crate::ReassemblyPool;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    struct SlicePool<'a>(&'a mut [[u8; 20]]);
+
+    impl ReassemblyPool for SlicePool<'_> {
+        fn buffer(&mut self, index: usize) -> &mut [u8] {
+            &mut self.0[index]
+        }
+
+        fn footprint(&self) -> usize {
+            size_of_val(self.0)
+        }
+    }
+
+    start_log();
+    let mut lookup = DefaultRoute(None);
+    let mut mem = [[0u8; 20]; crate::NUM_RECEIVE];
+    let mut stack = Stack::new_with_pool(SlicePool(&mut mem), Eid(10), 64, 0);
+    let router: Router<'_, NoopRawMutex, SlicePool<'_>> =
+        Router::new_borrowed(&mut stack, &[], &mut lookup);
+
+    let expect = size_of::<Router<'_, NoopRawMutex, SlicePool<'_>>>()
+        + size_of::<Stack<SlicePool<'_>>>()
+        + size_of::<[[u8; 20]; crate::NUM_RECEIVE]>();
+    assert_eq!(block_on(router.memory_footprint()), expect);
+}
+
+#[test]
+fn mirror() {
+    use mctp::AsyncListener;
+
+    /// Forwards to port 0, mirrors to port 1
+    struct Mirror;
+
+    impl PortLookup for Mirror {
+        fn by_eid(
+            &mut self,
+            _eid: Eid,
+            _source_port: Option<PortId>,
+        ) -> Option<PortId> {
+            Some(PortId(0))
+        }
+
+        fn mirror(
+            &mut self,
+            _header: &PacketHeader,
+            source_port: PortId,
+        ) -> Option<PortId> {
+            (source_port == PortId(0)).then_some(PortId(1))
+        }
+    }
+
+    start_log();
+    let mut storage0 = PortStorage::<8>::new();
+    let mut pb0 = PortBuilder::new(&mut storage0);
+    let (top0, mut bottom0) = pb0.build(255).unwrap();
+    let mut storage1 = PortStorage::<8>::new();
+    let mut pb1 = PortBuilder::new(&mut storage1);
+    let (top1, mut bottom1) = pb1.build(HEADER_LEN + 8).unwrap();
+    let ports = [top0, top1];
+    let mut lookup = Mirror;
+    let stack = Stack::new(Eid(10), 255, 0);
+    let router: Router = Router::new(stack, &ports, &mut lookup);
+    let typ = MsgType(0x10);
+    let mut l = router.listener(typ).unwrap();
+    let t = Tag::Owned(TagValue(1));
+    let mut buf = [0u8; 20];
+
+    // Local delivery and mirror
+    let pkt = packet(Eid(20), Eid(10), t, typ, &[1]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[1]);
+    let (m, dest) = bottom1.try_outbound().unwrap();
+    assert_eq!((m, dest), (pkt.as_slice(), Eid(10)));
+    bottom1.outbound_done();
+
+    // Forward and mirror
+    let pkt = packet(Eid(20), Eid(30), t, typ, &[2]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    assert_eq!(bottom0.try_outbound().unwrap().0, pkt.as_slice());
+    bottom0.outbound_done();
+    assert_eq!(bottom1.try_outbound().unwrap().0, pkt.as_slice());
+    bottom1.outbound_done();
+    assert_eq!(router.stats().mirrored, 2);
+
+    // Too large for the mirror port, still delivered
+    let pkt = packet(Eid(20), Eid(10), t, typ, &[3; 10]);
+    block_on(router.inbound(&pkt, PortId(0)));
+    let (msg, ..) = block_on(l.recv(&mut buf)).unwrap();
+    assert_eq!(msg, &[3; 10]);
+    assert!(bottom1.try_outbound().is_none());
+    assert_eq!(router.stats().mirror_dropped, 1);
+}
+
+#[test]
+fn session() {
+    start_log();
+    one_port_router!(router, mut bottom, 8, 255);
+    let typ = MsgType(0x10);
+
+    let mut session = router.session(Eid(20));
+    assert_eq!(session.eid(), Eid(20));
+    let mut tags = std::vec::Vec::new();
+    for i in 0..4u8 {
+        let mut buf = [0u8; 8];
+        let (resp, rtyp, ic) =
+            block_on_with(session.exchange(typ, false, &[i], &mut buf), || {
+                // Respond to the request as the peer
+                let mut reqs = std::vec::Vec::new();
+                drain_port(&mut bottom, |pkt, _dest| {
+                    let hdr = crate::peek_header(pkt).unwrap();
+                    reqs.push((hdr.tag, pkt[HEADER_LEN + 1]));
+                });
+                for (tag, b) in reqs.iter() {
+                    tags.push(*tag);
+                    let tv = tag.tag();
+                    let resp = packet(
+                        Eid(20),
+                        Eid(10),
+                        Tag::Unowned(tv),
+                        typ,
+                        &[b + 100],
+                    );
+                    block_on(router.inbound(&resp, PortId(0)));
+                }
+                !reqs.is_empty()
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp, &[i + 100]);
+        assert_eq!((rtyp, ic), (typ, false));
+    }
+
+    // The same tag is used for each exchange
+    assert_eq!(tags.len(), 4);
+    assert!(tags.iter().all(|t| *t == tags[0] && t.is_owner()));
+    assert_eq!(block_on(router.free_tags(Eid(20))), 7);
+
+    // Expiry doesn't release the session's tag
+    block_on(router.update_time(100_000)).unwrap();
+    assert_eq!(block_on(router.free_tags(Eid(20))), 7);
+
+    block_on(session.close());
+    assert_eq!(block_on(router.free_tags(Eid(20))), 8);
+}
@@ -222,7 +222,7 @@ impl MctpSerialHandler {
         output: &mut impl Write,
         mctp: &mut Stack,
         fill_msg: F,
-    ) -> SendOutput
+    ) -> SendOutput<'_>
     where
         F: FnOnce(&mut Vec<u8, MAX_PAYLOAD>) -> Option<()>,
     {
@@ -346,7 +346,7 @@ mod tests {
     async fn do_roundtrip(payload: &[u8]) {
         let mut esc = vec![];
         let mut s = FromFutures::new(&mut esc);
-        MctpSerialHandler::frame_to_serial(&payload, &mut s)
+        MctpSerialHandler::frame_to_serial(payload, &mut s)
             .await
             .unwrap();
         debug!("{:02x?}", payload);
@@ -0,0 +1,322 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! Helpers for testing
+//!
+//! Enabled with the `test-util` feature.
+
+use crate::router::{PortBottom, PortId, Router};
+use crate::{peek_header, Header, ReassemblyPool, Vec, HEADER_LEN, MAX_MTU};
+use core::future::Future;
+use core::pin::pin;
+use core::task::Poll;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use mctp::{Eid, Error, MsgType, Result, Tag, MCTP_HEADER_VERSION_1};
+
+/// Constructs MCTP packets.
+///
+/// Defaults to a single-packet message (SOM and EOM set),
+/// sequence number 0, and IC bit unset.
+///
+/// Fragmented messages can be built with [`fragments()`](Self::fragments).
+#[derive(Debug, Clone)]
+pub struct PacketBuilder {
+    pub src: Eid,
+    pub dest: Eid,
+    pub tag: Tag,
+    pub typ: MsgType,
+    pub som: bool,
+    pub eom: bool,
+    pub seq: u8,
+    pub ic: bool,
+    /// Index of a packet to omit from `fragments()`
+    pub drop_fragment: Option<usize>,
+}
+
+impl PacketBuilder {
+    pub fn new(src: Eid, dest: Eid, tag: Tag, typ: MsgType) -> Self {
+        Self {
+            src,
+            dest,
+            tag,
+            typ,
+            som: true,
+            eom: true,
+            seq: 0,
+            ic: false,
+            drop_fragment: None,
+        }
+    }
+
+    pub fn som(mut self, som: bool) -> Self {
+        self.som = som;
+        self
+    }
+
+    pub fn eom(mut self, eom: bool) -> Self {
+        self.eom = eom;
+        self
+    }
+
+    pub fn seq(mut self, seq: u8) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    pub fn ic(mut self, ic: bool) -> Self {
+        self.ic = ic;
+        self
+    }
+
+    /// Omits packet `n` (counting from 0) from [`fragments()`](Self::fragments).
+    ///
+    /// This simulates a lossy link.
+    pub fn drop_fragment(mut self, n: usize) -> Self {
+        self.drop_fragment = Some(n);
+        self
+    }
+
+    /// Returns the packets of a message with `payload`.
+    ///
+    /// Packets are at most `mtu` bytes, including the header.
+    /// Sequence numbers start from `seq`, and `som`/`eom` are set
+    /// for the first and last packets.
+    /// The packet set with [`drop_fragment()`](Self::drop_fragment)
+    /// is skipped.
+    ///
+    /// Yields [`Error::BadArgument`] if `mtu` is too small.
+    pub fn fragments<'a>(
+        &'a self,
+        payload: &'a [u8],
+        mtu: usize,
+    ) -> impl Iterator<Item = Result<Vec<u8, MAX_MTU>>> + 'a {
+        let mut off = 0;
+        let mut n = 0;
+        let mut done = false;
+        core::iter::from_fn(move || loop {
+            if done {
+                return None;
+            }
+            // The first packet includes the message type byte
+            let som = n == 0;
+            let Some(space) = mtu
+                .checked_sub(HEADER_LEN + som as usize)
+                .filter(|s| *s > 0)
+            else {
+                done = true;
+                return Some(Err(Error::BadArgument));
+            };
+            let end = payload.len().min(off + space);
+            let eom = end == payload.len();
+            let seq = self.seq.wrapping_add(n as u8) & mctp::MCTP_SEQ_MASK;
+            let pkt = self
+                .clone()
+                .som(som)
+                .eom(eom)
+                .seq(seq)
+                .build(&payload[off..end]);
+            let drop = self.drop_fragment == Some(n);
+            off = end;
+            n += 1;
+            done = eom;
+            if !drop {
+                return Some(pkt);
+            }
+        })
+    }
+
+    /// Returns a packet with `payload`.
+    ///
+    /// The message type byte is included when `som` is set.
+    /// Returns [`Error::NoSpace`] if the packet would exceed `MAX_MTU`.
+    pub fn build(&self, payload: &[u8]) -> Result<Vec<u8, MAX_MTU>> {
+        if self.tag.tag().0 > mctp::MCTP_TAG_MAX
+            || self.seq > mctp::MCTP_SEQ_MASK
+        {
+            return Err(Error::BadArgument);
+        }
+
+        let mut header = Header::new(MCTP_HEADER_VERSION_1);
+        header.set_dest_endpoint_id(self.dest.0);
+        header.set_source_endpoint_id(self.src.0);
+        header.set_pkt_seq(self.seq);
+        header.set_som(self.som as u8);
+        header.set_eom(self.eom as u8);
+        header.set_msg_tag(self.tag.tag().0);
+        header.set_to(self.tag.is_owner() as u8);
+
+        let mut pkt = Vec::new();
+        debug_assert_eq!(header.0.len(), HEADER_LEN);
+        pkt.extend_from_slice(&header.0)
+            .map_err(|_| Error::NoSpace)?;
+        if self.som {
+            pkt.push(mctp::encode_type_ic(self.typ, self.ic))
+                .map_err(|_| Error::NoSpace)?;
+        }
+        pkt.extend_from_slice(payload).map_err(|_| Error::NoSpace)?;
+        Ok(pkt)
+    }
+}
+
+/// Runs a future to completion without an executor.
+///
+/// The future is polled repeatedly with a no-op waker, so this
+/// busy-waits. Something other than the future must make progress for a
+/// pending future to complete, see [`block_on_with()`].
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    // OK unwrap: step never stops
+    block_on_with(fut, || true).unwrap()
+}
+
+/// Runs a future, calling `step` each time it is pending.
+///
+/// `step` can provide the external events that the future is waiting
+/// for, such as injecting packets with
+/// [`Router::inbound()`](crate::Router::inbound) or draining a port with
+/// [`drain_port()`]. The future is polled again after each `step`.
+///
+/// Returns `None` if `step` returns `false` before the future completes.
+pub fn block_on_with<F: Future>(
+    fut: F,
+    mut step: impl FnMut() -> bool,
+) -> Option<F::Output> {
+    let mut fut = pin!(fut);
+    loop {
+        if let Poll::Ready(r) = embassy_futures::poll_once(fut.as_mut()) {
+            return Some(r);
+        }
+        if !step() {
+            return None;
+        }
+    }
+}
+
+/// Consumes all packets queued for a port.
+///
+/// `f(packet, dest)` is called for each packet.
+/// Returns the number of packets consumed.
+pub fn drain_port<M: RawMutex>(
+    bottom: &mut PortBottom<'_, M>,
+    mut f: impl FnMut(&[u8], Eid),
+) -> usize {
+    let mut n = 0;
+    while let Some((pkt, dest)) = bottom.try_outbound() {
+        f(pkt, dest);
+        bottom.outbound_done();
+        n += 1;
+    }
+    n
+}
+
+/// Entry point for fuzzing, providing arbitrary bytes as a packet.
+///
+/// This runs the same path as [`Router::inbound()`] with port 0,
+/// blocking until it completes. Returns the packet's source EID,
+/// or `Error::InvalidInput` if the header could not be parsed.
+///
+/// Malformed input must never panic.
+pub fn fuzz_inbound<
+    M: RawMutex,
+    R: ReassemblyPool,
+    const LISTENERS: usize,
+    const RECEIVERS: usize,
+>(
+    router: &Router<'_, M, R, LISTENERS, RECEIVERS>,
+    bytes: &[u8],
+) -> Result<Eid> {
+    peek_header(bytes)?;
+    block_on(router.inbound(bytes, PortId(0))).ok_or(Error::InvalidInput)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{peek_header, PacketHeader, Stack};
+    use mctp::TagValue;
+
+    #[test]
+    fn roundtrip_header() {
+        let b = PacketBuilder::new(
+            Eid(9),
+            Eid(10),
+            Tag::Owned(TagValue(3)),
+            MsgType(0x7e),
+        )
+        .eom(false)
+        .seq(2);
+        let pkt = b.build(&[1, 2, 3]).unwrap();
+        let h = peek_header(&pkt).unwrap();
+        assert_eq!(
+            h,
+            PacketHeader {
+                source: Eid(9),
+                dest: Eid(10),
+                tag: Tag::Owned(TagValue(3)),
+                som: true,
+                eom: false,
+                seq: 2,
+            }
+        );
+
+        // The stack can reassemble built packets
+        let mut stack = Stack::new(Eid(10), 64, 0);
+        assert!(stack.receive(&pkt).unwrap().is_none());
+        let pkt = b.som(false).eom(true).seq(3).build(&[4]).unwrap();
+        let (msg, handle) = stack.receive(&pkt).unwrap().unwrap();
+        assert_eq!(msg.typ, MsgType(0x7e));
+        assert_eq!(msg.payload, &[1, 2, 3, 4]);
+        stack.finished_receive(handle);
+    }
+
+    #[test]
+    fn dropped_fragment() {
+        let b = PacketBuilder::new(
+            Eid(9),
+            Eid(10),
+            Tag::Owned(TagValue(3)),
+            MsgType(0x7e),
+        );
+        let payload = [1u8; 40];
+        let mtu = HEADER_LEN + 12;
+
+        // Complete message, 4 packets
+        let mut stack = Stack::new(Eid(10), mtu, 0);
+        let pkts = b
+            .fragments(&payload, mtu)
+            .collect::<Result<std::vec::Vec<_>>>();
+        let pkts = pkts.unwrap();
+        assert_eq!(pkts.len(), 4);
+        let (last, first) = pkts.split_last().unwrap();
+        for p in first {
+            assert!(stack.receive(p).unwrap().is_none());
+        }
+        let (msg, handle) = stack.receive(last).unwrap().unwrap();
+        assert_eq!(msg.payload, &payload);
+        stack.finished_receive(handle);
+
+        // Middle packet dropped
+        let b = b.drop_fragment(1);
+        let mut stack = Stack::new(Eid(10), mtu, 0);
+        let mut count = 0;
+        for p in b.fragments(&payload, mtu) {
+            // Never completes, later packets may be rejected
+            if let Ok(r) = stack.receive(&p.unwrap()) {
+                assert!(r.is_none());
+            }
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        // The reassembly context is reclaimed after the timeout
+        stack.update(10_000).unwrap();
+        assert!(stack.reassemblers.iter().all(|r| r.is_none()));
+
+        assert!(b
+            .fragments(&payload, HEADER_LEN + 1)
+            .next()
+            .unwrap()
+            .is_err());
+    }
+}
@@ -19,3 +19,18 @@ macro_rules! get_build_var {
         }
     }};
 }
+
+/// Writes and reads back test patterns to `buf`.
+///
+/// Returns `Error::Other` on a mismatch. `buf` is left zeroed.
+pub(crate) fn check_memory(buf: &mut [u8]) -> mctp::Result<()> {
+    for pattern in [0x55, 0xaa, 0x00] {
+        buf.fill(pattern);
+        // Ensure the writes and reads aren't optimised out
+        let buf = core::hint::black_box(&mut *buf);
+        if buf.iter().any(|b| *b != pattern) {
+            return Err(mctp::Error::Other);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+/*
+ * Copyright (c) 2025 Code Construct
+ */
+
+//! MCTP Vendor Defined Messages
+//!
+//! Message types [`MCTP_TYPE_VENDOR_PCIE`] and [`MCTP_TYPE_VENDOR_IANA`]
+//! carry a vendor ID prefix prior to the vendor-defined message body,
+//! as described in DSP0236.
+
+#[allow(unused)]
+use crate::fmt::{debug, error, info, trace, warn};
+
+use crate::router::{RouterAsyncListener, RouterAsyncRespChannel};
+use crate::{ReassemblyPool, StaticPool};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use heapless::Vec;
+use mctp::{
+    AsyncListener, Error, MsgType, Result, Tag, MCTP_TYPE_VENDOR_IANA,
+    MCTP_TYPE_VENDOR_PCIE,
+};
+
+/// Maximum length of a vendor ID header
+pub const MAX_VENDOR_HEADER: usize = 4;
+
+/// A vendor identifier for a Vendor Defined message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VendorId {
+    /// PCI Vendor ID, sent with [`MCTP_TYPE_VENDOR_PCIE`]
+    Pci(u16),
+    /// IANA Enterprise Number, sent with [`MCTP_TYPE_VENDOR_IANA`]
+    Iana(u32),
+}
+
+impl VendorId {
+    /// Returns the MCTP message type used for this vendor ID form.
+    pub fn msg_type(&self) -> MsgType {
+        match self {
+            Self::Pci(_) => MCTP_TYPE_VENDOR_PCIE,
+            Self::Iana(_) => MCTP_TYPE_VENDOR_IANA,
+        }
+    }
+
+    /// Returns the encoded vendor ID header.
+    ///
+    /// This should be sent as the first part of a message payload,
+    /// for example as the first slice passed to `send_vectored()`.
+    pub fn header(&self) -> Vec<u8, MAX_VENDOR_HEADER> {
+        let mut v = Vec::new();
+        // OK unwrap: MAX_VENDOR_HEADER is sufficient for either form
+        match self {
+            Self::Pci(id) => v.extend_from_slice(&id.to_be_bytes()).unwrap(),
+            Self::Iana(id) => v.extend_from_slice(&id.to_be_bytes()).unwrap(),
+        }
+        v
+    }
+
+    /// Parses the vendor ID header of a message.
+    ///
+    /// `typ` is the MCTP message type, `payload` is the message payload
+    /// following the type byte.
+    /// Returns the vendor ID and the remaining vendor-defined body.
+    ///
+    /// Returns [`Error::InvalidInput`] if `typ` is not a vendor defined
+    /// type or the payload is too short.
+    pub fn parse(typ: MsgType, payload: &[u8]) -> Result<(Self, &[u8])> {
+        let (vid, len) = Self::parse_len(typ, payload)?;
+        Ok((vid, &payload[len..]))
+    }
+
+    /// Returns the vendor ID and the length of the header
+    fn parse_len(typ: MsgType, payload: &[u8]) -> Result<(Self, usize)> {
+        match typ {
+            MCTP_TYPE_VENDOR_PCIE => {
+                let b = payload.first_chunk().ok_or(Error::InvalidInput)?;
+                Ok((Self::Pci(u16::from_be_bytes(*b)), b.len()))
+            }
+            MCTP_TYPE_VENDOR_IANA => {
+                let b = payload.first_chunk().ok_or(Error::InvalidInput)?;
+                Ok((Self::Iana(u32::from_be_bytes(*b)), b.len()))
+            }
+            _ => Err(Error::InvalidInput),
+        }
+    }
+}
+
+/// A listener for Vendor Defined messages with a specific vendor ID.
+///
+/// This wraps a [`RouterAsyncListener`] bound to the vendor ID's
+/// message type. Incoming messages with a different vendor ID
+/// (or a malformed header) are discarded.
+///
+/// Since the [`Router`](crate::Router) binds listeners by message type,
+/// only a single `VendorListener` can exist for each of the PCI and IANA
+/// forms.
+pub struct VendorListener<
+    'r,
+    M: RawMutex = CriticalSectionRawMutex,
+    R: ReassemblyPool = StaticPool,
+    const LISTENERS: usize = 20,
+    const RECEIVERS: usize = 50,
+> {
+    listener: RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>,
+    vendor: VendorId,
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > VendorListener<'r, M, R, LISTENERS, RECEIVERS>
+{
+    /// Create a new `VendorListener`.
+    ///
+    /// `listener` must be bound to `vendor.msg_type()`.
+    pub fn new(
+        listener: RouterAsyncListener<'r, M, R, LISTENERS, RECEIVERS>,
+        vendor: VendorId,
+    ) -> Result<Self> {
+        if listener.msg_type() != vendor.msg_type() {
+            return Err(Error::BadArgument);
+        }
+        Ok(Self { listener, vendor })
+    }
+
+    /// Returns the vendor ID being received.
+    pub fn vendor(&self) -> VendorId {
+        self.vendor
+    }
+}
+
+impl<
+        'r,
+        M: RawMutex,
+        R: ReassemblyPool,
+        const LISTENERS: usize,
+        const RECEIVERS: usize,
+    > AsyncListener for VendorListener<'r, M, R, LISTENERS, RECEIVERS>
+{
+    type RespChannel<'a>
+        = RouterAsyncRespChannel<'r, M, R, LISTENERS, RECEIVERS>
+    where
+        Self: 'a;
+
+    /// Receive a message matching the vendor ID.
+    ///
+    /// The returned buffer contains the vendor-defined body,
+    /// following the vendor ID header.
+    async fn recv<'f>(
+        &mut self,
+        buf: &'f mut [u8],
+    ) -> Result<(&'f mut [u8], Self::RespChannel<'_>, Tag, MsgType, bool)> {
+        loop {
+            let (msg, resp, tag, typ, ic) =
+                self.listener.recv(&mut *buf).await?;
+            let len = msg.len();
+
+            match VendorId::parse_len(typ, msg) {
+                Ok((vid, hlen)) if vid == self.vendor => {
+                    // Router listeners fill from the start of `buf`
+                    return Ok((&mut buf[hlen..len], resp, tag, typ, ic));
+                }
+                Ok(_) => trace!("Mismatching vendor ID"),
+                Err(_) => debug!("Bad vendor message"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_pci() {
+        let vid = VendorId::Pci(0x1af4);
+        let hdr = vid.header();
+        assert_eq!(hdr.as_slice(), &[0x1a, 0xf4]);
+
+        let mut msg = std::vec::Vec::from(hdr.as_slice());
+        msg.extend_from_slice(&[1, 2, 3]);
+        let (p, body) = VendorId::parse(vid.msg_type(), &msg).unwrap();
+        assert_eq!(p, vid);
+        assert_eq!(body, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn roundtrip_iana() {
+        let vid = VendorId::Iana(0x0000_b70d);
+        let hdr = vid.header();
+        assert_eq!(hdr.as_slice(), &[0x00, 0x00, 0xb7, 0x0d]);
+
+        let mut msg = std::vec::Vec::from(hdr.as_slice());
+        msg.extend_from_slice(&[4, 5]);
+        let (p, body) = VendorId::parse(vid.msg_type(), &msg).unwrap();
+        assert_eq!(p, vid);
+        assert_eq!(body, &[4, 5]);
+
+        // Short header
+        assert!(VendorId::parse(vid.msg_type(), &msg[..3]).is_err());
+        // Not a vendor type
+        assert!(VendorId::parse(mctp::MCTP_TYPE_PLDM, &msg).is_err());
+    }
+}
@@ -122,6 +122,12 @@ pub struct Responder<R: RespChannel> {
     update_timestamp_fd_t1: u64,
 }
 
+impl<R: RespChannel> Default for Responder<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<R: RespChannel> Responder<R> {
     /// Update mode idle timeout, 120 seconds
     pub const FD_T1_TIMEOUT: u64 = 120_000;
@@ -155,7 +161,7 @@ impl<R: RespChannel> Responder<R> {
 
         let Some(cmd) = Cmd::from_u8(req.cmd) else {
             self.reply_error(
-                &req,
+                req,
                 &mut comm,
                 CCode::ERROR_UNSUPPORTED_PLDM_CMD as u8,
             );
@@ -176,7 +182,7 @@ impl<R: RespChannel> Responder<R> {
             _ => {
                 if self.ua_eid != Some(eid) {
                     debug!("Ignoring {cmd:?} from mismatching EID {eid}, expected {:?}", self.ua_eid);
-                    self.reply_error(&req, &mut comm,
+                    self.reply_error(req, &mut comm,
                         CCode::ERROR_NOT_READY as u8,
                     );
                 }
@@ -193,25 +199,25 @@ impl<R: RespChannel> Responder<R> {
 
         // Handlers will return Ok if they have replied
         let r = match cmd {
-            Cmd::QueryDeviceIdentifiers => self.cmd_qdi(&req, &mut comm, d),
-            Cmd::GetFirmwareParameters => self.cmd_fwparams(&req, &mut comm, d),
-            Cmd::RequestUpdate => self.cmd_update(&req, eid, &mut comm, d),
+            Cmd::QueryDeviceIdentifiers => self.cmd_qdi(req, &mut comm, d),
+            Cmd::GetFirmwareParameters => self.cmd_fwparams(req, &mut comm, d),
+            Cmd::RequestUpdate => self.cmd_update(req, eid, &mut comm, d),
             Cmd::PassComponentTable => {
-                self.cmd_pass_components(&req, &mut comm, d)
+                self.cmd_pass_components(req, &mut comm, d)
             }
             Cmd::UpdateComponent => {
-                return self.cmd_update_component(&req, comm, d)
+                return self.cmd_update_component(req, comm, d)
             }
-            Cmd::ActivateFirmware => self.cmd_activate(&req, &mut comm, d),
-            Cmd::CancelUpdate => self.cmd_cancel_update(&req, &mut comm, d),
+            Cmd::ActivateFirmware => self.cmd_activate(req, &mut comm, d),
+            Cmd::CancelUpdate => self.cmd_cancel_update(req, &mut comm, d),
             Cmd::CancelUpdateComponent => {
-                self.cmd_cancel_update_component(&req, &mut comm, d)
+                self.cmd_cancel_update_component(req, &mut comm, d)
             }
-            Cmd::GetStatus => self.cmd_get_status(&req, &mut comm, d),
+            Cmd::GetStatus => self.cmd_get_status(req, &mut comm, d),
             _ => {
                 trace!("unhandled command {cmd:?}");
                 self.reply_error(
-                    &req,
+                    req,
                     &mut comm,
                     CCode::ERROR_UNSUPPORTED_PLDM_CMD as u8,
                 );
@@ -221,7 +227,7 @@ impl<R: RespChannel> Responder<R> {
 
         if let Err(e) = &r {
             debug!("Error handling {cmd:?}: {e:?}");
-            self.reply_error(&req, &mut comm, CCode::ERROR as u8);
+            self.reply_error(req, &mut comm, CCode::ERROR as u8);
         }
         Ok(())
     }
@@ -434,7 +440,7 @@ impl<R: RespChannel> Responder<R> {
         if found {
             dev.update_component(update, up)
         } else {
-            return ComponentResponseCode::NotSupported as u8;
+            ComponentResponseCode::NotSupported as u8
         }
     }
 
@@ -491,7 +497,7 @@ impl<R: RespChannel> Responder<R> {
         // Special case for zero size component. It will immediately send TransferComplete
         // instead of RequestFirmwareData.
         let transfer_result =
-            (details.size == 0).then(|| TransferResult::Success);
+            (details.size == 0).then_some(TransferResult::Success);
 
         let req_comm = comm.req_channel()?;
 
@@ -589,7 +595,9 @@ impl<R: RespChannel> Responder<R> {
         resp.cc = CCode::SUCCESS as u8;
         pldm_tx_resp(comm, &resp)?;
 
-        details.map(|d| dev.cancel_component(d));
+        if let Some(d) = details {
+            dev.cancel_component(d)
+        }
         self.set_idle(PldmIdleReason::Cancel);
 
         Ok(())
@@ -629,7 +637,7 @@ impl<R: RespChannel> Responder<R> {
         resp.cc = CCode::SUCCESS as u8;
         pldm_tx_resp(comm, &resp)?;
 
-        dev.cancel_component(&details);
+        dev.cancel_component(details);
         self.set_state(State::ReadyXfer);
 
         Ok(())
@@ -707,12 +715,12 @@ impl<R: RespChannel> Responder<R> {
             State::Download { .. } => self.progress_download(dev),
             State::Verify { .. } => self.progress_verify(dev),
             State::Apply { .. } => self.progress_apply(dev),
-            State::Idle { .. } => {
-                if dev.now() - self.update_timestamp_fd_t1 > Self::FD_T1_TIMEOUT
-                {
-                    // TODO cancel any updates in Device?
-                    self.set_state_idle_timeout();
-                }
+            State::Idle { .. }
+                if dev.now() - self.update_timestamp_fd_t1
+                    > Self::FD_T1_TIMEOUT =>
+            {
+                // TODO cancel any updates in Device?
+                self.set_state_idle_timeout();
             }
             _ => (),
         }
@@ -1194,7 +1202,7 @@ pub trait Device {
     /// or a ComponentResponseCode otherwise (see PLDM FW specification).
     /// When `update == false` a response may indicate conditional success,
     /// such as requiring update flags to be set.
-
+    //
     // fd shouldn't call this directly, instead use check_update_component()
     // which checks that comp exists in the component list.
     fn update_component(&mut self, update: bool, comp: &UpdateComponent) -> u8;
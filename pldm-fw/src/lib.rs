@@ -152,15 +152,15 @@ impl Cmd {
     }
 
     pub const fn is_fd(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Self::GetPackageData
-            | Self::RequestFirmwareData
-            | Self::TransferComplete
-            | Self::VerifyComplete
-            | Self::ApplyComplete
-            | Self::GetMetaData => true,
-            _ => false,
-        }
+                | Self::RequestFirmwareData
+                | Self::TransferComplete
+                | Self::VerifyComplete
+                | Self::ApplyComplete
+                | Self::GetMetaData
+        )
     }
 }
 
@@ -360,11 +360,7 @@ impl fmt::Display for DescriptorString {
         let trim_chars = ['\0', ' '];
         match self {
             Self::String(s) => {
-                write!(
-                    f,
-                    "{}",
-                    s.trim_end_matches(&trim_chars).escape_default()
-                )
+                write!(f, "{}", s.trim_end_matches(trim_chars).escape_default())
             }
             Self::Bytes(bs) => {
                 for b in bs.iter() {
@@ -426,7 +422,7 @@ impl DescriptorString {
     }
 
     pub fn new_str(s: &str) -> Option<Self> {
-        if s.as_bytes().len() > 0xff {
+        if s.len() > 0xff {
             return None;
         }
         Some(Self::String(s.to_string()))
@@ -1079,9 +1075,7 @@ impl UpdateComponent {
                 comparisonstamp,
                 version,
             ),
-        ) = tuple((le_u16, le_u16, le_u8, le_u32, parse_string_adjacent))(
-            &buf,
-        )?;
+        ) = tuple((le_u16, le_u16, le_u8, le_u32, parse_string_adjacent))(buf)?;
 
         let s = Self {
             classification: classification.into(),
@@ -1114,7 +1108,7 @@ impl UpdateComponent {
             le_u32,
             le_u32,
             parse_string_adjacent,
-        ))(&buf)?;
+        ))(buf)?;
 
         let s = Self {
             classification: classification.into(),
@@ -153,7 +153,7 @@ pub fn query_device_identifiers(
 
 pub fn query_firmware_parameters(
     comm: &mut impl mctp::ReqChannel,
-) -> Result<FirmwareParameters> {
+) -> Result<FirmwareParameters<'_>> {
     let req = pldm::PldmRequest::new(PLDM_TYPE_FW, 0x02);
 
     let rsp = pldm::pldm_xfer(comm, req)?;
@@ -390,7 +390,7 @@ where
                     let rate = elapsed / sz_done as i32; // time per byte
 
                     /* blocks may be repeated */
-                    let sz_left = if sz_done <= sz { sz - sz_done } else { 0 };
+                    let sz_left = sz.saturating_sub(sz_done);
 
                     remaining = rate * sz_left as i32;
                     // OK unwrap, overflows after 200k years
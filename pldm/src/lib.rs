@@ -183,7 +183,7 @@ impl<'a> PldmRequest<'a> {
     ///
     /// Convert this request to a response, using the instance, type and command
     /// from the original request.
-    pub fn response(&self) -> PldmResponse {
+    pub fn response(&self) -> PldmResponse<'_> {
         PldmResponse {
             iid: self.iid,
             typ: self.typ,
@@ -216,7 +216,7 @@ impl PldmRequest<'static> {
     /// Create a PLDM request from message data.
     ///
     /// May fail if the message data is not parsable as a PLDM message.
-    pub fn from_buf<'f>(data: &'f [u8]) -> Result<Self> {
+    pub fn from_buf(data: &[u8]) -> Result<Self> {
         PldmRequest::from_buf_borrowed(data).map(|p| p.make_owned())
     }
 }
@@ -80,7 +80,7 @@ fn main() -> Result<()> {
         let l = (l as usize) % 24;
         let payload = &payload[..l];
 
-        if let Err(e) = req(&mut ch, REQ_MSG_TYPE, &payload, args.fatal) {
+        if let Err(e) = req(&mut ch, REQ_MSG_TYPE, payload, args.fatal) {
             warn!("Error {e:?}");
             if args.fatal {
                 bail!("Response error.")
@@ -95,7 +95,7 @@ fn req(
     payload: &[u8],
     fatal: bool,
 ) -> Result<()> {
-    ch.send(typ, &payload).context("Error sending")?;
+    ch.send(typ, payload).context("Error sending")?;
 
     info!("Sent OK");
 
@@ -104,11 +104,9 @@ fn req(
 
     info!("Reply {rep:02x?}");
 
-    if fatal {
-        if rep != payload || typ != rep_typ {
-            info!("rep_typ 0x{rep_typ:x?}");
-            bail!("Response mismatch)")
-        }
+    if fatal && (rep != payload || typ != rep_typ) {
+        info!("rep_typ 0x{rep_typ:x?}");
+        bail!("Response mismatch)")
     }
     assert!(rep == payload);
     Ok(())